@@ -0,0 +1,406 @@
+//! Automatic repair of masters that are almost, but not quite, compatible.
+//!
+//! The cases handled here are the ones that come up all the time when
+//! drawing masters by hand: contours in a different order, a different
+//! start point, or a different direction. Contours are matched across
+//! layers by similarity of area and bounding box, then rotated and
+//! reversed so they line up with the reference layer.
+
+use kurbo::{Point, Rect};
+
+use crate::font::{Font, Glyph, NodeType, Path};
+
+/// A summary of what happened during a compatibility pass.
+#[derive(Debug, Default)]
+pub struct CompatReport {
+    /// Descriptions of changes that were made.
+    pub fixed: Vec<String>,
+    /// Descriptions of problems that could not be repaired automatically.
+    pub unfixed: Vec<String>,
+}
+
+/// Cost assigned to matching contours with different structure.
+const MISMATCH_COST: f64 = 10.0;
+
+struct PathInfo {
+    area: f64,
+    bbox: Rect,
+    n_nodes: usize,
+    closed: bool,
+}
+
+impl PathInfo {
+    fn new(path: &Path) -> PathInfo {
+        PathInfo {
+            area: signed_area(path),
+            bbox: bbox(path),
+            n_nodes: path.nodes.len(),
+            closed: path.closed,
+        }
+    }
+
+    /// A dissimilarity measure between two contours, normalized by `scale`.
+    fn cost(&self, other: &PathInfo, scale: f64) -> f64 {
+        let d_center = (self.bbox.center() - other.bbox.center()).hypot() / scale;
+        let d_size = ((self.bbox.width() - other.bbox.width()).abs()
+            + (self.bbox.height() - other.bbox.height()).abs())
+            / scale;
+        let a0 = self.area.abs().max(1.0);
+        let a1 = other.area.abs().max(1.0);
+        let mut cost = d_center + d_size + (a0 / a1).ln().abs();
+        if self.n_nodes != other.n_nodes || self.closed != other.closed {
+            cost += MISMATCH_COST;
+        }
+        cost
+    }
+}
+
+/// Signed area of the control polygon; its sign gives the path direction.
+fn signed_area(path: &Path) -> f64 {
+    let n = path.nodes.len();
+    let mut area = 0.0;
+    for i in 0..n {
+        let p0 = path.nodes[i].pt;
+        let p1 = path.nodes[(i + 1) % n].pt;
+        area += p0.x * p1.y - p1.x * p0.y;
+    }
+    0.5 * area
+}
+
+fn bbox(path: &Path) -> Rect {
+    let mut iter = path.nodes.iter();
+    let first = match iter.next() {
+        Some(node) => node.pt,
+        None => return Rect::new(0.0, 0.0, 0.0, 0.0),
+    };
+    iter.fold(Rect::from_points(first, first), |bbox, node| {
+        bbox.union_pt(node.pt)
+    })
+}
+
+fn centroid(path: &Path) -> Point {
+    let n = path.nodes.len().max(1) as f64;
+    let (x, y) = path
+        .nodes
+        .iter()
+        .fold((0.0, 0.0), |(x, y), node| (x + node.pt.x, y + node.pt.y));
+    Point::new(x / n, y / n)
+}
+
+/// Whether two node types describe the same structure (ignoring smoothness).
+fn same_kind(a: NodeType, b: NodeType) -> bool {
+    matches!(
+        (a, b),
+        (NodeType::OffCurve, NodeType::OffCurve)
+            | (
+                NodeType::Line | NodeType::LineSmooth,
+                NodeType::Line | NodeType::LineSmooth
+            )
+            | (
+                NodeType::Curve | NodeType::CurveSmooth,
+                NodeType::Curve | NodeType::CurveSmooth
+            )
+            | (
+                NodeType::QCurve | NodeType::QCurveSmooth,
+                NodeType::QCurve | NodeType::QCurveSmooth
+            )
+    )
+}
+
+/// Find the rotation of `path` that best lines up with `reference`.
+///
+/// Only rotations that preserve the node structure are considered. Points
+/// are compared relative to their centroids, so that the translation
+/// between masters (typical for weight) doesn't affect the result.
+fn best_rotation(reference: &Path, path: &Path) -> Option<usize> {
+    let n = reference.nodes.len();
+    if n != path.nodes.len() {
+        return None;
+    }
+    let c0 = centroid(reference);
+    let c1 = centroid(path);
+    let mut best = None;
+    let mut best_err = f64::INFINITY;
+    for r in 0..n {
+        let structure_ok = (0..n).all(|i| {
+            same_kind(
+                reference.nodes[i].node_type,
+                path.nodes[(i + r) % n].node_type,
+            )
+        });
+        if !structure_ok {
+            continue;
+        }
+        let err: f64 = (0..n)
+            .map(|i| {
+                let v0 = reference.nodes[i].pt - c0;
+                let v1 = path.nodes[(i + r) % n].pt - c1;
+                (v0 - v1).hypot2()
+            })
+            .sum();
+        if err < best_err {
+            best_err = err;
+            best = Some(r);
+        }
+    }
+    best
+}
+
+/// Match contours greedily, cheapest pair first.
+///
+/// Returns, for each reference contour, the index of the matching contour.
+fn match_paths(reference: &[Path], paths: &[Path]) -> Vec<usize> {
+    let ref_info: Vec<_> = reference.iter().map(PathInfo::new).collect();
+    let info: Vec<_> = paths.iter().map(PathInfo::new).collect();
+    let scale = ref_info
+        .iter()
+        .map(|info| info.bbox)
+        .fold(None, |acc: Option<Rect>, bbox| {
+            Some(acc.map(|acc| acc.union(bbox)).unwrap_or(bbox))
+        })
+        .map(|bbox| bbox.width().hypot(bbox.height()))
+        .unwrap_or(1.0)
+        .max(1.0);
+    let mut costs = Vec::new();
+    for (i, ri) in ref_info.iter().enumerate() {
+        for (j, pj) in info.iter().enumerate() {
+            costs.push((ri.cost(pj, scale), i, j));
+        }
+    }
+    costs.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+    let mut result = vec![None; reference.len()];
+    let mut used = vec![false; paths.len()];
+    for (_, i, j) in costs {
+        if result[i].is_none() && !used[j] {
+            result[i] = Some(j);
+            used[j] = true;
+        }
+    }
+    result.into_iter().map(Option::unwrap).collect()
+}
+
+/// Make the paths of one layer compatible with the reference, in place.
+fn fix_paths(reference: &[Path], paths: &mut Vec<Path>, what: &str, report: &mut CompatReport) {
+    if reference.len() != paths.len() {
+        report.unfixed.push(format!(
+            "{}: {} contours, reference has {}",
+            what,
+            paths.len(),
+            reference.len()
+        ));
+        return;
+    }
+    let order = match_paths(reference, paths);
+    if order.iter().enumerate().any(|(i, &j)| i != j) {
+        report
+            .fixed
+            .push(format!("{}: reordered contours {:?}", what, order));
+        *paths = order.iter().map(|&j| paths[j].clone()).collect();
+    }
+    for (i, (ref_path, path)) in reference.iter().zip(paths.iter_mut()).enumerate() {
+        if ref_path.nodes.len() != path.nodes.len() || ref_path.closed != path.closed {
+            report.unfixed.push(format!(
+                "{}: contour {} has {} nodes, reference has {}",
+                what,
+                i,
+                path.nodes.len(),
+                ref_path.nodes.len()
+            ));
+            continue;
+        }
+        if !path.closed {
+            // Open paths have a fixed start, but may be reversed.
+            let ok = |p: &Path| {
+                (0..p.nodes.len())
+                    .all(|k| same_kind(ref_path.nodes[k].node_type, p.nodes[k].node_type))
+            };
+            if !ok(path) {
+                let mut reversed = path.clone();
+                reversed.reverse_direction();
+                if ok(&reversed) {
+                    *path = reversed;
+                    report
+                        .fixed
+                        .push(format!("{}: reversed open contour {}", what, i));
+                } else {
+                    report.unfixed.push(format!(
+                        "{}: open contour {} has different structure",
+                        what, i
+                    ));
+                }
+            }
+            continue;
+        }
+        // Only touch the contour once both its direction and start point
+        // line up with the reference.
+        let mut fixed = path.clone();
+        let reversed = signed_area(ref_path).signum() != signed_area(path).signum();
+        if reversed {
+            fixed.reverse_direction();
+        }
+        let r = match best_rotation(ref_path, &fixed) {
+            Some(r) => r,
+            None => {
+                report
+                    .unfixed
+                    .push(format!("{}: contour {} has different structure", what, i));
+                continue;
+            }
+        };
+        if reversed {
+            report
+                .fixed
+                .push(format!("{}: reversed contour {}", what, i));
+        }
+        if r != 0 {
+            fixed.rotate_left(r);
+            report.fixed.push(format!(
+                "{}: moved start point of contour {} by {}",
+                what, i, r
+            ));
+        }
+        *path = fixed;
+    }
+}
+
+/// Make all layers of the glyph compatible with the reference layer.
+pub fn fix_glyph_compat(glyph: &mut Glyph, reference_id: &str, report: &mut CompatReport) {
+    let reference = match glyph.get_layer(reference_id) {
+        Some(layer) => layer.paths.clone().unwrap_or_default(),
+        None => return,
+    };
+    let glyphname = glyph.glyphname.clone();
+    for layer in &mut glyph.layers {
+        if layer.layer_id == reference_id {
+            continue;
+        }
+        let what = format!("{} layer {}", glyphname, layer.layer_id);
+        match layer.paths {
+            Some(ref mut paths) => fix_paths(&reference, paths, &what, report),
+            None => {
+                if !reference.is_empty() {
+                    report.unfixed.push(format!("{}: no contours", what));
+                }
+            }
+        }
+    }
+}
+
+/// Detect and fix start point, contour order, and direction mismatches
+/// between masters.
+///
+/// The first master is used as the reference. If `glyphnames` is nonempty,
+/// only those glyphs are touched.
+pub fn fix_compat(font: &mut Font, glyphnames: &[String]) -> CompatReport {
+    let mut report = CompatReport::default();
+    let reference_id = match font.font_master.first() {
+        Some(master) => master.id.clone(),
+        None => return report,
+    };
+    for glyph in &mut font.glyphs {
+        if glyphnames.is_empty() || glyphnames.contains(&glyph.glyphname) {
+            fix_glyph_compat(glyph, &reference_id, &mut report);
+        }
+    }
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::font::Layer;
+
+    /// A counter-clockwise square of lines, starting at its lower left.
+    fn square(x: f64, y: f64, size: f64) -> Path {
+        let mut path = Path::new(true);
+        for &(dx, dy) in &[(0.0, 0.0), (size, 0.0), (size, size), (0.0, size)] {
+            path.add((x + dx, y + dy), NodeType::Line);
+        }
+        path
+    }
+
+    fn layer(layer_id: &str, paths: Vec<Path>) -> Layer {
+        Layer {
+            layer_id: layer_id.to_string(),
+            width: 600.0,
+            paths: Some(paths),
+            components: None,
+            anchors: None,
+            guide_lines: None,
+            other_stuff: HashMap::new(),
+        }
+    }
+
+    fn fix(reference: Vec<Path>, paths: Vec<Path>) -> (Vec<Path>, CompatReport) {
+        let mut glyph = Glyph {
+            layers: vec![layer("light", reference), layer("bold", paths)],
+            glyphname: "a".to_string(),
+            other_stuff: HashMap::new(),
+        };
+        let mut report = CompatReport::default();
+        fix_glyph_compat(&mut glyph, "light", &mut report);
+        let paths = glyph.get_layer("bold").unwrap().paths.clone().unwrap();
+        (paths, report)
+    }
+
+    fn points(path: &Path) -> Vec<Point> {
+        path.nodes.iter().map(|node| node.pt).collect()
+    }
+
+    #[test]
+    fn reorder_contours() {
+        let reference = vec![square(0.0, 0.0, 100.0), square(300.0, 0.0, 50.0)];
+        let bold = vec![square(310.0, 0.0, 60.0), square(0.0, 0.0, 120.0)];
+        let (paths, report) = fix(reference, bold.clone());
+        assert_eq!(points(&paths[0]), points(&bold[1]));
+        assert_eq!(points(&paths[1]), points(&bold[0]));
+        assert_eq!(
+            report.fixed,
+            vec!["a layer bold: reordered contours [1, 0]"]
+        );
+        assert!(report.unfixed.is_empty());
+    }
+
+    #[test]
+    fn fix_start_point() {
+        let mut bold = square(0.0, 0.0, 120.0);
+        bold.rotate_left(1);
+        let (paths, report) = fix(vec![square(0.0, 0.0, 100.0)], vec![bold]);
+        assert_eq!(points(&paths[0]), points(&square(0.0, 0.0, 120.0)));
+        assert_eq!(
+            report.fixed,
+            vec!["a layer bold: moved start point of contour 0 by 3"]
+        );
+        assert!(report.unfixed.is_empty());
+    }
+
+    #[test]
+    fn fix_direction() {
+        let mut bold = square(0.0, 0.0, 120.0);
+        bold.reverse_direction();
+        let (paths, report) = fix(vec![square(0.0, 0.0, 100.0)], vec![bold]);
+        assert_eq!(points(&paths[0]), points(&square(0.0, 0.0, 120.0)));
+        assert_eq!(report.fixed[0], "a layer bold: reversed contour 0");
+        assert!(report.unfixed.is_empty());
+    }
+
+    #[test]
+    fn unfixed_contour_is_left_alone() {
+        // Reversed, but with a curve where the reference has lines: neither
+        // the reversal nor anything else should be reported as fixed.
+        let mut bold = Path::new(true);
+        bold.add((0.0, 0.0), NodeType::Line);
+        bold.add((0.0, 120.0), NodeType::Line);
+        bold.add((60.0, 120.0), NodeType::OffCurve);
+        bold.add((120.0, 60.0), NodeType::Curve);
+        let (paths, report) = fix(vec![square(0.0, 0.0, 100.0)], vec![bold.clone()]);
+        assert_eq!(points(&paths[0]), points(&bold));
+        assert!(report.fixed.is_empty());
+        assert_eq!(
+            report.unfixed,
+            vec!["a layer bold: contour 0 has different structure"]
+        );
+    }
+}
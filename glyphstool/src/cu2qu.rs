@@ -0,0 +1,376 @@
+//! Conversion of cubic outlines to quadratic, keeping masters compatible.
+//!
+//! This follows the approach of the fontTools cu2qu library: each cubic is
+//! approximated by a quadratic spline with `n` off-curve points (on-curve
+//! points implied at the midpoints), and `n` is chosen as the smallest value
+//! for which every corresponding cubic, in every master and brace layer,
+//! is within tolerance. Using the same `n` everywhere is what keeps the
+//! result interpolatable.
+
+use kurbo::{CubicBez, ParamCurve, Point, Vec2};
+
+use crate::compat::CompatReport;
+use crate::font::{Font, Glyph, Node, NodeType, Path};
+
+/// The maximum number of off-curve points in a converted segment.
+const MAX_N: usize = 100;
+
+fn cross(a: Vec2, b: Vec2) -> f64 {
+    a.x * b.y - a.y * b.x
+}
+
+fn abs(v: Vec2) -> f64 {
+    v.hypot()
+}
+
+/// Intersection of the line through `a` and `b` with the line through `c`
+/// and `d`.
+fn calc_intersect(a: Point, b: Point, c: Point, d: Point) -> Option<Point> {
+    let ab = b - a;
+    let cd = d - c;
+    let denom = cross(ab, cd);
+    if denom == 0.0 {
+        return None;
+    }
+    let h = cross(ab, a - c) / denom;
+    Some(c + cd * h)
+}
+
+/// Check that a cubic (given as deltas from the one it approximates) stays
+/// within `tolerance` of the origin, by recursive subdivision.
+fn cubic_farthest_fit_inside(p0: Vec2, p1: Vec2, p2: Vec2, p3: Vec2, tolerance: f64) -> bool {
+    if abs(p2) <= tolerance && abs(p1) <= tolerance {
+        return true;
+    }
+    let mid = (p0 + (p1 + p2) * 3.0 + p3) * 0.125;
+    if abs(mid) > tolerance {
+        return false;
+    }
+    let deriv3 = (p3 + p2 - p1 - p0) * 0.125;
+    cubic_farthest_fit_inside(p0, (p0 + p1) * 0.5, mid - deriv3, mid, tolerance)
+        && cubic_farthest_fit_inside(mid, mid + deriv3, (p2 + p3) * 0.5, p3, tolerance)
+}
+
+fn cubic_approx_quadratic(c: CubicBez, tolerance: f64) -> Option<Vec<Point>> {
+    let q1 = calc_intersect(c.p0, c.p1, c.p2, c.p3)?;
+    let c1 = c.p0 + (q1 - c.p0) * (2.0 / 3.0);
+    let c2 = c.p3 + (q1 - c.p3) * (2.0 / 3.0);
+    let zero = Vec2::new(0.0, 0.0);
+    if !cubic_farthest_fit_inside(zero, c1 - c.p1, c2 - c.p2, zero, tolerance) {
+        return None;
+    }
+    Some(vec![q1])
+}
+
+fn cubic_approx_control(t: f64, c: CubicBez) -> Point {
+    let p1 = c.p0 + (c.p1 - c.p0) * 1.5;
+    let p2 = c.p3 + (c.p2 - c.p3) * 1.5;
+    p1.lerp(p2, t)
+}
+
+/// Approximate a cubic with a quadratic spline of `n` off-curve points.
+///
+/// Returns the off-curve points, or `None` if the error exceeds tolerance.
+fn cubic_approx_spline(c: CubicBez, n: usize, tolerance: f64) -> Option<Vec<Point>> {
+    if n == 1 {
+        return cubic_approx_quadratic(c, tolerance);
+    }
+    let pieces: Vec<CubicBez> = (0..n)
+        .map(|i| c.subsegment((i as f64 / n as f64)..((i + 1) as f64 / n as f64)))
+        .collect();
+    let mut spline = Vec::with_capacity(n);
+    let mut next_q1 = cubic_approx_control(0.0, pieces[0]);
+    spline.push(next_q1);
+    let mut q2 = c.p0;
+    let mut d1 = Vec2::new(0.0, 0.0);
+    for i in 1..=n {
+        let piece = pieces[i - 1];
+        let q0 = q2;
+        let q1 = next_q1;
+        if i < n {
+            next_q1 = cubic_approx_control(i as f64 / (n - 1) as f64, pieces[i]);
+            spline.push(next_q1);
+            q2 = q1.midpoint(next_q1);
+        } else {
+            q2 = piece.p3;
+        }
+        let d0 = d1;
+        d1 = q2 - piece.p3;
+        if abs(d1) > tolerance
+            || !cubic_farthest_fit_inside(
+                d0,
+                q0 + (q1 - q0) * (2.0 / 3.0) - piece.p1,
+                q2 + (q1 - q2) * (2.0 / 3.0) - piece.p2,
+                d1,
+                tolerance,
+            )
+        {
+            return None;
+        }
+    }
+    Some(spline)
+}
+
+/// Convert corresponding cubics to quadratic splines that all have the same
+/// number of off-curve points.
+pub fn curves_to_quadratic(curves: &[CubicBez], tolerance: f64) -> Option<Vec<Vec<Point>>> {
+    for n in 1..=MAX_N {
+        let splines: Option<Vec<_>> = curves
+            .iter()
+            .map(|&c| cubic_approx_spline(c, n, tolerance))
+            .collect();
+        if splines.is_some() {
+            return splines;
+        }
+    }
+    None
+}
+
+/// Convert corresponding paths (one per layer) to quadratic.
+fn convert_paths(paths: &[&Path], tolerance: f64) -> Result<Vec<Path>, String> {
+    let first = paths[0];
+    let n = first.nodes.len();
+    for path in paths {
+        let same = path.nodes.len() == n
+            && path.closed == first.closed
+            && path.nodes.iter().zip(&first.nodes).all(|(a, b)| {
+                (a.node_type == NodeType::OffCurve) == (b.node_type == NodeType::OffCurve)
+            });
+        if !same {
+            return Err("incompatible contours".to_string());
+        }
+    }
+    // For each converted segment, keyed by the index of its end node, the
+    // quadratic off-curve points for each layer.
+    let mut converted = vec![None; n];
+    // Index of the end node for off-curve points in converted segments.
+    let mut owner = vec![None; n];
+    for (end, node) in first.nodes.iter().enumerate() {
+        let node_type = node.node_type;
+        if node_type == NodeType::OffCurve || node_type.is_quadratic() {
+            continue;
+        }
+        if !first.closed && end < 3 {
+            continue;
+        }
+        let ix = |k: usize| (end + n - k) % n;
+        let is_cubic = first.nodes[ix(1)].node_type == NodeType::OffCurve
+            && first.nodes[ix(2)].node_type == NodeType::OffCurve
+            && first.nodes[ix(3)].node_type != NodeType::OffCurve;
+        if !is_cubic {
+            continue;
+        }
+        let cubics: Vec<CubicBez> = paths
+            .iter()
+            .map(|path| {
+                let p = |k: usize| path.nodes[ix(k)].pt;
+                CubicBez::new(p(3), p(2), p(1), p(0))
+            })
+            .collect();
+        let splines = curves_to_quadratic(&cubics, tolerance)
+            .ok_or_else(|| format!("could not convert segment ending at node {}", end))?;
+        converted[end] = Some(splines);
+        owner[ix(1)] = Some(end);
+        owner[ix(2)] = Some(end);
+    }
+    let result = paths
+        .iter()
+        .enumerate()
+        .map(|(layer_ix, path)| {
+            let mut nodes = Vec::with_capacity(n);
+            for (i, node) in path.nodes.iter().enumerate() {
+                match owner[i] {
+                    Some(end) => {
+                        // Emit the whole spline in place of the first off-curve.
+                        if i == (end + n - 2) % n {
+                            let splines = converted[end].as_ref().unwrap();
+                            for &pt in &splines[layer_ix] {
+                                nodes.push(Node {
+                                    pt,
+                                    node_type: NodeType::OffCurve,
+                                });
+                            }
+                        }
+                    }
+                    None => {
+                        let node_type = match (converted[i].is_some(), node.node_type.is_smooth()) {
+                            (true, true) => NodeType::QCurveSmooth,
+                            (true, false) => NodeType::QCurve,
+                            (false, _) => node.node_type,
+                        };
+                        nodes.push(Node {
+                            pt: node.pt,
+                            node_type,
+                        });
+                    }
+                }
+            }
+            Path {
+                closed: path.closed,
+                nodes,
+            }
+        })
+        .collect();
+    Ok(result)
+}
+
+/// Convert the interpolating layers (masters and brace layers) of a glyph to
+/// quadratic.
+pub fn glyph_to_quadratic(
+    glyph: &mut Glyph,
+    master_ids: &[&str],
+    tolerance: f64,
+    report: &mut CompatReport,
+) {
+    let layer_ixs: Vec<usize> = glyph
+        .layers
+        .iter()
+        .enumerate()
+        .filter(|(_, layer)| {
+            master_ids.contains(&layer.layer_id.as_str()) || layer.brace_coords().is_some()
+        })
+        .map(|(i, _)| i)
+        .collect();
+    let n_paths = match layer_ixs.first() {
+        Some(&i) => glyph.layers[i].paths.as_ref().map(Vec::len).unwrap_or(0),
+        None => return,
+    };
+    if layer_ixs
+        .iter()
+        .any(|&i| glyph.layers[i].paths.as_ref().map(Vec::len).unwrap_or(0) != n_paths)
+    {
+        report.unfixed.push(format!(
+            "{}: layers have different numbers of contours",
+            glyph.glyphname
+        ));
+        return;
+    }
+    let mut converted = Vec::with_capacity(n_paths);
+    for path_ix in 0..n_paths {
+        let paths: Vec<&Path> = layer_ixs
+            .iter()
+            .map(|&i| &glyph.layers[i].paths.as_ref().unwrap()[path_ix])
+            .collect();
+        match convert_paths(&paths, tolerance) {
+            Ok(paths) => converted.push(paths),
+            Err(e) => {
+                report
+                    .unfixed
+                    .push(format!("{} contour {}: {}", glyph.glyphname, path_ix, e));
+                return;
+            }
+        }
+    }
+    for (k, &i) in layer_ixs.iter().enumerate() {
+        if let Some(ref mut paths) = glyph.layers[i].paths {
+            for (path_ix, path) in paths.iter_mut().enumerate() {
+                *path = converted[path_ix][k].clone();
+            }
+        }
+    }
+    if n_paths > 0 {
+        report
+            .fixed
+            .push(format!("{}: converted to quadratic", glyph.glyphname));
+    }
+}
+
+/// Convert all glyphs in the font to quadratic, within the given tolerance
+/// (in font units).
+pub fn font_to_quadratic(font: &mut Font, tolerance: f64) -> CompatReport {
+    let mut report = CompatReport::default();
+    let master_ids: Vec<String> = font.font_master.iter().map(|m| m.id.clone()).collect();
+    let master_ids: Vec<&str> = master_ids.iter().map(String::as_str).collect();
+    for glyph in &mut font.glyphs {
+        glyph_to_quadratic(glyph, &master_ids, tolerance, &mut report);
+    }
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::font::Layer;
+
+    fn cubic(pts: [(f64, f64); 4]) -> CubicBez {
+        CubicBez::new(pts[0], pts[1], pts[2], pts[3])
+    }
+
+    #[test]
+    fn elevated_quadratic() {
+        // The quadratic (0, 0), (60, 90), (90, 0), raised to a cubic.
+        let c = cubic([(0.0, 0.0), (40.0, 60.0), (70.0, 60.0), (90.0, 0.0)]);
+        let splines = curves_to_quadratic(&[c], 0.01).unwrap();
+        assert_eq!(splines.len(), 1);
+        assert_eq!(splines[0].len(), 1);
+        let q = splines[0][0];
+        assert!((q - Point::new(60.0, 90.0)).hypot() < 1e-9, "{:?}", q);
+    }
+
+    /// An S curve has no single quadratic, so it is split, and the implied
+    /// on-curve points lie on it.
+    #[test]
+    fn split_s_curve() {
+        let c = cubic([(0.0, 0.0), (100.0, 0.0), (0.0, 100.0), (100.0, 100.0)]);
+        let spline = curves_to_quadratic(&[c], 1.0).unwrap().remove(0);
+        let n = spline.len();
+        assert!(n > 1, "{:?}", spline);
+        for i in 1..n {
+            let on_curve = spline[i - 1].midpoint(spline[i]);
+            let expected = c.eval(i as f64 / n as f64);
+            assert!((on_curve - expected).hypot() <= 1.0, "{:?}", spline);
+        }
+    }
+
+    fn curve_layer(layer_id: &str, pts: [(f64, f64); 4]) -> Layer {
+        let mut path = Path::new(true);
+        path.add(pts[0], NodeType::Line);
+        path.add(pts[1], NodeType::OffCurve);
+        path.add(pts[2], NodeType::OffCurve);
+        path.add(pts[3], NodeType::Curve);
+        Layer {
+            layer_id: layer_id.to_string(),
+            width: 500.0,
+            paths: Some(vec![path]),
+            components: None,
+            anchors: None,
+            guide_lines: None,
+            other_stuff: HashMap::new(),
+        }
+    }
+
+    /// A segment that one quadratic fits in one master is split as finely
+    /// as the other master needs, so the layers stay compatible.
+    #[test]
+    fn compatible_masters() {
+        let light = curve_layer(
+            "light",
+            [(0.0, 0.0), (40.0, 60.0), (70.0, 60.0), (90.0, 0.0)],
+        );
+        let bold = curve_layer(
+            "bold",
+            [(0.0, 0.0), (100.0, 0.0), (0.0, 100.0), (100.0, 100.0)],
+        );
+        let mut glyph = Glyph {
+            layers: vec![light, bold],
+            glyphname: "a".to_string(),
+            other_stuff: HashMap::new(),
+        };
+        let mut report = CompatReport::default();
+        glyph_to_quadratic(&mut glyph, &["light", "bold"], 1.0, &mut report);
+        assert!(report.unfixed.is_empty(), "{:?}", report.unfixed);
+        let node_types: Vec<Vec<NodeType>> = glyph
+            .layers
+            .iter()
+            .map(|layer| {
+                let path = &layer.paths.as_ref().unwrap()[0];
+                path.nodes.iter().map(|node| node.node_type).collect()
+            })
+            .collect();
+        assert_eq!(node_types[0], node_types[1]);
+        assert!(node_types[0].len() > 3, "{:?}", node_types[0]);
+        assert_eq!(node_types[0].last(), Some(&NodeType::QCurve));
+    }
+}
@@ -6,7 +6,7 @@
 
 use std::collections::HashMap;
 
-use kurbo::{Affine, Point};
+use kurbo::{Affine, BezPath, Point};
 
 use crate::from_plist::FromPlist;
 use crate::plist::Plist;
@@ -59,6 +59,8 @@ pub enum NodeType {
     OffCurve,
     Curve,
     CurveSmooth,
+    QCurve,
+    QCurveSmooth,
 }
 
 #[derive(Clone, Debug, FromPlist, ToPlist)]
@@ -90,8 +92,99 @@ pub struct FontMaster {
     pub other_stuff: HashMap<String, Plist>,
 }
 
+/// A design space axis.
+#[derive(Clone, Debug)]
+pub struct Axis {
+    pub name: String,
+    pub tag: String,
+}
+
+/// Keys for master coordinates, by slot (see `axis_slots`).
+pub(crate) const MASTER_AXIS_KEYS: &[&str] = &[
+    "weightValue",
+    "widthValue",
+    "customValue",
+    "customValue1",
+    "customValue2",
+    "customValue3",
+];
+
+/// Keys for instance coordinates, by slot, with their defaults.
+pub(crate) const INSTANCE_AXIS_KEYS: &[(&str, f64)] = &[
+    ("interpolationWeight", 100.0),
+    ("interpolationWidth", 100.0),
+    ("interpolationCustom", 0.0),
+    ("interpolationCustom1", 0.0),
+    ("interpolationCustom2", 0.0),
+    ("interpolationCustom3", 0.0),
+];
+
+/// The slot of each axis in `MASTER_AXIS_KEYS` and `INSTANCE_AXIS_KEYS`:
+/// the weight and width axes are found by tag, and the other axes take the
+/// custom values in order.
+pub(crate) fn axis_slots(axes: &[Axis]) -> Vec<usize> {
+    let mut used = [false; 2];
+    let mut next_custom = 2;
+    axes.iter()
+        .map(|axis| {
+            let slot = match axis.tag.as_str() {
+                "wght" => Some(0),
+                "wdth" => Some(1),
+                _ => None,
+            };
+            match slot {
+                Some(slot) if !used[slot] => {
+                    used[slot] = true;
+                    slot
+                }
+                _ => {
+                    next_custom += 1;
+                    next_custom - 1
+                }
+            }
+        })
+        .collect()
+}
+
+/// The "Axes" custom parameter describing the given axes.
+pub(crate) fn axes_parameter(axes: &[Axis]) -> Plist {
+    let value: Vec<Plist> = axes
+        .iter()
+        .map(|axis| {
+            let mut dict = HashMap::new();
+            dict.insert("Name".to_string(), axis.name.clone().into());
+            dict.insert("Tag".to_string(), axis.tag.clone().into());
+            dict.into()
+        })
+        .collect();
+    let mut param = HashMap::new();
+    param.insert("name".to_string(), "Axes".to_string().into());
+    param.insert("value".to_string(), value.into());
+    param.into()
+}
+
+/// An entry of the "instances" array, at a design space location.
+pub(crate) fn instance_plist(name: &str, axes: &[Axis], location: &[f64]) -> Plist {
+    let mut dict = HashMap::new();
+    dict.insert("name".to_string(), name.to_string().into());
+    for (slot, &value) in axis_slots(axes).into_iter().zip(location) {
+        if let Some(&(key, _)) = INSTANCE_AXIS_KEYS.get(slot) {
+            dict.insert(key.to_string(), Plist::Float(value));
+        }
+    }
+    dict.into()
+}
+
 impl Font {
+    /// Load a font from a Glyphs file, a designspace document, a UFO or a
+    /// variable TrueType font.
     pub fn load(path: &std::path::Path) -> Result<Font, String> {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("designspace") => return crate::ufo::import_designspace(path),
+            Some("ufo") => return crate::ufo::import_ufo(path),
+            Some("ttf") => return crate::ttf::import_variable_ttf(path),
+            _ => (),
+        }
         let contents = std::fs::read_to_string(path).map_err(|e| format!("{:?}", e))?;
         let plist = Plist::parse(&contents).map_err(|e| format!("{:?}", e))?;
         Ok(FromPlist::from_plist(plist))
@@ -104,6 +197,141 @@ impl Font {
     pub fn get_glyph_mut(&mut self, glyphname: &str) -> Option<&mut Glyph> {
         self.glyphs.iter_mut().find(|g| g.glyphname == glyphname)
     }
+
+    pub fn custom_parameter(&self, name: &str) -> Option<&Plist> {
+        let params = self.other_stuff.get("customParameters")?.as_array()?;
+        params
+            .iter()
+            .find(|p| p.get("name").and_then(Plist::as_str) == Some(name))
+            .and_then(|p| p.get("value"))
+    }
+
+    /// The axes of the font.
+    ///
+    /// These come from the "Axes" custom parameter if present. Otherwise,
+    /// weight is assumed, plus width if any master has a width value.
+    pub fn axes(&self) -> Vec<Axis> {
+        if let Some(axes) = self.custom_parameter("Axes").and_then(Plist::as_array) {
+            return axes
+                .iter()
+                .map(|axis| Axis {
+                    name: axis
+                        .get("Name")
+                        .and_then(Plist::as_str)
+                        .unwrap_or("")
+                        .into(),
+                    tag: axis.get("Tag").and_then(Plist::as_str).unwrap_or("").into(),
+                })
+                .collect();
+        }
+        let mut axes = vec![Axis {
+            name: "Weight".into(),
+            tag: "wght".into(),
+        }];
+        if self.font_master.iter().any(|m| m.width_value.is_some()) {
+            axes.push(Axis {
+                name: "Width".into(),
+                tag: "wdth".into(),
+            });
+        }
+        axes
+    }
+
+    /// The design space location of a master or brace layer, one value per
+    /// axis. Coordinates missing from the name of a brace layer are taken
+    /// from its associated master.
+    pub fn layer_location(&self, layer: &Layer) -> Option<Vec<f64>> {
+        let axes = self.axes();
+        layer.location(axes.len(), |id| {
+            self.font_master
+                .iter()
+                .find(|m| m.id == id)
+                .map(|m| m.location(&axes))
+        })
+    }
+
+    /// The named instances of the font, with their design space locations.
+    pub fn instances(&self) -> Vec<(String, Vec<f64>)> {
+        let slots = axis_slots(&self.axes());
+        let instances = self
+            .other_stuff
+            .get("instances")
+            .and_then(Plist::as_array)
+            .unwrap_or(&[]);
+        instances
+            .iter()
+            .map(|instance| {
+                let name = instance
+                    .get("name")
+                    .and_then(Plist::as_str)
+                    .unwrap_or("Regular");
+                let location = slots
+                    .iter()
+                    .map(|&slot| {
+                        INSTANCE_AXIS_KEYS
+                            .get(slot)
+                            .map(|&(key, default)| {
+                                instance.get(key).and_then(Plist::as_f64).unwrap_or(default)
+                            })
+                            .unwrap_or(0.0)
+                    })
+                    .collect();
+                (name.to_string(), location)
+            })
+            .collect()
+    }
+}
+
+impl FontMaster {
+    /// The location of the master in design space, one value per axis.
+    pub fn location(&self, axes: &[Axis]) -> Vec<f64> {
+        axis_slots(axes)
+            .into_iter()
+            .map(|slot| match slot {
+                0 => self.weight_value as f64,
+                1 => self.width_value.unwrap_or(100) as f64,
+                _ => MASTER_AXIS_KEYS
+                    .get(slot)
+                    .and_then(|key| self.other_stuff.get(*key))
+                    .and_then(Plist::as_f64)
+                    .unwrap_or(0.0),
+            })
+            .collect()
+    }
+
+    /// Move the master to a location in design space. Weight and width are
+    /// stored as integers, so they are rounded.
+    pub fn set_location(&mut self, axes: &[Axis], location: &[f64]) {
+        for (slot, &value) in axis_slots(axes).into_iter().zip(location) {
+            match slot {
+                0 => self.weight_value = value.round() as i64,
+                1 => self.width_value = Some(value.round() as i64),
+                _ => {
+                    if let Some(key) = MASTER_AXIS_KEYS.get(slot) {
+                        self.other_stuff
+                            .insert(key.to_string(), Plist::Float(value));
+                    }
+                }
+            }
+        }
+    }
+
+    /// A human-readable name for the master.
+    pub fn name(&self) -> String {
+        if let Some(name) = self.other_stuff.get("name").and_then(Plist::as_str) {
+            return name.to_string();
+        }
+        let parts: Vec<&str> = ["weight", "width", "custom"]
+            .iter()
+            .filter_map(|key| self.other_stuff.get(*key).and_then(Plist::as_str))
+            .filter(|part| *part != "Regular")
+            .collect();
+        if parts.is_empty() {
+            "Regular".to_string()
+        } else {
+            parts.join(" ")
+        }
+    }
 }
 
 impl Glyph {
@@ -112,6 +340,53 @@ impl Glyph {
     }
 }
 
+impl Layer {
+    /// The design space location of the layer, given the location of each
+    /// master by id. See `Font::layer_location`.
+    pub(crate) fn location(
+        &self,
+        n_axes: usize,
+        master_location: impl Fn(&str) -> Option<Vec<f64>>,
+    ) -> Option<Vec<f64>> {
+        if let Some(location) = master_location(&self.layer_id) {
+            return Some(location);
+        }
+        let coords = self.brace_coords()?;
+        let master = self
+            .other_stuff
+            .get("associatedMasterId")
+            .and_then(Plist::as_str)
+            .and_then(&master_location);
+        let location = (0..n_axes)
+            .map(|i| {
+                coords
+                    .get(i)
+                    .copied()
+                    .or_else(|| master.as_ref().map(|m| m[i]))
+                    .unwrap_or(0.0)
+            })
+            .collect();
+        Some(location)
+    }
+
+    pub fn name(&self) -> Option<&str> {
+        self.other_stuff.get("name").and_then(Plist::as_str)
+    }
+
+    /// The design space location of a brace (intermediate) layer, parsed
+    /// from a layer name such as `{550}` or `{550, 100}`.
+    pub fn brace_coords(&self) -> Option<Vec<f64>> {
+        let name = self.name()?.trim();
+        if !name.starts_with('{') || !name.ends_with('}') {
+            return None;
+        }
+        name[1..name.len() - 1]
+            .split(',')
+            .map(|c| c.trim().parse().ok())
+            .collect()
+    }
+}
+
 impl FromPlist for Node {
     fn from_plist(plist: Plist) -> Self {
         let mut spl = plist.as_str().unwrap().splitn(3, ' ');
@@ -132,12 +407,25 @@ impl std::str::FromStr for NodeType {
             "OFFCURVE" => Ok(NodeType::OffCurve),
             "CURVE" => Ok(NodeType::Curve),
             "CURVE SMOOTH" => Ok(NodeType::CurveSmooth),
+            "QCURVE" => Ok(NodeType::QCurve),
+            "QCURVE SMOOTH" => Ok(NodeType::QCurveSmooth),
             _ => Err(format!("unknown node type {}", s)),
         }
     }
 }
 
 impl NodeType {
+    pub fn is_smooth(self) -> bool {
+        matches!(
+            self,
+            NodeType::LineSmooth | NodeType::CurveSmooth | NodeType::QCurveSmooth
+        )
+    }
+
+    pub fn is_quadratic(self) -> bool {
+        matches!(self, NodeType::QCurve | NodeType::QCurveSmooth)
+    }
+
     fn glyphs_str(&self) -> &'static str {
         match self {
             NodeType::Line => "LINE",
@@ -145,6 +433,8 @@ impl NodeType {
             NodeType::OffCurve => "OFFCURVE",
             NodeType::Curve => "CURVE",
             NodeType::CurveSmooth => "CURVE SMOOTH",
+            NodeType::QCurve => "QCURVE",
+            NodeType::QCurveSmooth => "QCURVE SMOOTH",
         }
     }
 }
@@ -220,4 +510,143 @@ impl Path {
     pub fn reverse(&mut self) {
         self.nodes.reverse();
     }
+
+    /// Reverse the direction of the path, keeping it the same shape.
+    ///
+    /// Unlike `reverse`, this fixes up the node types, as the type of an
+    /// on-curve node describes the segment leading into it.
+    pub fn reverse_direction(&mut self) {
+        let n = self.nodes.len();
+        // Whether the segment leaving each node is quadratic; after reversal,
+        // it will be the segment entering it.
+        let mut quad = vec![false; n];
+        for (i, quad) in quad.iter_mut().enumerate() {
+            if self.nodes[i].node_type == NodeType::OffCurve {
+                continue;
+            }
+            let next_on = (1..n)
+                .map(|j| (i + j) % n)
+                .take_while(|&j| self.closed || j > i)
+                .find(|&j| self.nodes[j].node_type != NodeType::OffCurve);
+            if let Some(j) = next_on {
+                *quad = self.nodes[j].node_type.is_quadratic();
+            }
+        }
+        self.reverse();
+        quad.reverse();
+        for (node, &quad) in self.nodes.iter_mut().zip(&quad) {
+            let smooth = node.node_type.is_smooth();
+            node.node_type = match node.node_type {
+                NodeType::OffCurve => NodeType::OffCurve,
+                _ if quad && smooth => NodeType::QCurveSmooth,
+                _ if quad => NodeType::QCurve,
+                // Cubic or line; fixed up below.
+                _ if smooth => NodeType::CurveSmooth,
+                _ => NodeType::Curve,
+            };
+        }
+        self.fix_node_types();
+    }
+
+    /// Set on-curve node types to match the segments that lead into them,
+    /// preserving smoothness.
+    pub fn fix_node_types(&mut self) {
+        let n = self.nodes.len();
+        for i in 0..n {
+            if self.nodes[i].node_type == NodeType::OffCurve {
+                continue;
+            }
+            let prev_off = if i > 0 {
+                self.nodes[i - 1].node_type == NodeType::OffCurve
+            } else {
+                self.closed && n > 0 && self.nodes[n - 1].node_type == NodeType::OffCurve
+            };
+            let node_type = self.nodes[i].node_type;
+            let smooth = node_type.is_smooth();
+            self.nodes[i].node_type = match (prev_off, smooth) {
+                (true, true) if node_type.is_quadratic() => NodeType::QCurveSmooth,
+                (true, false) if node_type.is_quadratic() => NodeType::QCurve,
+                (true, true) => NodeType::CurveSmooth,
+                (true, false) => NodeType::Curve,
+                (false, true) => NodeType::LineSmooth,
+                (false, false) => NodeType::Line,
+            };
+        }
+    }
+
+    /// Convert the path to a `BezPath`.
+    pub fn to_bez_path(&self) -> BezPath {
+        let mut bez_path = BezPath::new();
+        self.append_to_bez_path(&mut bez_path);
+        bez_path
+    }
+
+    /// Append the path to an existing `BezPath`, as a new subpath.
+    pub fn append_to_bez_path(&self, bez_path: &mut BezPath) {
+        let pts: Vec<Point> = self.nodes.iter().map(|node| node.pt).collect();
+        let types: Vec<NodeType> = self.nodes.iter().map(|node| node.node_type).collect();
+        append_contour(bez_path, &pts, &types, self.closed);
+    }
+}
+
+/// Append a contour, given as parallel slices of points and node types, to
+/// a `BezPath`.
+///
+/// Cubic segments have exactly two off-curve points. Quadratic segments may
+/// have any number, with on-curve points implied at the midpoints between
+/// consecutive off-curve points, as in TrueType. A closed contour with no
+/// on-curve points at all is treated as entirely quadratic.
+pub fn append_contour(bez_path: &mut BezPath, pts: &[Point], types: &[NodeType], closed: bool) {
+    let n = pts.len();
+    if n == 0 {
+        return;
+    }
+    let (start_ix, start_pt) = match types.iter().position(|&t| t != NodeType::OffCurve) {
+        Some(ix) => (ix, pts[ix]),
+        // All off-curve: start at the implied point between last and first.
+        None => (n - 1, pts[n - 1].midpoint(pts[0])),
+    };
+    bez_path.move_to(start_pt);
+    let count = if closed { n } else { n - 1 - start_ix };
+    let mut ctrl_pts: Vec<Point> = Vec::with_capacity(2);
+    for i in 1..=count {
+        let ix = (start_ix + i) % n;
+        let p = pts[ix];
+        let node_type = types[ix];
+        if node_type == NodeType::OffCurve {
+            ctrl_pts.push(p);
+            continue;
+        }
+        if node_type.is_quadratic() || ctrl_pts.len() == 1 {
+            append_quads(bez_path, &ctrl_pts, p);
+        } else {
+            match ctrl_pts.len() {
+                0 => bez_path.line_to(p),
+                // Malformed cubics (more than two off-curves) just use the
+                // first two.
+                _ => bez_path.curve_to(ctrl_pts[0], ctrl_pts[1], p),
+            }
+        }
+        ctrl_pts.clear();
+    }
+    if closed {
+        if !ctrl_pts.is_empty() {
+            // The all off-curve case, ending back at the implied start.
+            append_quads(bez_path, &ctrl_pts, start_pt);
+        }
+        bez_path.close_path();
+    }
+}
+
+/// Append a sequence of quadratic segments with implied on-curve points.
+fn append_quads(bez_path: &mut BezPath, ctrl_pts: &[Point], end: Point) {
+    if ctrl_pts.is_empty() {
+        bez_path.line_to(end);
+        return;
+    }
+    for i in 0..ctrl_pts.len() - 1 {
+        let implied = ctrl_pts[i].midpoint(ctrl_pts[i + 1]);
+        bez_path.quad_to(ctrl_pts[i], implied);
+    }
+    bez_path.quad_to(ctrl_pts[ctrl_pts.len() - 1], end);
 }
@@ -0,0 +1,300 @@
+//! The geometry of a layer as a list of points.
+//!
+//! Everything about a layer that varies between masters is listed as
+//! points, so that interpolating a glyph is the same operation for all of
+//! it: the nodes of the paths, then the advance width, the anchors, the
+//! transforms of the components and the guides. Each point has a kind,
+//! which says how it responds to a change of coordinates.
+
+use kurbo::{Affine, Point, Vec2};
+
+use crate::font::{Layer, NodeType};
+
+/// The coefficients of the identity transform.
+const IDENTITY: [f64; 6] = [1.0, 0.0, 0.0, 1.0, 0.0, 0.0];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PointKind {
+    /// A node of a path.
+    Node,
+    /// The advance width, as the point on the baseline where it ends.
+    Advance,
+    /// The position of an anchor.
+    Anchor,
+    /// The translation of a component's transform.
+    ComponentOffset,
+    /// Where a component's transform takes the unit x vector, less its
+    /// translation.
+    ComponentXAxis,
+    /// Where a component's transform takes the unit y vector, less its
+    /// translation.
+    ComponentYAxis,
+    /// The position of a guide.
+    GuidePosition,
+    /// The angle of a guide in degrees, as x (y is always 0).
+    GuideAngle,
+}
+
+impl PointKind {
+    /// The point in coordinates related to the layer's by `a`: positions
+    /// are mapped, vectors by the linear part only, and angles so that
+    /// they keep their direction.
+    pub fn transform(self, a: Affine, p: Point) -> Point {
+        match self {
+            PointKind::ComponentXAxis | PointKind::ComponentYAxis => {
+                let v = a * p - a * Point::ORIGIN;
+                Point::new(v.x, v.y)
+            }
+            PointKind::GuideAngle => {
+                let angle = p.x.to_radians();
+                let v = a * Point::new(angle.cos(), angle.sin()) - a * Point::ORIGIN;
+                Point::new(v.y.atan2(v.x).to_degrees(), 0.0)
+            }
+            _ => a * p,
+        }
+    }
+}
+
+/// An angle in degrees, plus or minus whole turns to be within half a turn
+/// of a reference angle. Angles of the same guide in different masters are
+/// unwrapped so that blending them turns the short way: from 350 to 10
+/// degrees through 0, not 180.
+pub fn unwrap_angle(angle: f64, reference: f64) -> f64 {
+    angle + 360.0 * ((reference - angle) / 360.0).round()
+}
+
+/// The order in which the anchors of a layer are listed: by name, so
+/// layers that have them in a different order still line up.
+fn anchor_order(layer: &Layer) -> Vec<usize> {
+    let anchors = layer.anchors.as_deref().unwrap_or(&[]);
+    let mut order: Vec<usize> = (0..anchors.len()).collect();
+    order.sort_by(|&i, &j| anchors[i].name.cmp(&anchors[j].name));
+    order
+}
+
+/// The geometry of a layer: the nodes of its paths in order, then its
+/// advance, anchors (by name), components and guides.
+pub fn layer_geometry(layer: &Layer) -> Vec<(PointKind, Point)> {
+    let mut geometry = Vec::new();
+    for path in layer.paths.iter().flatten() {
+        for node in &path.nodes {
+            geometry.push((PointKind::Node, node.pt));
+        }
+    }
+    geometry.push((PointKind::Advance, Point::new(layer.width, 0.0)));
+    if let Some(anchors) = &layer.anchors {
+        for i in anchor_order(layer) {
+            geometry.push((PointKind::Anchor, anchors[i].position));
+        }
+    }
+    for component in layer.components.iter().flatten() {
+        let c = component.transform.map_or(IDENTITY, |a| a.as_coeffs());
+        geometry.push((PointKind::ComponentOffset, Point::new(c[4], c[5])));
+        geometry.push((PointKind::ComponentXAxis, Point::new(c[0], c[1])));
+        geometry.push((PointKind::ComponentYAxis, Point::new(c[2], c[3])));
+    }
+    for guide in layer.guide_lines.iter().flatten() {
+        geometry.push((PointKind::GuidePosition, guide.position));
+        let angle = guide.angle.unwrap_or(0.0);
+        geometry.push((PointKind::GuideAngle, Point::new(angle, 0.0)));
+    }
+    geometry
+}
+
+/// Set the geometry of a layer from points in the order given by
+/// `layer_geometry`. Missing points leave the rest unchanged. Transforms
+/// and angles that were absent stay so if they are still the default.
+pub fn set_layer_geometry(layer: &mut Layer, pts: &[Point]) {
+    let mut pts = pts.iter().copied();
+    for path in layer.paths.iter_mut().flatten() {
+        for node in &mut path.nodes {
+            match pts.next() {
+                Some(pt) => node.pt = pt,
+                None => return,
+            }
+        }
+    }
+    match pts.next() {
+        Some(pt) => layer.width = pt.x,
+        None => return,
+    }
+    let order = anchor_order(layer);
+    if let Some(anchors) = &mut layer.anchors {
+        for i in order {
+            match pts.next() {
+                Some(pt) => anchors[i].position = pt,
+                None => return,
+            }
+        }
+    }
+    for component in layer.components.iter_mut().flatten() {
+        let (offset, x_axis, y_axis) = match (pts.next(), pts.next(), pts.next()) {
+            (Some(offset), Some(x_axis), Some(y_axis)) => (offset, x_axis, y_axis),
+            _ => return,
+        };
+        let transform = Affine::new([x_axis.x, x_axis.y, y_axis.x, y_axis.y, offset.x, offset.y]);
+        if component.transform.is_some() || transform.as_coeffs() != IDENTITY {
+            component.transform = Some(transform);
+        }
+    }
+    for guide in layer.guide_lines.iter_mut().flatten() {
+        let (position, angle) = match (pts.next(), pts.next()) {
+            (Some(position), Some(angle)) => (position, angle.x),
+            _ => return,
+        };
+        guide.position = position;
+        if guide.angle.is_some() || angle != 0.0 {
+            guide.angle = Some(angle);
+        }
+    }
+}
+
+fn node_types(layer: &Layer) -> Vec<Vec<NodeType>> {
+    layer
+        .paths
+        .iter()
+        .flatten()
+        .map(|path| path.nodes.iter().map(|node| node.node_type).collect())
+        .collect()
+}
+
+fn anchor_names(layer: &Layer) -> Vec<String> {
+    let anchors = layer.anchors.as_deref().unwrap_or(&[]);
+    anchor_order(layer)
+        .into_iter()
+        .map(|i| anchors[i].name.clone())
+        .collect()
+}
+
+fn component_names(layer: &Layer) -> Vec<&str> {
+    layer
+        .components
+        .iter()
+        .flatten()
+        .map(|component| component.name.as_str())
+        .collect()
+}
+
+fn n_guides(layer: &Layer) -> usize {
+    layer.guide_lines.as_ref().map_or(0, Vec::len)
+}
+
+/// Whether two layers have the same geometry: the same number and types of
+/// nodes, anchors with the same names, components of the same glyphs in the
+/// same order, and as many guides.
+pub fn is_geometry_compatible(a: &Layer, b: &Layer) -> bool {
+    node_types(a) == node_types(b)
+        && anchor_names(a) == anchor_names(b)
+        && component_names(a) == component_names(b)
+        && n_guides(a) == n_guides(b)
+}
+
+/// Copies of two layers without the parts of their geometry (paths,
+/// anchors, components or guides) that don't line up, so that they can be
+/// blended. A part that is removed is `None` in both.
+pub fn compatible_parts(a: &Layer, b: &Layer) -> (Layer, Layer) {
+    let (mut a, mut b) = (a.clone(), b.clone());
+    if node_types(&a) != node_types(&b) {
+        a.paths = None;
+        b.paths = None;
+    }
+    if anchor_names(&a) != anchor_names(&b) {
+        a.anchors = None;
+        b.anchors = None;
+    }
+    if component_names(&a) != component_names(&b) {
+        a.components = None;
+        b.components = None;
+    }
+    if n_guides(&a) != n_guides(&b) {
+        a.guide_lines = None;
+        b.guide_lines = None;
+    }
+    (a, b)
+}
+
+/// The weighted sum of the geometry of some layers, as a copy of the first
+/// with its geometry replaced. The weights usually sum to 1. Guide angles
+/// are unwrapped to those of the first layer.
+pub fn blend_layers(layers: &[&Layer], weights: &[f64]) -> Result<Layer, String> {
+    let first = match layers.first() {
+        Some(first) => *first,
+        None => return Err("no layers to blend".to_string()),
+    };
+    if let Some(layer) = layers[1..]
+        .iter()
+        .find(|layer| !is_geometry_compatible(first, layer))
+    {
+        return Err(format!(
+            "layer {} is incompatible with {}",
+            layer.layer_id, first.layer_id
+        ));
+    }
+    let reference = layer_geometry(first);
+    let mut sum = vec![Vec2::new(0.0, 0.0); reference.len()];
+    for (layer, &w) in layers.iter().zip(weights) {
+        let geometry = layer_geometry(layer);
+        for ((s, (kind, mut pt)), (_, r)) in sum.iter_mut().zip(geometry).zip(&reference) {
+            if kind == PointKind::GuideAngle {
+                pt.x = unwrap_angle(pt.x, r.x);
+            }
+            *s += pt.to_vec2() * w;
+        }
+    }
+    let pts: Vec<Point> = sum.into_iter().map(|v| Point::new(v.x, v.y)).collect();
+    let mut result = first.clone();
+    set_layer_geometry(&mut result, &pts);
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::font::{Component, GuideLine};
+
+    fn layer(layer_id: &str, components: &[&str], angle: f64) -> Layer {
+        let components = components
+            .iter()
+            .map(|name| Component {
+                name: name.to_string(),
+                transform: None,
+                other_stuff: HashMap::new(),
+            })
+            .collect();
+        let guide = GuideLine {
+            angle: Some(angle),
+            position: Point::new(0.0, 0.0),
+        };
+        Layer {
+            layer_id: layer_id.to_string(),
+            width: 500.0,
+            paths: None,
+            components: Some(components),
+            anchors: None,
+            guide_lines: Some(vec![guide]),
+            other_stuff: HashMap::new(),
+        }
+    }
+
+    #[test]
+    fn compatible_components() {
+        let a = layer("a", &["a", "acute"], 0.0);
+        let b = layer("b", &["a", "grave"], 0.0);
+        let c = layer("c", &["a", "acute"], 0.0);
+        assert!(!is_geometry_compatible(&a, &b));
+        assert!(is_geometry_compatible(&a, &c));
+        let (a, b) = compatible_parts(&a, &b);
+        assert!(a.components.is_none() && b.components.is_none());
+    }
+
+    #[test]
+    fn blend_guide_angles() {
+        let a = layer("a", &[], 350.0);
+        let b = layer("b", &[], 10.0);
+        let blended = blend_layers(&[&a, &b], &[0.5, 0.5]).unwrap();
+        let angle = blended.guide_lines.unwrap()[0].angle.unwrap();
+        assert!(unwrap_angle(angle, 0.0).abs() < 1e-9, "{}", angle);
+    }
+}
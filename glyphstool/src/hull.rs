@@ -0,0 +1,102 @@
+//! The convex hull of sample locations.
+//!
+//! The nearest point of the hull is found as the combination of the
+//! locations with non-negative weights summing to 1 that best approximates
+//! the location, by non-negative least squares (Lawson and Hanson). This
+//! works for any number of axes without constructing the hull.
+
+use nalgebra::{DMatrix, DVector};
+
+/// The weight of the row enforcing that the weights sum to 1.
+const SUM_WEIGHT: f64 = 1e3;
+
+/// Tolerance for the least squares solutions.
+const EPSILON: f64 = 1e-12;
+
+/// Distances below which a location is in the hull.
+pub const HULL_TOLERANCE: f64 = 1e-6;
+
+/// Solve min |Ax - b| for x >= 0.
+fn nnls(a: &DMatrix<f64>, b: &DVector<f64>) -> DVector<f64> {
+    let m = a.ncols();
+    let mut x = DVector::zeros(m);
+    let mut passive = vec![false; m];
+    // Least squares on the passive columns, zero elsewhere.
+    let solve = |passive: &[bool]| -> DVector<f64> {
+        let cols: Vec<usize> = (0..m).filter(|&j| passive[j]).collect();
+        let mut s = DVector::zeros(m);
+        let sub = DMatrix::from_fn(a.nrows(), cols.len(), |i, k| a[(i, cols[k])]);
+        if let Ok(inverse) = sub.pseudo_inverse(EPSILON) {
+            let sol = inverse * b;
+            for (k, &j) in cols.iter().enumerate() {
+                s[j] = sol[k];
+            }
+        }
+        s
+    };
+    for _ in 0..3 * m {
+        let w = a.tr_mul(&(b - a * &x));
+        let next = (0..m)
+            .filter(|&j| !passive[j] && w[j] > EPSILON)
+            .max_by(|&i, &j| w[i].partial_cmp(&w[j]).unwrap());
+        let j = match next {
+            Some(j) => j,
+            None => break,
+        };
+        passive[j] = true;
+        let mut s = solve(&passive);
+        while (0..m).any(|i| passive[i] && s[i] <= 0.0) {
+            let alpha = (0..m)
+                .filter(|&i| passive[i] && s[i] <= 0.0)
+                .map(|i| x[i] / (x[i] - s[i]))
+                .fold(1.0, f64::min);
+            x += (&s - &x) * alpha;
+            for i in 0..m {
+                if passive[i] && x[i] <= EPSILON {
+                    passive[i] = false;
+                    x[i] = 0.0;
+                }
+            }
+            s = solve(&passive);
+        }
+        x = s;
+    }
+    x
+}
+
+/// The point of the convex hull of some locations nearest to a location.
+pub fn nearest_in_hull(locations: &[Vec<f64>], location: &[f64]) -> Vec<f64> {
+    let n = location.len();
+    let m = locations.len();
+    if m == 0 {
+        return location.to_owned();
+    }
+    let a = DMatrix::from_fn(
+        n + 1,
+        m,
+        |i, j| {
+            if i < n {
+                locations[j][i]
+            } else {
+                SUM_WEIGHT
+            }
+        },
+    );
+    let b = DVector::from_fn(n + 1, |i, _| if i < n { location[i] } else { SUM_WEIGHT });
+    let weights = nnls(&a, &b);
+    let sum = weights.sum();
+    (0..n)
+        .map(|i| (0..m).map(|j| weights[j] * locations[j][i]).sum::<f64>() / sum)
+        .collect()
+}
+
+/// Whether a location is outside the convex hull of some locations.
+pub fn is_outside(locations: &[Vec<f64>], location: &[f64]) -> bool {
+    let nearest = nearest_in_hull(locations, location);
+    let dist2: f64 = nearest
+        .iter()
+        .zip(location)
+        .map(|(a, b)| (a - b).powi(2))
+        .sum();
+    dist2.sqrt() > HULL_TOLERANCE
+}
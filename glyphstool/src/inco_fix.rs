@@ -8,7 +8,11 @@ use std::collections::HashMap;
 
 use kurbo::{Affine, Point, Rect};
 
-use glyphstool::{Component, Font, Glyph, Layer, Node, NodeType, Path, Region};
+use glyphstool::interp::{interpolate_layer, Method, Model};
+use glyphstool::{
+    blend_layers, compatible_parts, restore_smooth_path, Component, Font, Glyph, Layer, Node,
+    NodeType, Path, PointKind, Region,
+};
 
 #[derive(Default)]
 struct LayerMap {
@@ -35,50 +39,35 @@ fn affine_stretch(stretch: f64) -> Affine {
     Affine::new([stretch, 0., 0., 1., 0., 0.])
 }
 
-fn simple_lerp_path(path0: &Path, path1: &Path, t: f64) -> Path {
-    let nodes = path0
+fn stretch_path(path: &Path, a: Affine) -> Path {
+    let nodes = path
         .nodes
         .iter()
-        .zip(path1.nodes.iter())
-        .map(|(n0, n1)| Node {
-            pt: n0.pt.lerp(n1.pt, t),
-            node_type: n0.node_type,
+        .map(|n| Node {
+            pt: a * n.pt,
+            node_type: n.node_type,
         })
         .collect();
-    Path {
-        closed: path0.closed,
-        nodes,
-    }
-}
-
-fn fix_path(path0: &Path, path1: &Path, t: f64, a: Affine) -> Path {
-    let nodes = path0
-        .nodes
-        .iter()
-        .zip(path1.nodes.iter())
-        .map(|(n0, n1)| Node {
-            pt: (a * n0.pt.lerp(n1.pt, t)).round(),
-            node_type: n0.node_type,
-        })
-        .collect();
-    Path {
-        closed: path0.closed,
+    let mut path = Path {
+        closed: path.closed,
         nodes,
+    };
+    // Interpolation can put a kink at smooth nodes; remove it before
+    // rounding.
+    restore_smooth_path(&mut path);
+    for node in &mut path.nodes {
+        node.pt = node.pt.round();
     }
+    path
 }
 
 fn fix_glyph(glyph: &mut Glyph, layers: &LayerMap) {
-    let paths0 = glyph
-        .get_layer(layers.get_id(400, 100))
-        .unwrap()
-        .paths
-        .clone();
+    let layer0 = glyph.get_layer(layers.get_id(400, 100)).unwrap();
     // This is actually the 700 from the master, but is stored in 900.
-    let paths1 = glyph
-        .get_layer(layers.get_id(900, 100))
-        .unwrap()
-        .paths
-        .clone();
+    let layer1 = glyph.get_layer(layers.get_id(900, 100)).unwrap();
+    // Parts of the masters that don't line up (such as anchors that differ)
+    // are left as they are in each layer.
+    let (layer0, layer1) = compatible_parts(layer0, layer1);
     println!("processing glyph {}", glyph.glyphname);
     for layer in &mut glyph.layers {
         if let Some((wght, wdth)) = layers.get_params(&layer.layer_id) {
@@ -86,27 +75,31 @@ fn fix_glyph(glyph: &mut Glyph, layers: &LayerMap) {
             let stretch = wdth as f64 / 100.0;
             let a = affine_stretch(stretch);
             println!("  touching layer {}, t = {}", layer.layer_id, t);
-            if let Some(ref p0) = paths0 {
-                let paths = p0
-                    .iter()
-                    .zip(paths1.as_ref().unwrap().iter())
-                    .map(|(p0, p1)| fix_path(p0, p1, t, a))
-                    .collect();
-                layer.paths = Some(paths);
+            // Paths, anchors, component transforms and guides are lerped
+            // from the masters where they line up, then stretched.
+            let lerped = blend_layers(&[&layer0, &layer1], &[1.0 - t, t]).unwrap();
+            match lerped.paths {
+                Some(paths) => {
+                    layer.paths = Some(paths.iter().map(|p| stretch_path(p, a)).collect());
+                }
+                None => println!("  paths don't match, keeping those of the layer"),
             }
             layer.width = wdth as f64 * 5.0;
 
-            // Possibly TODO: lerp the affine from the masters, rather than
-            // doing the processing in-place. Not clear whether it makes much
-            // difference.
             let a_inv = affine_stretch(stretch.recip());
 
+            if lerped.anchors.is_some() {
+                layer.anchors = lerped.anchors;
+            }
             if let Some(ref mut anchors) = layer.anchors {
                 for anchor in anchors {
                     anchor.position = (a * anchor.position).round();
                 }
             }
 
+            if lerped.components.is_some() {
+                layer.components = lerped.components;
+            }
             if let Some(ref mut components) = layer.components {
                 for component in components {
                     if let Some(ref mut transform) = component.transform {
@@ -115,6 +108,17 @@ fn fix_glyph(glyph: &mut Glyph, layers: &LayerMap) {
                     }
                 }
             }
+
+            if let Some(mut guides) = lerped.guide_lines {
+                for guide in &mut guides {
+                    guide.position = (a * guide.position).round();
+                    if let Some(angle) = guide.angle {
+                        let angle = PointKind::GuideAngle.transform(a, Point::new(angle, 0.0));
+                        guide.angle = Some(angle.x);
+                    }
+                }
+                layer.guide_lines = Some(guides);
+            }
         }
     }
 }
@@ -141,7 +145,7 @@ pub fn inco_fix(font: &mut Font) {
     }
 }
 
-/// Scaling of small alphanumerics follows
+// Scaling of small alphanumerics follows
 
 const NUM_PAIRS: &[(&str, &str)] = &[
     ("zero", "zerosuperior"),
@@ -168,52 +172,29 @@ const FRACS: &[(&str, &str, &str)] = &[
 
 const CARONS: &[(&str, &str)] = &[("d", "dcaron")];
 
-fn lerp_layers(glyph: &Glyph, weight: f64, width: f64, a: Affine, layers: &LayerMap) -> Vec<Path> {
-    let (wt0, wt1, wtt) = if weight < 400.0 {
-        (200, 400, (weight - 200.0) / 200.0)
-    } else {
-        (400, 900, (weight - 400.0) / 500.0)
-    };
-    let (wd0, wd1, wdt) = if width < 100.0 {
-        (50, 100, (width - 50.0) / 50.0)
-    } else {
-        (100, 200, (width - 100.0) / 100.0)
-    };
-    let paths00 = glyph
-        .get_layer(layers.get_id(wt0, wd0))
-        .unwrap()
-        .paths
-        .clone();
-    let paths01 = glyph
-        .get_layer(layers.get_id(wt0, wd1))
-        .unwrap()
-        .paths
-        .clone();
-    let paths10 = glyph
-        .get_layer(layers.get_id(wt1, wd0))
-        .unwrap()
-        .paths
-        .clone();
-    let paths11 = glyph
-        .get_layer(layers.get_id(wt1, wd1))
-        .unwrap()
-        .paths
-        .clone();
-    if let Some(ref p0) = paths00 {
-        return p0
-            .iter()
-            .zip(paths10.as_ref().unwrap().iter())
-            .zip(paths01.as_ref().unwrap().iter())
-            .zip(paths11.as_ref().unwrap().iter())
-            .map(|(((p00, p10), p01), p11)| {
-                let p0 = simple_lerp_path(p00, p01, wdt);
-                let p1 = simple_lerp_path(p10, p11, wdt);
-                fix_path(&p0, &p1, wtt, a)
-            })
-            .collect();
+/// A linear model of the masters, with the regular as the default so that
+/// interpolation is piecewise bilinear over the grid of masters.
+fn get_model(font: &Font) -> Model {
+    let axes = font.axes();
+    let mut masters: Vec<(String, Vec<f64>)> = font
+        .font_master
+        .iter()
+        .map(|master| (master.id.clone(), master.location(&axes)))
+        .collect();
+    if let Some(i) = masters.iter().position(|(_, loc)| loc[..] == [400.0, 100.0]) {
+        let regular = masters.remove(i);
+        masters.insert(0, regular);
+    }
+    Model::from_masters(masters, Method::Linear)
+}
+
+fn lerp_layers(glyph: &Glyph, weight: f64, width: f64, a: Affine, model: &Model) -> Vec<Path> {
+    let layer = interpolate_layer(glyph, &[weight, width], model).unwrap();
+    match layer.paths {
+        Some(paths) => paths.iter().map(|p| stretch_path(p, a)).collect(),
+        // This shouldn't happen.
+        None => Vec::new(),
     }
-    // This shouldn't happen.
-    Vec::new()
 }
 
 fn add_ord_dash(paths: &mut Vec<Path>, wght: i64, wdth: i64) {
@@ -268,6 +249,7 @@ fn add_caron(layer: &mut Layer, wdth: i64) {
 
 pub fn inco_scale(font: &mut Font, subcmd: i32) {
     let layers = get_layer_map(font);
+    let model = get_model(font);
 
     // This is very cut'n'pasty, reflecting the development process. Obviously this
     // would be cleaned up for a reusable tool.
@@ -285,7 +267,7 @@ pub fn inco_scale(font: &mut Font, subcmd: i32) {
                         let dst_wdth = wdth as f64 * 1.1;
                         let x = (wdth as f64 - dst_wdth * 0.62) * 5.0 * 0.5;
                         let a = Affine::new([0.62, 0.0, 0.0, 0.62, x, 246.0]);
-                        let paths = lerp_layers(src_glyph, dst_wght, dst_wdth, a, &layers);
+                        let paths = lerp_layers(src_glyph, dst_wght, dst_wdth, a, &model);
                         layer.paths = Some(paths);
                     }
                 }
@@ -307,7 +289,7 @@ pub fn inco_scale(font: &mut Font, subcmd: i32) {
                         let dst_wdth = wdth as f64 * 0.95;
                         let x = (wdth as f64 - dst_wdth * 0.77) * 5.0 * 0.5;
                         let a = Affine::new([0.77, 0.0, 0.0, 0.77, x, 267.0]);
-                        let mut paths = lerp_layers(src_glyph, dst_wght, dst_wdth, a, &layers);
+                        let mut paths = lerp_layers(src_glyph, dst_wght, dst_wdth, a, &model);
                         add_ord_dash(&mut paths, wght, wdth);
                         layer.paths = Some(paths);
                     }
@@ -337,9 +319,9 @@ pub fn inco_scale(font: &mut Font, subcmd: i32) {
                             _ => 0.0,
                         };
                         let a = Affine::new([0.49, 0.0, 0.0, 0.49, x - dx, 380.0]);
-                        let mut paths = lerp_layers(num_glyph, dst_wght, dst_wdth, a, &layers);
+                        let mut paths = lerp_layers(num_glyph, dst_wght, dst_wdth, a, &model);
                         let a = Affine::new([0.49, 0.0, 0.0, 0.49, x + dx2, -70.0]);
-                        let denom_paths = lerp_layers(denom_glyph, dst_wght, dst_wdth, a, &layers);
+                        let denom_paths = lerp_layers(denom_glyph, dst_wght, dst_wdth, a, &model);
                         paths.extend(denom_paths);
                         layer.paths = Some(paths);
                         add_fraction_ref(layer);
@@ -363,7 +345,7 @@ pub fn inco_scale(font: &mut Font, subcmd: i32) {
                         let dst_wdth = wdth as f64 * 0.8;
                         let x = (wdth as f64 - dst_wdth * 1.0) * 5.0 * 0.0;
                         let a = Affine::new([1.0, 0.0, 0.0, 1.0, x, 0.0]);
-                        let paths = lerp_layers(src_glyph, dst_wght, dst_wdth, a, &layers);
+                        let paths = lerp_layers(src_glyph, dst_wght, dst_wdth, a, &model);
                         layer.paths = Some(paths);
                         add_caron(layer, wdth);
                         layer.anchors = None;
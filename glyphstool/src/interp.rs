@@ -0,0 +1,1088 @@
+//! Interpolation of glyphs anywhere in design space.
+//!
+//! A `Model` knows where the masters of a font are and how to interpolate
+//! between them: piecewise linearly, as a variable font does, or with
+//! radial basis functions. Either way, the interpolated layer is a weighted
+//! sum of the layers of the glyph that have a location (masters and brace
+//! layers), with weights that depend only on where those layers are, so
+//! the same weights apply to every node, anchor, component and guide.
+//!
+//! Underneath, `Model::interpolate_points` interpolates any points from
+//! their own samples, so points may use different methods, or have samples
+//! in different places, as in the interpolation toy.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use kurbo::Point;
+use nalgebra::{DMatrix, DVector};
+
+use crate::font::{Font, Glyph, Layer, NodeType};
+use crate::geometry::{
+    is_geometry_compatible, layer_geometry, set_layer_geometry, unwrap_angle, PointKind,
+};
+use crate::hull::{nearest_in_hull, HULL_TOLERANCE};
+use crate::polar;
+use crate::rigid;
+use crate::settings::{
+    recorded_coords, recorded_extrapolation, recorded_kernel, recorded_metric, recorded_overrides,
+    Kernel,
+};
+use crate::smooth::restore_smooth_path;
+use crate::varmodel::{normalize_value, VariationModel};
+
+/// A location in design space, one value per axis.
+pub type Location = [f64];
+
+/// Tolerance for the pseudo-inverse of the kernel matrix.
+const EPSILON: f64 = 1e-6;
+
+/// The step (in units of the range of the masters) over which the slope is
+/// measured for linear extrapolation.
+const SLOPE_STEP: f64 = 1e-3;
+
+/// A radial basis function.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Basis {
+    Gaussian,
+    ThinPlate,
+    Linear,
+    Multiquadric,
+    InverseMultiquadric,
+    /// The polyharmonic spline r^3.
+    Cubic,
+    /// Wendland's C2 function, which is zero beyond the radius.
+    Wendland,
+}
+
+impl Basis {
+    /// The name used on the command line and in the font.
+    pub fn name(self) -> &'static str {
+        match self {
+            Basis::Gaussian => "gaussian",
+            Basis::ThinPlate => "thin-plate",
+            Basis::Linear => "linear",
+            Basis::Multiquadric => "multiquadric",
+            Basis::InverseMultiquadric => "inverse-multiquadric",
+            Basis::Cubic => "cubic",
+            Basis::Wendland => "wendland",
+        }
+    }
+
+    /// The value of the function at distance `r`, for a kernel radius.
+    pub fn eval(self, radius: f64, r: f64) -> f64 {
+        let q = r / radius;
+        match self {
+            Basis::Gaussian => (-q * q).exp(),
+            Basis::ThinPlate => {
+                if r == 0.0 {
+                    0.0
+                } else {
+                    r * r * r.ln()
+                }
+            }
+            Basis::Linear => r,
+            Basis::Multiquadric => (1.0 + q * q).sqrt(),
+            Basis::InverseMultiquadric => 1.0 / (1.0 + q * q).sqrt(),
+            Basis::Cubic => r * r * r,
+            Basis::Wendland => {
+                if q >= 1.0 {
+                    0.0
+                } else {
+                    (1.0 - q).powi(4) * (4.0 * q + 1.0)
+                }
+            }
+        }
+    }
+}
+
+impl std::str::FromStr for Basis {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Basis, String> {
+        match s {
+            "gaussian" => Ok(Basis::Gaussian),
+            "thin-plate" => Ok(Basis::ThinPlate),
+            "linear" => Ok(Basis::Linear),
+            "multiquadric" => Ok(Basis::Multiquadric),
+            "inverse-multiquadric" => Ok(Basis::InverseMultiquadric),
+            "cubic" => Ok(Basis::Cubic),
+            "wendland" => Ok(Basis::Wendland),
+            _ => Err(format!("unknown basis {}", s)),
+        }
+    }
+}
+
+/// Parameters of an interpolation by radial basis functions.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RbfParams {
+    pub basis: Basis,
+    /// Kernel radius. Used by the gaussian, multiquadric, inverse
+    /// multiquadric and Wendland bases.
+    pub radius: f64,
+    /// Order of the polynomial term: 0 for none, 1 for a constant, 2 for
+    /// linear.
+    pub order: usize,
+    /// Regularization added to the diagonal of the kernel matrix. At 0 the
+    /// samples are interpolated exactly.
+    pub smoothing: f64,
+}
+
+/// How to interpolate between samples.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Method {
+    /// The OpenType variation model with the first sample as the default,
+    /// as a variable font interpolates. Locations are clamped to the range
+    /// of the samples along each axis.
+    Linear,
+    /// Radial basis functions.
+    Rbf(RbfParams),
+}
+
+impl Method {
+    /// The method as numbers, for comparing exactly.
+    fn key(self) -> Vec<u64> {
+        match self {
+            Method::Linear => vec![0],
+            Method::Rbf(params) => vec![
+                1,
+                params.basis as u64,
+                params.radius.to_bits(),
+                params.order as u64,
+                params.smoothing.to_bits(),
+            ],
+        }
+    }
+}
+
+/// The coordinates in which the nodes of contours are interpolated.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Coords {
+    /// Their positions, each in a straight line.
+    Cartesian,
+    /// The angle and distance of each node from the centroid of its
+    /// contour (or a handle from its node), so that rotations stay
+    /// rotations.
+    Polar,
+    /// The rigid motion of each contour and the residual deformation of
+    /// its nodes, so that a contour that rotates stays rigid.
+    Rigid,
+}
+
+impl Coords {
+    /// The name used on the command line and in the font.
+    pub fn name(self) -> &'static str {
+        match self {
+            Coords::Cartesian => "cartesian",
+            Coords::Polar => "polar",
+            Coords::Rigid => "rigid",
+        }
+    }
+}
+
+impl std::str::FromStr for Coords {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Coords, String> {
+        match s {
+            "cartesian" => Ok(Coords::Cartesian),
+            "polar" => Ok(Coords::Polar),
+            "rigid" => Ok(Coords::Rigid),
+            _ => Err(format!("unsupported coordinates {}", s)),
+        }
+    }
+}
+
+/// What to do at locations outside the convex hull of the samples of a
+/// point.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Extrapolation {
+    /// Evaluate the interpolation anyway.
+    Raw,
+    /// Stop at the nearest location of the hull.
+    Clamp,
+    /// Continue from the nearest location of the hull in a straight line.
+    Linear,
+}
+
+impl Extrapolation {
+    /// The name used on the command line and in the font.
+    pub fn name(self) -> &'static str {
+        match self {
+            Extrapolation::Raw => "raw",
+            Extrapolation::Clamp => "clamp",
+            Extrapolation::Linear => "linear",
+        }
+    }
+}
+
+impl std::str::FromStr for Extrapolation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Extrapolation, String> {
+        match s {
+            "raw" => Ok(Extrapolation::Raw),
+            "clamp" => Ok(Extrapolation::Clamp),
+            "linear" => Ok(Extrapolation::Linear),
+            _ => Err(format!("unknown extrapolation {}", s)),
+        }
+    }
+}
+
+/// The position of a point at a location where it was sampled.
+#[derive(Clone, Debug)]
+pub struct Sample {
+    pub pt: Point,
+    pub location: Vec<f64>,
+}
+
+/// How radial basis functions measure distances between locations, in
+/// units of the range of the masters.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Metric {
+    /// The factor by which offsets along each axis are scaled. Missing
+    /// values are 1.
+    pub scales: Vec<f64>,
+    /// A symmetric positive definite matrix M, so that the squared distance
+    /// for scaled offsets s is sᵀMs. `None` for the identity.
+    pub matrix: Option<Vec<Vec<f64>>>,
+}
+
+impl Metric {
+    /// The default metric for axes with the given (min, max) of the
+    /// masters: each is scaled by its length relative to the longest, so
+    /// that distances follow the proportions of the design space.
+    pub fn for_ranges(ranges: &[(f64, f64)]) -> Metric {
+        let lengths: Vec<f64> = ranges.iter().map(|&(min, max)| (max - min).abs()).collect();
+        let longest = lengths.iter().cloned().fold(0.0, f64::max);
+        let scales = lengths
+            .iter()
+            .map(|&length| if length > 0.0 { length / longest } else { 1.0 })
+            .collect();
+        Metric {
+            scales,
+            matrix: None,
+        }
+    }
+
+    /// The scale factor of an axis.
+    pub fn scale(&self, axis: usize) -> f64 {
+        self.scales.get(axis).copied().unwrap_or(1.0)
+    }
+
+    /// Set the scale factor of an axis.
+    pub fn set_scale(&mut self, axis: usize, scale: f64) {
+        if self.scales.len() <= axis {
+            self.scales.resize(axis + 1, 1.0);
+        }
+        self.scales[axis] = scale;
+    }
+
+    /// The linear map taking locations to a space where the metric is
+    /// Euclidean: Lᵀ S, where S scales each axis and L is the Cholesky
+    /// factor of the matrix. A matrix that isn't positive definite, or is
+    /// of the wrong size, is ignored.
+    fn transform(&self, n_axes: usize) -> DMatrix<f64> {
+        let scales = DVector::from_iterator(n_axes, (0..n_axes).map(|i| self.scale(i)));
+        let scales = DMatrix::from_diagonal(&scales);
+        let factor = self
+            .matrix
+            .as_ref()
+            .filter(|m| m.len() == n_axes && m.iter().all(|row| row.len() == n_axes))
+            .and_then(|m| DMatrix::from_fn(n_axes, n_axes, |i, j| m[i][j]).cholesky())
+            .map(|cholesky| cholesky.l().transpose());
+        match factor {
+            Some(factor) => factor * scales,
+            None => scales,
+        }
+    }
+}
+
+/// The weights of samples at some locations, for evaluation anywhere.
+pub struct Interpolator {
+    solver: Solver,
+}
+
+enum Solver {
+    /// Always the first sample.
+    First,
+    Rbf {
+        locations: Vec<Vec<f64>>,
+        params: RbfParams,
+        /// The rows of the (pseudo-)inverse of the kernel matrix that
+        /// correspond to the samples.
+        inverse: DMatrix<f64>,
+    },
+    Variation {
+        /// The (min, default, max) of each axis.
+        ranges: Vec<(f64, f64, f64)>,
+        model: VariationModel,
+        /// Column j is the deltas when sample j is 1 and the others 0.
+        deltas: DMatrix<f64>,
+    },
+}
+
+fn distance(a: &[f64], b: &[f64]) -> f64 {
+    a.iter()
+        .zip(b)
+        .map(|(x, y)| (x - y).powi(2))
+        .sum::<f64>()
+        .sqrt()
+}
+
+/// The polynomial terms of the given order at a location: a constant for
+/// order 1, plus the coordinates for order 2.
+fn poly(location: &[f64], order: usize) -> Vec<f64> {
+    match order {
+        0 => Vec::new(),
+        1 => vec![1.0],
+        _ => std::iter::once(1.0)
+            .chain(location.iter().copied())
+            .collect(),
+    }
+}
+
+fn normalize(location: &[f64], ranges: &[(f64, f64, f64)]) -> Vec<f64> {
+    location
+        .iter()
+        .zip(ranges)
+        .map(|(&value, &range)| normalize_value(value, range))
+        .collect()
+}
+
+impl Interpolator {
+    /// An interpolator for samples at the given locations. For the linear
+    /// method, samples at the same location leave only the first.
+    pub fn new(locations: &[Vec<f64>], method: Method) -> Result<Interpolator, String> {
+        let solver = match method {
+            _ if locations.is_empty() => Solver::First,
+            Method::Linear => Self::variation(locations)?,
+            Method::Rbf(params) => Self::rbf(locations, params)?,
+        };
+        Ok(Interpolator { solver })
+    }
+
+    fn rbf(locations: &[Vec<f64>], params: RbfParams) -> Result<Solver, String> {
+        let n = locations.len();
+        let n_aug = n + poly(&locations[0], params.order).len();
+        let mut mat = DMatrix::zeros(n_aug, n_aug);
+        for (i, loc) in locations.iter().enumerate() {
+            for (j, other) in locations.iter().enumerate() {
+                mat[(i, j)] = params.basis.eval(params.radius, distance(loc, other));
+            }
+            mat[(i, i)] += params.smoothing;
+            for (k, term) in poly(loc, params.order).into_iter().enumerate() {
+                mat[(i, n + k)] = term;
+                mat[(n + k, i)] = term;
+            }
+        }
+        // The pseudo-inverse gives a least squares fit when the system is
+        // singular, for example when all samples lie on a line.
+        let inverse = mat
+            .pseudo_inverse(EPSILON)
+            .map_err(|e| format!("can't solve the kernel system: {}", e))?;
+        Ok(Solver::Rbf {
+            locations: locations.to_owned(),
+            params,
+            inverse: inverse.rows(0, n).into_owned(),
+        })
+    }
+
+    fn variation(locations: &[Vec<f64>]) -> Result<Solver, String> {
+        let default = &locations[0];
+        let ranges: Vec<(f64, f64, f64)> = (0..default.len())
+            .map(|i| {
+                let values = locations.iter().map(|loc| loc[i]);
+                let min = values.clone().fold(default[i], f64::min);
+                let max = values.fold(default[i], f64::max);
+                (min, default[i], max)
+            })
+            .collect();
+        let normalized: Vec<Vec<f64>> = locations
+            .iter()
+            .map(|loc| normalize(loc, &ranges))
+            .collect();
+        // Coincident samples have no variation model, so the model is of the
+        // first sample at each location, and the others get no weight.
+        let mut unique: Vec<usize> = Vec::new();
+        for (i, loc) in normalized.iter().enumerate() {
+            if !unique.iter().any(|&j| normalized[j] == *loc) {
+                unique.push(i);
+            }
+        }
+        let unique_locations: Vec<Vec<f64>> =
+            unique.iter().map(|&i| normalized[i].clone()).collect();
+        let model = VariationModel::new(&unique_locations)?;
+        let mut deltas = DMatrix::zeros(unique.len(), locations.len());
+        for (k, &j) in unique.iter().enumerate() {
+            let mut unit = vec![0.0; unique.len()];
+            unit[k] = 1.0;
+            for (i, delta) in model.get_deltas(&unit).into_iter().enumerate() {
+                deltas[(i, j)] = delta;
+            }
+        }
+        Ok(Solver::Variation {
+            ranges,
+            model,
+            deltas,
+        })
+    }
+
+    /// The weight of each sample at a location. They sum to 1.
+    pub fn weights(&self, location: &Location) -> Vec<f64> {
+        match &self.solver {
+            Solver::First => vec![1.0],
+            Solver::Rbf {
+                locations,
+                params,
+                inverse,
+            } => {
+                let row = locations
+                    .iter()
+                    .map(|center| params.basis.eval(params.radius, distance(location, center)))
+                    .chain(poly(location, params.order));
+                let weights = inverse * DVector::from_iterator(inverse.ncols(), row);
+                weights.iter().copied().collect()
+            }
+            Solver::Variation {
+                ranges,
+                model,
+                deltas,
+            } => {
+                let loc = normalize(location, ranges);
+                let scalars = DVector::from_vec(model.get_scalars(&loc));
+                deltas.tr_mul(&scalars).iter().copied().collect()
+            }
+        }
+    }
+}
+
+/// What determines an interpolator: the method, the transform of the
+/// metric (for radial basis functions), and the sample locations in units
+/// of the range of the masters. Compared exactly.
+#[derive(Clone, PartialEq, Eq, Hash)]
+struct Key {
+    method: Vec<u64>,
+    transform: Vec<u64>,
+    locations: Vec<Vec<u64>>,
+}
+
+impl Key {
+    fn new(method: Method, transform: &DMatrix<f64>, locations: &[Vec<f64>]) -> Key {
+        let transform = match method {
+            Method::Rbf(_) => transform.iter().map(|x| x.to_bits()).collect(),
+            Method::Linear => Vec::new(),
+        };
+        let locations = locations
+            .iter()
+            .map(|loc| loc.iter().map(|x| x.to_bits()).collect())
+            .collect();
+        Key {
+            method: method.key(),
+            transform,
+            locations,
+        }
+    }
+}
+
+/// An interpolator, with the transform it applies to locations.
+struct Entry {
+    transform: Option<DMatrix<f64>>,
+    interpolator: Interpolator,
+}
+
+fn apply(transform: &DMatrix<f64>, location: &[f64]) -> Vec<f64> {
+    if transform.ncols() != location.len() {
+        return location.to_owned();
+    }
+    let v = DVector::from_iterator(location.len(), location.iter().copied());
+    (transform * v).iter().copied().collect()
+}
+
+impl Entry {
+    fn new(
+        locations: &[Vec<f64>],
+        method: Method,
+        transform: &DMatrix<f64>,
+    ) -> Result<Entry, String> {
+        let transform = match method {
+            Method::Rbf(_) => Some(transform.clone()),
+            Method::Linear => None,
+        };
+        let locations: Vec<Vec<f64>> = match &transform {
+            Some(transform) => locations.iter().map(|l| apply(transform, l)).collect(),
+            None => locations.to_owned(),
+        };
+        Ok(Entry {
+            transform,
+            interpolator: Interpolator::new(&locations, method)?,
+        })
+    }
+
+    fn weights(&self, location: &[f64]) -> DVector<f64> {
+        let weights = match &self.transform {
+            Some(transform) => self.interpolator.weights(&apply(transform, location)),
+            None => self.interpolator.weights(location),
+        };
+        DVector::from_vec(weights)
+    }
+
+    /// The weights at a location, following the extrapolation policy
+    /// outside the hull of the sample locations.
+    fn extrapolated_weights(
+        &self,
+        locations: &[Vec<f64>],
+        location: &[f64],
+        extrapolation: Extrapolation,
+    ) -> DVector<f64> {
+        if extrapolation == Extrapolation::Raw || locations.is_empty() {
+            return self.weights(location);
+        }
+        let nearest = nearest_in_hull(locations, location);
+        let dist = distance(&nearest, location);
+        if dist <= HULL_TOLERANCE {
+            return self.weights(location);
+        }
+        let at_hull = self.weights(&nearest);
+        match extrapolation {
+            Extrapolation::Linear => {
+                // The difference over a small step back into the hull.
+                let inside: Vec<f64> = nearest
+                    .iter()
+                    .zip(location)
+                    .map(|(&p, &x)| p - (x - p) * SLOPE_STEP / dist)
+                    .collect();
+                let slope = (&at_hull - self.weights(&inside)) * (1.0 / SLOPE_STEP);
+                at_hull + slope * dist
+            }
+            _ => at_hull,
+        }
+    }
+}
+
+/// Sum the samples of a point with the given weights.
+fn weighted_sum(samples: &[Sample], weights: &DVector<f64>) -> Point {
+    let (mut x, mut y) = (0.0, 0.0);
+    for (sample, &w) in samples.iter().zip(weights.iter()) {
+        x += w * sample.pt.x;
+        y += w * sample.pt.y;
+    }
+    Point::new(x, y)
+}
+
+/// The masters of a font, and how to interpolate between them.
+///
+/// Sample locations are measured in units of the range of the masters
+/// along each axis, so an RBF radius of 1 spans them. Interpolators are
+/// cached, and the cache is shared between clones; the ones that weren't
+/// used by the last evaluation are dropped.
+#[derive(Clone)]
+pub struct Model {
+    method: Method,
+    /// The id and design space location of each master.
+    masters: Vec<(String, Vec<f64>)>,
+    /// The (min, max) of the masters along each axis.
+    ranges: Vec<(f64, f64)>,
+    metric: Metric,
+    extrapolation: Extrapolation,
+    cache: Arc<Mutex<HashMap<Key, Arc<Entry>>>>,
+}
+
+impl Default for Model {
+    /// A model without masters, where locations are used as they are.
+    fn default() -> Model {
+        Model::from_masters(Vec::new(), Method::Linear)
+    }
+}
+
+impl Model {
+    /// A model of the masters of a font, with the metric recorded for it
+    /// if any.
+    pub fn new(font: &Font, method: Method) -> Model {
+        let axes = font.axes();
+        let masters = font
+            .font_master
+            .iter()
+            .map(|m| (m.id.clone(), m.location(&axes)))
+            .collect();
+        let mut model = Model::from_masters(masters, method);
+        if let Some(metric) = recorded_metric(font) {
+            if !metric.scales.is_empty() {
+                model.metric.scales = metric.scales;
+            }
+            model.metric.matrix = metric.matrix;
+        }
+        model
+    }
+
+    /// A model of masters given by layer id and design space location. The
+    /// first is the default.
+    pub fn from_masters(masters: Vec<(String, Vec<f64>)>, method: Method) -> Model {
+        let n_axes = masters.first().map_or(0, |(_, loc)| loc.len());
+        let ranges = (0..n_axes)
+            .map(|i| {
+                let mut values = masters.iter().map(|(_, loc)| loc[i]);
+                let first = values.next().unwrap_or(0.0);
+                values.fold((first, first), |(min, max), x| (min.min(x), max.max(x)))
+            })
+            .collect::<Vec<_>>();
+        Model {
+            method,
+            masters,
+            metric: Metric::for_ranges(&ranges),
+            ranges,
+            extrapolation: Extrapolation::Raw,
+            cache: Default::default(),
+        }
+    }
+
+    /// The model with distances for radial basis functions measured by
+    /// another metric.
+    pub fn with_metric(mut self, metric: Metric) -> Model {
+        self.metric = metric;
+        self
+    }
+
+    /// The model with another policy outside the hull of the samples.
+    pub fn with_extrapolation(mut self, extrapolation: Extrapolation) -> Model {
+        self.extrapolation = extrapolation;
+        self
+    }
+
+    pub fn method(&self) -> Method {
+        self.method
+    }
+
+    pub fn metric(&self) -> &Metric {
+        &self.metric
+    }
+
+    /// The design space location of a layer, if it is a master or a brace
+    /// layer.
+    pub fn layer_location(&self, layer: &Layer) -> Option<Vec<f64>> {
+        layer.location(self.ranges.len(), |id| {
+            self.masters
+                .iter()
+                .find(|(master_id, _)| master_id == id)
+                .map(|(_, loc)| loc.clone())
+        })
+    }
+
+    /// A design space location in units of the range of the masters.
+    /// Coordinates along axes the model doesn't know are used as they are.
+    fn to_unit(&self, location: &Location) -> Vec<f64> {
+        location
+            .iter()
+            .enumerate()
+            .map(|(i, &x)| match self.ranges.get(i) {
+                Some(&(min, max)) if max > min => (x - min) / (max - min),
+                Some(_) => 0.0,
+                None => x,
+            })
+            .collect()
+    }
+
+    /// Interpolate points at a design space location, each from its own
+    /// samples, with the method of the point with each index given by
+    /// `method`. Points with samples at the same locations and the same
+    /// method share an interpolator. It is an error if an interpolator
+    /// can't be built.
+    pub fn interpolate_points(
+        &self,
+        pts: &[Vec<Sample>],
+        method: impl Fn(usize) -> Method,
+        location: &Location,
+    ) -> Result<Vec<Point>, String> {
+        let location = self.to_unit(location);
+        let transform = self.metric.transform(location.len());
+        let mut cache = self.cache.lock().unwrap();
+        let mut old = std::mem::take(&mut *cache);
+        let mut weights: HashMap<Key, DVector<f64>> = HashMap::new();
+        pts.iter()
+            .enumerate()
+            .map(|(i, samples)| {
+                let method = method(i);
+                let locations: Vec<Vec<f64>> =
+                    samples.iter().map(|s| self.to_unit(&s.location)).collect();
+                let key = Key::new(method, &transform, &locations);
+                if !weights.contains_key(&key) {
+                    let entry = match old.remove(&key) {
+                        Some(entry) => entry,
+                        None => Arc::new(Entry::new(&locations, method, &transform)?),
+                    };
+                    let w = entry.extrapolated_weights(&locations, &location, self.extrapolation);
+                    weights.insert(key.clone(), w);
+                    cache.insert(key.clone(), entry);
+                }
+                Ok(weighted_sum(samples, &weights[&key]))
+            })
+            .collect()
+    }
+
+    /// Interpolate points as `interpolate_points` does, in the given
+    /// coordinates. The first points are the nodes of contours with the
+    /// given node types, and any others are interpolated directly.
+    pub fn interpolate_coords(
+        &self,
+        pts: &[Vec<Sample>],
+        structure: &[Vec<NodeType>],
+        coords: Coords,
+        method: impl Fn(usize) -> Method,
+        location: &Location,
+    ) -> Result<Vec<Point>, String> {
+        // Channels added for a contour use the method of the point that
+        // owns them.
+        let interp = |pts: &[Vec<Sample>], owners: &[usize]| {
+            self.interpolate_points(pts, |i| method(owners[i]), location)
+        };
+        match coords {
+            Coords::Cartesian => self.interpolate_points(pts, &method, location),
+            Coords::Polar => polar::eval(pts, structure, interp),
+            Coords::Rigid => rigid::eval(pts, structure, interp),
+        }
+    }
+}
+
+/// Interpolate a glyph at a design space location.
+///
+/// The samples are the layers with a location: the default master's layer
+/// (or the first such layer), then the rest. The result is a copy of the
+/// first with all of its geometry interpolated, and the neighbours of
+/// smooth nodes made collinear again. It is an error if the glyph has no
+/// such layers, or if any of them is incompatible with the first.
+///
+/// The method and extrapolation recorded for the glyph replace those of the
+/// model, and nodes with a recorded override use its method instead. The
+/// nodes are interpolated in the coordinates recorded for the glyph; it is
+/// an error if those aren't supported.
+pub fn interpolate_layer(
+    glyph: &Glyph,
+    location: &Location,
+    model: &Model,
+) -> Result<Layer, String> {
+    let mut samples: Vec<(&Layer, Vec<f64>)> = glyph
+        .layers
+        .iter()
+        .filter_map(|layer| Some((layer, model.layer_location(layer)?)))
+        .collect();
+    let default_id = model.masters.first().map(|(id, _)| id.as_str());
+    if let Some(i) = samples
+        .iter()
+        .position(|(layer, _)| Some(layer.layer_id.as_str()) == default_id)
+    {
+        let default = samples.remove(i);
+        samples.insert(0, default);
+    }
+    let template = match samples.first() {
+        Some(&(layer, _)) => layer,
+        None => return Err(format!("glyph {} has no master layers", glyph.glyphname)),
+    };
+    let incompatible: Vec<&str> = samples
+        .iter()
+        .filter(|(layer, _)| !is_geometry_compatible(template, layer))
+        .map(|(layer, _)| layer.layer_id.as_str())
+        .collect();
+    if !incompatible.is_empty() {
+        return Err(format!(
+            "glyph {}: layers {} are incompatible with {}",
+            glyph.glyphname,
+            incompatible.join(", "),
+            template.layer_id
+        ));
+    }
+    // Guide angles turn the short way from those of the template.
+    let reference = layer_geometry(template);
+    let geometries: Vec<Vec<Point>> = samples
+        .iter()
+        .map(|(layer, _)| {
+            layer_geometry(layer)
+                .into_iter()
+                .zip(&reference)
+                .map(|((kind, pt), (_, r))| match kind {
+                    PointKind::GuideAngle => Point::new(unwrap_angle(pt.x, r.x), pt.y),
+                    _ => pt,
+                })
+                .collect()
+        })
+        .collect();
+    let pts: Vec<Vec<Sample>> = (0..geometries[0].len())
+        .map(|i| {
+            samples
+                .iter()
+                .zip(&geometries)
+                .map(|((_, location), geometry)| Sample {
+                    pt: geometry[i],
+                    location: location.clone(),
+                })
+                .collect()
+        })
+        .collect();
+    let glyph_method = recorded_kernel(glyph).map_or(model.method, Kernel::method);
+    let structure: Vec<Vec<NodeType>> = template
+        .paths
+        .iter()
+        .flatten()
+        .map(|path| path.nodes.iter().map(|node| node.node_type).collect())
+        .collect();
+    let overrides = recorded_overrides(glyph, &structure);
+    let method = |i: usize| match overrides.get(i) {
+        Some(Some(kernel)) => kernel.method(),
+        _ => glyph_method,
+    };
+    let error = |e| format!("glyph {}: {}", glyph.glyphname, e);
+    let coords = recorded_coords(glyph).map_err(error)?;
+    let coords = coords.unwrap_or(Coords::Cartesian);
+    let result = match recorded_extrapolation(glyph) {
+        Some(extrapolation) => model
+            .clone()
+            .with_extrapolation(extrapolation)
+            .interpolate_coords(&pts, &structure, coords, method, location),
+        None => model.interpolate_coords(&pts, &structure, coords, method, location),
+    }
+    .map_err(error)?;
+    let mut layer = template.clone();
+    set_layer_geometry(&mut layer, &result);
+    // A layer at the location of a master is left as drawn there.
+    if !samples
+        .iter()
+        .any(|(_, sample)| sample.as_slice() == location)
+    {
+        for path in layer.paths.iter_mut().flatten() {
+            restore_smooth_path(path);
+        }
+    }
+    Ok(layer)
+}
+
+/// Turn a font into a single master instance at a design space location:
+/// each glyph is interpolated, and the default master moved there.
+pub fn interpolate_font(font: &mut Font, location: &Location, model: &Model) -> Result<(), String> {
+    let master_id = match font.font_master.first() {
+        Some(master) => master.id.clone(),
+        None => return Err("font has no masters".to_string()),
+    };
+    for glyph in &mut font.glyphs {
+        if glyph.layers.is_empty() {
+            continue;
+        }
+        let mut layer = interpolate_layer(glyph, location, model)?;
+        layer.layer_id = master_id.clone();
+        layer.other_stuff.remove("name");
+        layer.other_stuff.remove("associatedMasterId");
+        glyph.layers = vec![layer];
+    }
+    let axes = font.axes();
+    font.font_master.truncate(1);
+    font.font_master[0].set_location(&axes, location);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::font::Path;
+    use crate::settings::{record_coords, record_kernel, record_overrides, user_data_dict};
+
+    fn layer(layer_id: &str, n_nodes: usize, width: f64) -> Layer {
+        let mut path = Path::new(true);
+        for i in 0..n_nodes {
+            path.add((i as f64 * 100.0, 0.0), NodeType::Line);
+        }
+        Layer {
+            layer_id: layer_id.to_string(),
+            width,
+            paths: Some(vec![path]),
+            components: None,
+            anchors: None,
+            guide_lines: None,
+            other_stuff: HashMap::new(),
+        }
+    }
+
+    fn glyph(layers: Vec<Layer>) -> Glyph {
+        Glyph {
+            layers,
+            glyphname: "a".to_string(),
+            other_stuff: HashMap::new(),
+        }
+    }
+
+    fn model() -> Model {
+        let masters = vec![
+            ("light".to_string(), vec![400.0]),
+            ("bold".to_string(), vec![700.0]),
+        ];
+        Model::from_masters(masters, Method::Linear)
+    }
+
+    #[test]
+    fn interpolate_between_masters() {
+        let glyph = glyph(vec![layer("light", 3, 500.0), layer("bold", 3, 600.0)]);
+        let layer = interpolate_layer(&glyph, &[550.0], &model()).unwrap();
+        assert_eq!(layer.layer_id, "light");
+        assert_eq!(layer.width, 550.0);
+    }
+
+    #[test]
+    fn interpolate_incompatible() {
+        let glyph = glyph(vec![layer("light", 3, 500.0), layer("bold", 4, 600.0)]);
+        let err = interpolate_layer(&glyph, &[550.0], &model()).unwrap_err();
+        assert!(err.contains("bold"), "{}", err);
+    }
+
+    #[test]
+    fn interpolate_without_layers() {
+        assert!(interpolate_layer(&glyph(Vec::new()), &[550.0], &model()).is_err());
+    }
+
+    #[test]
+    fn interpolate_at_master_keeps_smooth_nodes() {
+        // The smooth node isn't quite in line with its neighbours in the
+        // bold master, which is kept as drawn.
+        let mut light = layer("light", 3, 500.0);
+        let mut bold = layer("bold", 3, 600.0);
+        for layer in &mut [&mut light, &mut bold] {
+            layer.paths.as_mut().unwrap()[0].nodes[1].node_type = NodeType::LineSmooth;
+        }
+        bold.paths.as_mut().unwrap()[0].nodes[1].pt.y = 10.0;
+        let glyph = glyph(vec![light, bold]);
+        let layer = interpolate_layer(&glyph, &[700.0], &model()).unwrap();
+        assert_eq!(layer.paths.unwrap()[0].nodes[1].pt.y, 10.0);
+        let layer = interpolate_layer(&glyph, &[550.0], &model()).unwrap();
+        assert_ne!(layer.paths.unwrap()[0].nodes[1].pt.y, 5.0);
+    }
+
+    #[test]
+    fn interpolate_duplicate_masters() {
+        // The second bold master is at the same location, so it is ignored.
+        let masters = vec![
+            ("light".to_string(), vec![400.0]),
+            ("bold".to_string(), vec![700.0]),
+            ("bold2".to_string(), vec![700.0]),
+        ];
+        let model = Model::from_masters(masters, Method::Linear);
+        let layers = vec![
+            layer("light", 3, 500.0),
+            layer("bold", 3, 600.0),
+            layer("bold2", 3, 900.0),
+        ];
+        let layer = interpolate_layer(&glyph(layers), &[550.0], &model).unwrap();
+        assert_eq!(layer.width, 550.0);
+    }
+
+    #[test]
+    fn interpolate_recorded_settings() {
+        // Both nodes move by 80 units to the middle master and 20 beyond.
+        let layers = [("light", 0.0), ("regular", 80.0), ("bold", 100.0)]
+            .iter()
+            .map(|&(id, x)| {
+                let mut layer = layer(id, 2, 500.0);
+                for node in &mut layer.paths.as_mut().unwrap()[0].nodes {
+                    node.pt.x = x;
+                }
+                layer
+            })
+            .collect();
+        let mut glyph = glyph(layers);
+        let thin_plate = Kernel {
+            basis: Some(Basis::ThinPlate),
+            ..Kernel::default()
+        };
+        let variation = Kernel {
+            basis: None,
+            ..Kernel::default()
+        };
+        let structure = vec![vec![NodeType::Line; 2]];
+        record_kernel(&mut glyph, thin_plate);
+        record_overrides(&mut glyph, &[Some(variation), None], &structure);
+        assert_eq!(
+            recorded_overrides(&glyph, &structure),
+            vec![Some(variation), None]
+        );
+        let masters = vec![
+            ("light".to_string(), vec![400.0]),
+            ("regular".to_string(), vec![550.0]),
+            ("bold".to_string(), vec![700.0]),
+        ];
+        let model = Model::from_masters(masters, Method::Linear);
+        let layer = interpolate_layer(&glyph, &[475.0], &model).unwrap();
+        let nodes = &layer.paths.unwrap()[0].nodes;
+        assert!((nodes[0].pt.x - 40.0).abs() < 1e-9, "{:?}", nodes[0].pt);
+        assert!((nodes[1].pt.x - 40.0).abs() > 1.0, "{:?}", nodes[1].pt);
+    }
+
+    /// A square about the origin, rotated by 90 degrees in the bold master.
+    fn rotating_square() -> Glyph {
+        let corners = [
+            (100.0, 100.0),
+            (-100.0, 100.0),
+            (-100.0, -100.0),
+            (100.0, -100.0),
+        ];
+        let square = |id: &str, rotate: bool| {
+            let mut layer = layer(id, 0, 500.0);
+            let path = &mut layer.paths.as_mut().unwrap()[0];
+            for &(x, y) in &corners {
+                let pt = if rotate { (-y, x) } else { (x, y) };
+                path.add(pt, NodeType::Line);
+            }
+            layer
+        };
+        glyph(vec![square("light", false), square("bold", true)])
+    }
+
+    #[test]
+    fn interpolate_recorded_coords() {
+        let mut glyph = rotating_square();
+        let layer = interpolate_layer(&glyph, &[550.0], &model()).unwrap();
+        let pt = layer.paths.unwrap()[0].nodes[0].pt;
+        assert!((pt.to_vec2().hypot() - 100.0).abs() < 1e-9, "{:?}", pt);
+        // In polar and rigid coordinates the square rotates instead of
+        // shrinking.
+        for &coords in &[Coords::Polar, Coords::Rigid] {
+            record_coords(&mut glyph, coords);
+            let layer = interpolate_layer(&glyph, &[550.0], &model()).unwrap();
+            let pt = layer.paths.unwrap()[0].nodes[0].pt;
+            let radius = pt.to_vec2().hypot();
+            assert!((radius - 200f64.sqrt() * 10.0).abs() < 1e-9, "{:?}", pt);
+        }
+    }
+
+    #[test]
+    fn interpolate_unsupported_coords() {
+        let mut glyph = rotating_square();
+        let dict = user_data_dict(&mut glyph.other_stuff).unwrap();
+        dict.insert("coords".to_string(), "skewed".to_string().into());
+        let err = interpolate_layer(&glyph, &[550.0], &model()).unwrap_err();
+        assert!(err.contains("skewed"), "{}", err);
+    }
+
+    #[test]
+    fn interpolate_points_extrapolation() {
+        let samples = [
+            Sample {
+                pt: Point::new(0.0, 0.0),
+                location: vec![400.0],
+            },
+            Sample {
+                pt: Point::new(100.0, 0.0),
+                location: vec![700.0],
+            },
+        ];
+        let pts = &[samples.to_vec()];
+        let rbf = Method::Rbf(RbfParams {
+            basis: Basis::ThinPlate,
+            radius: 1.0,
+            order: 2,
+            smoothing: 0.0,
+        });
+        let model = model().with_extrapolation(Extrapolation::Clamp);
+        let clamped = model.interpolate_points(pts, |_| rbf, &[1000.0]).unwrap();
+        assert!((clamped[0].x - 100.0).abs() < 1e-6, "{:?}", clamped);
+        let model = model.with_extrapolation(Extrapolation::Linear);
+        let linear = model.interpolate_points(pts, |_| rbf, &[1000.0]).unwrap();
+        assert!((linear[0].x - 200.0).abs() < 1e-3, "{:?}", linear);
+    }
+}
@@ -0,0 +1,315 @@
+//! Interpolation of untouched points (IUP), as used by gvar.
+//!
+//! Deltas for points that are not explicitly listed in a gvar tuple are
+//! inferred from their neighbors on the contour. This is a port of
+//! fontTools.varLib.iup, covering both inference and the optimization that
+//! drops deltas that can be inferred within a tolerance.
+
+use kurbo::{Point, Vec2};
+
+/// The maximum number of points the optimizer looks back over.
+const MAX_LOOKBACK: usize = 8;
+
+fn coord(p: Point, j: usize) -> f64 {
+    if j == 0 {
+        p.x
+    } else {
+        p.y
+    }
+}
+
+fn delta_coord(d: Vec2, j: usize) -> f64 {
+    if j == 0 {
+        d.x
+    } else {
+        d.y
+    }
+}
+
+/// Interpolate deltas for `coords`, which lie between two reference points.
+fn iup_segment(coords: &[Point], rc1: Point, rd1: Vec2, rc2: Point, rd2: Vec2) -> Vec<Vec2> {
+    let mut out = vec![Vec2::new(0.0, 0.0); coords.len()];
+    for j in 0..2 {
+        let (mut x1, mut x2) = (coord(rc1, j), coord(rc2, j));
+        let (mut d1, mut d2) = (delta_coord(rd1, j), delta_coord(rd2, j));
+        let values: Vec<f64> = if x1 == x2 {
+            let d = if d1 == d2 { d1 } else { 0.0 };
+            vec![d; coords.len()]
+        } else {
+            if x1 > x2 {
+                std::mem::swap(&mut x1, &mut x2);
+                std::mem::swap(&mut d1, &mut d2);
+            }
+            let scale = (d2 - d1) / (x2 - x1);
+            coords
+                .iter()
+                .map(|&p| {
+                    let x = coord(p, j);
+                    if x <= x1 {
+                        d1
+                    } else if x >= x2 {
+                        d2
+                    } else {
+                        d1 + (x - x1) * scale
+                    }
+                })
+                .collect()
+        };
+        for (o, v) in out.iter_mut().zip(values) {
+            if j == 0 {
+                o.x = v;
+            } else {
+                o.y = v;
+            }
+        }
+    }
+    out
+}
+
+/// Infer the missing deltas of one contour.
+fn iup_contour(deltas: &[Option<Vec2>], coords: &[Point]) -> Vec<Vec2> {
+    let n = deltas.len();
+    let indices: Vec<usize> = (0..n).filter(|&i| deltas[i].is_some()).collect();
+    if indices.is_empty() {
+        return vec![Vec2::new(0.0, 0.0); n];
+    }
+    let mut out: Vec<Vec2> = deltas
+        .iter()
+        .map(|d| d.unwrap_or_else(|| Vec2::new(0.0, 0.0)))
+        .collect();
+    if indices.len() == 1 {
+        let d = out[indices[0]];
+        return vec![d; n];
+    }
+    for k in 0..indices.len() {
+        let i = indices[k];
+        let next = indices[(k + 1) % indices.len()];
+        let span: Vec<usize> = if next > i {
+            (i + 1..next).collect()
+        } else {
+            (i + 1..n).chain(0..next).collect()
+        };
+        if span.is_empty() {
+            continue;
+        }
+        let pts: Vec<Point> = span.iter().map(|&s| coords[s]).collect();
+        let interp = iup_segment(&pts, coords[i], out[i], coords[next], out[next]);
+        for (&s, d) in span.iter().zip(interp) {
+            out[s] = d;
+        }
+    }
+    out
+}
+
+/// Infer missing deltas for a whole glyph. `ends` holds the index of the
+/// last point of each contour.
+pub fn iup_delta(deltas: &[Option<Vec2>], coords: &[Point], ends: &[usize]) -> Vec<Vec2> {
+    let mut out = Vec::with_capacity(deltas.len());
+    let mut start = 0;
+    for &end in ends {
+        out.extend(iup_contour(&deltas[start..=end], &coords[start..=end]));
+        start = end + 1;
+    }
+    out
+}
+
+/// Whether the deltas strictly between points `i` and `j` can be inferred
+/// from those two. As in fontTools, `i` may be -1, meaning the last point.
+fn can_iup_in_between(
+    deltas: &[Vec2],
+    coords: &[Point],
+    i: isize,
+    j: usize,
+    tolerance: f64,
+) -> bool {
+    let (start, ref_ix) = if i < 0 {
+        (0, deltas.len() - 1)
+    } else {
+        (i as usize + 1, i as usize)
+    };
+    let interp = iup_segment(
+        &coords[start..j],
+        coords[ref_ix],
+        deltas[ref_ix],
+        coords[j],
+        deltas[j],
+    );
+    deltas[start..j]
+        .iter()
+        .zip(interp)
+        .all(|(&d, p)| (d - p).hypot() <= tolerance)
+}
+
+/// The points that can't be inferred from their neighbors, whatever else
+/// is encoded.
+fn forced_set(deltas: &[Vec2], coords: &[Point], tolerance: f64) -> Vec<bool> {
+    let n = deltas.len();
+    let mut forced = vec![false; n];
+    for i in 0..n {
+        let prev = (i + n - 1) % n;
+        let next = (i + 1) % n;
+        for j in 0..2 {
+            let cj = coord(coords[i], j);
+            let dj = delta_coord(deltas[i], j);
+            let (lcj, ldj) = (coord(coords[prev], j), delta_coord(deltas[prev], j));
+            let (ncj, ndj) = (coord(coords[next], j), delta_coord(deltas[next], j));
+            let (c1, c2, d1, d2) = if lcj <= ncj {
+                (lcj, ncj, ldj, ndj)
+            } else {
+                (ncj, lcj, ndj, ldj)
+            };
+            let force = if c1 == c2 {
+                (d1 - d2).abs() > tolerance && dj.abs() > tolerance
+            } else if c1 <= cj && cj <= c2 {
+                !(d1.min(d2) - tolerance <= dj && dj <= d1.max(d2) + tolerance)
+            } else if d1 != d2 {
+                if cj < c1 {
+                    dj.abs() > tolerance
+                        && (dj - d1).abs() > tolerance
+                        && ((dj - tolerance < d1) != (d1 < d2))
+                } else {
+                    dj.abs() > tolerance
+                        && (dj - d2).abs() > tolerance
+                        && ((d2 < dj + tolerance) != (d1 < d2))
+                }
+            } else {
+                false
+            };
+            if force {
+                forced[i] = true;
+                break;
+            }
+        }
+    }
+    forced
+}
+
+/// Dynamic programming over a linear sequence; `chain[i + 1]` holds the
+/// previous encoded point (plus one, zero meaning none) in the best
+/// solution that encodes point `i`.
+fn optimize_dp(
+    deltas: &[Vec2],
+    coords: &[Point],
+    forced: &[bool],
+    tolerance: f64,
+    lookback: usize,
+) -> (Vec<usize>, Vec<usize>) {
+    let n = deltas.len();
+    let lookback = lookback.min(MAX_LOOKBACK);
+    // Indices are shifted by one so that index 0 stands for "before the
+    // start".
+    let mut costs = vec![0usize; n + 1];
+    let mut chain = vec![0usize; n + 1];
+    for i in 0..n {
+        let mut best_cost = costs[i] + 1;
+        costs[i + 1] = best_cost;
+        chain[i + 1] = i;
+        if i > 0 && forced[(i - 1) % forced.len()] {
+            continue;
+        }
+        let mut j = i as isize - 2;
+        while j > i as isize - lookback as isize && j >= -1 {
+            let cost = costs[(j + 1) as usize] + 1;
+            if cost < best_cost && can_iup_in_between(deltas, coords, j, i, tolerance) {
+                best_cost = cost;
+                costs[i + 1] = cost;
+                chain[i + 1] = (j + 1) as usize;
+            }
+            if j >= 0 && forced[j as usize % forced.len()] {
+                break;
+            }
+            j -= 1;
+        }
+    }
+    (chain, costs)
+}
+
+fn rotate<T: Clone>(v: &[T], k: usize) -> Vec<T> {
+    let n = v.len();
+    let k = k % n;
+    v[n - k..].iter().chain(&v[..n - k]).cloned().collect()
+}
+
+/// Choose which deltas of one contour to encode.
+fn iup_contour_optimize(deltas: &[Vec2], coords: &[Point], tolerance: f64) -> Vec<Option<Vec2>> {
+    let n = deltas.len();
+    if deltas.iter().all(|d| d.hypot() <= tolerance) {
+        return vec![None; n];
+    }
+    if n == 1 {
+        return vec![Some(deltas[0])];
+    }
+    let d0 = deltas[0];
+    if deltas.iter().all(|&d| d == d0) {
+        let mut result = vec![None; n];
+        result[0] = Some(d0);
+        return result;
+    }
+    let forced = forced_set(deltas, coords, tolerance);
+    if let Some(last_forced) = (0..n).rev().find(|&i| forced[i]) {
+        // Rotate so that the last point is forced, then solve the linear
+        // problem, which always encodes the last point.
+        let k = n - 1 - last_forced;
+        let deltas = rotate(deltas, k);
+        let coords = rotate(coords, k);
+        let forced = rotate(&forced, k);
+        let (chain, _) = optimize_dp(&deltas, &coords, &forced, tolerance, n);
+        let mut solution = vec![false; n];
+        let mut i = n;
+        while i > 0 {
+            solution[i - 1] = true;
+            i = chain[i];
+        }
+        let result: Vec<Option<Vec2>> = (0..n)
+            .map(|i| if solution[i] { Some(deltas[i]) } else { None })
+            .collect();
+        rotate(&result, n - k)
+    } else {
+        // Repeat the contour, solve the linear problem, and look for the
+        // best circular solution within it.
+        let deltas2: Vec<Vec2> = deltas.iter().chain(deltas).copied().collect();
+        let coords2: Vec<Point> = coords.iter().chain(coords).copied().collect();
+        let (chain, costs) = optimize_dp(&deltas2, &coords2, &forced, tolerance, n);
+        let mut best: Option<(Vec<bool>, usize)> = None;
+        for start in n - 1..2 * n - 1 {
+            let mut solution = vec![false; n];
+            let mut i = start as isize;
+            while i > start as isize - n as isize {
+                solution[i as usize % n] = true;
+                i = chain[i as usize + 1] as isize - 1;
+            }
+            if i == start as isize - n as isize {
+                let cost = costs[start + 1] - costs[start + 1 - n];
+                if best.as_ref().map(|b| cost <= b.1).unwrap_or(true) {
+                    best = Some((solution, cost));
+                }
+            }
+        }
+        let solution = best.map(|b| b.0).unwrap_or_else(|| vec![true; n]);
+        (0..n)
+            .map(|i| if solution[i] { Some(deltas[i]) } else { None })
+            .collect()
+    }
+}
+
+/// Choose which deltas of a glyph to encode, dropping those that IUP
+/// reproduces within `tolerance`. `ends` holds the index of the last point
+/// of each contour.
+pub fn iup_delta_optimize(
+    deltas: &[Vec2],
+    coords: &[Point],
+    ends: &[usize],
+    tolerance: f64,
+) -> Vec<Option<Vec2>> {
+    let mut out = Vec::with_capacity(deltas.len());
+    let mut start = 0;
+    for &end in ends {
+        out.extend(iup_contour_optimize(
+            &deltas[start..=end],
+            &coords[start..=end],
+            tolerance,
+        ));
+        start = end + 1;
+    }
+    out
+}
@@ -1,16 +1,45 @@
 //! Lightweight library for reading and writing Glyphs font files.
 
+mod compat;
+mod cu2qu;
 mod font;
 mod from_plist;
+mod geometry;
+pub mod hull;
+pub mod interp;
+mod iup;
 pub mod ops;
 mod plist;
+mod polar;
 mod region;
+mod rigid;
+pub mod settings;
+mod smooth;
 mod stretch;
+mod subdivide;
 mod to_plist;
+mod ttf;
+mod ufo;
+mod varmodel;
+mod xml;
 
-pub use font::{Component, Font, Glyph, Layer, Node, NodeType, Path};
+pub use compat::{fix_compat, fix_glyph_compat, CompatReport};
+pub use cu2qu::{curves_to_quadratic, font_to_quadratic, glyph_to_quadratic};
+pub use font::{
+    append_contour, Axis, Component, Font, FontMaster, Glyph, Layer, Node, NodeType, Path,
+};
 pub use from_plist::FromPlist;
+pub use geometry::{
+    blend_layers, compatible_parts, is_geometry_compatible, layer_geometry, set_layer_geometry,
+    unwrap_angle, PointKind,
+};
+pub use iup::{iup_delta, iup_delta_optimize};
 pub use plist::Plist;
 pub use region::Region;
+pub use smooth::{restore_smooth, restore_smooth_path};
 pub use stretch::stretch;
+pub use subdivide::{equalize_glyph, equalize_nodes};
 pub use to_plist::ToPlist;
+pub use ttf::{export_variable_ttf, import_variable_ttf, TtfOptions};
+pub use ufo::{export_master_ufo, export_ufo, import_designspace, import_ufo};
+pub use varmodel::{normalize_value, support_scalar, Support, VariationModel};
@@ -11,6 +11,12 @@ enum Cmd {
     IncoFix(IncoFixCmd),
     IncoScale(IncoScaleCmd),
     IncoSyms(IncoSymsCmd),
+    FixCompat(FixCompatCmd),
+    Equalize(EqualizeCmd),
+    Cu2qu(Cu2quCmd),
+    ExportUfo(ExportUfoCmd),
+    ExportTtf(ExportTtfCmd),
+    Instance(InstanceCmd),
 }
 
 #[derive(StructOpt, Debug)]
@@ -53,17 +59,151 @@ struct IncoSymsCmd {
     font: PathBuf,
 }
 
-use glyphstool::{ops, Font, FromPlist, Plist, ToPlist};
+#[derive(StructOpt, Debug)]
+struct FixCompatCmd {
+    /// The font file to operate on.
+    #[structopt(parse(from_os_str))]
+    font: PathBuf,
+
+    /// Glyphs to fix (default all).
+    glyphs: Vec<String>,
+}
+
+#[derive(StructOpt, Debug)]
+struct EqualizeCmd {
+    /// The font file to operate on.
+    #[structopt(parse(from_os_str))]
+    font: PathBuf,
+
+    /// Glyphs to subdivide (default all).
+    glyphs: Vec<String>,
+}
+
+#[derive(StructOpt, Debug)]
+struct Cu2quCmd {
+    /// The font file to operate on.
+    #[structopt(parse(from_os_str))]
+    font: PathBuf,
+
+    /// Maximum error, in font units.
+    #[structopt(long, default_value = "1.0")]
+    tolerance: f64,
+}
+
+#[derive(StructOpt, Debug)]
+struct ExportUfoCmd {
+    /// The font file to export.
+    #[structopt(parse(from_os_str))]
+    font: PathBuf,
+
+    /// The directory to write UFOs and the designspace into.
+    #[structopt(parse(from_os_str))]
+    out: PathBuf,
+}
+
+#[derive(StructOpt, Debug)]
+struct ExportTtfCmd {
+    /// The font file to export.
+    #[structopt(parse(from_os_str))]
+    font: PathBuf,
+
+    /// The variable TrueType file to write.
+    #[structopt(parse(from_os_str))]
+    out: PathBuf,
+
+    /// Maximum error of the conversion to quadratic, in font units.
+    #[structopt(long, default_value = "1.0")]
+    tolerance: f64,
+
+    /// Omit deltas that can be inferred (IUP optimization).
+    #[structopt(long)]
+    iup: bool,
+
+    /// Maximum error allowed by IUP optimization, in font units.
+    #[structopt(long, default_value = "0.5")]
+    iup_tolerance: f64,
+}
+
+#[derive(StructOpt, Debug)]
+struct InstanceCmd {
+    /// The font file to interpolate.
+    #[structopt(parse(from_os_str))]
+    font: PathBuf,
+
+    /// The single master font file to write, in the format given by its
+    /// extension: .glyphs, .ufo, .designspace or .ttf.
+    #[structopt(parse(from_os_str))]
+    out: PathBuf,
+
+    /// The design space location of the instance, one value per axis.
+    #[structopt(long, use_delimiter = true, required = true)]
+    location: Vec<f64>,
+
+    /// Interpolate with radial basis functions of this kind instead of
+    /// linearly: gaussian, thin-plate, linear, multiquadric,
+    /// inverse-multiquadric, cubic or wendland. Glyphs and points with
+    /// settings recorded by the interpolation toy use those instead.
+    #[structopt(long)]
+    basis: Option<Basis>,
+
+    /// Kernel radius, in units of the range of the masters along each axis.
+    #[structopt(long, default_value = "1.0")]
+    radius: f64,
+
+    /// Order of the polynomial term: 0 for none, 1 for a constant, 2 for
+    /// linear.
+    #[structopt(long, default_value = "2")]
+    order: usize,
+
+    /// Regularization of the kernel matrix; 0 interpolates the masters
+    /// exactly.
+    #[structopt(long, default_value = "0.0")]
+    smoothing: f64,
+}
+
+use glyphstool::interp::{self, Basis, Method, Model, RbfParams};
+use glyphstool::{ops, CompatReport, Font, ToPlist};
 
 fn read_font(path: &Path) -> Font {
-    let contents = fs::read_to_string(path).expect("error reading font file");
-    let plist = Plist::parse(&contents).expect("error parsing font file");
-    FromPlist::from_plist(plist)
+    Font::load(path).expect("error reading font file")
 }
 
-fn write_font(path: &Path, font: Font) {
-    let plist = font.to_plist();
-    fs::write(path, &plist.to_string()).unwrap();
+/// Write the font in the format given by the extension of the path, as
+/// `Font::load` reads it: a designspace document with a UFO per master
+/// beside it, a UFO of a single master font, a variable TrueType font with
+/// the default options, or else a Glyphs file.
+fn write_font(path: &Path, font: Font) -> Result<(), String> {
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some("designspace") => {
+            let dir = path.parent().unwrap_or_else(|| Path::new(""));
+            let written = glyphstool::export_ufo(&font, dir).map_err(|e| e.to_string())?;
+            if written != path {
+                fs::rename(&written, path).map_err(|e| e.to_string())?;
+            }
+            Ok(())
+        }
+        Some("ufo") => glyphstool::export_master_ufo(&font, path),
+        Some("ttf") => {
+            let mut report = CompatReport::default();
+            let options = glyphstool::TtfOptions::default();
+            let data = glyphstool::export_variable_ttf(&font, &options, &mut report)?;
+            print_report(&report);
+            fs::write(path, data).map_err(|e| e.to_string())
+        }
+        _ => {
+            let plist = font.to_plist();
+            fs::write(path, plist.to_string()).map_err(|e| e.to_string())
+        }
+    }
+}
+
+fn print_report(report: &CompatReport) {
+    for fixed in &report.fixed {
+        println!("fixed {}", fixed);
+    }
+    for unfixed in &report.unfixed {
+        println!("could not fix {}", unfixed);
+    }
 }
 
 fn main() {
@@ -75,22 +215,75 @@ fn main() {
             let mut font = read_font(&m.font);
             let other = read_font(&m.other);
             ops::merge(&mut font, &other, &m.layer);
-            write_font(&m.font, font);
+            write_font(&m.font, font).expect("error writing font");
         }
         Cmd::IncoFix(m) => {
             let mut font = read_font(&m.font);
             inco_fix::inco_fix(&mut font);
-            write_font(&m.font, font);
+            write_font(&m.font, font).expect("error writing font");
         }
         Cmd::IncoScale(m) => {
             let mut font = read_font(&m.font);
             inco_fix::inco_scale(&mut font, m.subcmd);
-            write_font(&m.font, font);
+            write_font(&m.font, font).expect("error writing font");
         }
         Cmd::IncoSyms(m) => {
             let mut font = read_font(&m.font);
             inco_fix::inco_syms(&mut font);
-            write_font(&m.font, font);
+            write_font(&m.font, font).expect("error writing font");
+        }
+        Cmd::FixCompat(m) => {
+            let mut font = read_font(&m.font);
+            let report = glyphstool::fix_compat(&mut font, &m.glyphs);
+            print_report(&report);
+            write_font(&m.font, font).expect("error writing font");
+        }
+        Cmd::Equalize(m) => {
+            let mut font = read_font(&m.font);
+            let report = glyphstool::equalize_nodes(&mut font, &m.glyphs);
+            print_report(&report);
+            write_font(&m.font, font).expect("error writing font");
+        }
+        Cmd::Cu2qu(m) => {
+            let mut font = read_font(&m.font);
+            let report = glyphstool::font_to_quadratic(&mut font, m.tolerance);
+            print_report(&report);
+            write_font(&m.font, font).expect("error writing font");
+        }
+        Cmd::ExportUfo(m) => {
+            let font = read_font(&m.font);
+            let path = glyphstool::export_ufo(&font, &m.out).expect("error writing UFO");
+            println!("wrote {}", path.display());
+        }
+        Cmd::ExportTtf(m) => {
+            let font = read_font(&m.font);
+            let options = glyphstool::TtfOptions {
+                cu2qu_tolerance: m.tolerance,
+                iup: m.iup,
+                iup_tolerance: m.iup_tolerance,
+            };
+            let mut report = CompatReport::default();
+            let data = glyphstool::export_variable_ttf(&font, &options, &mut report)
+                .expect("error exporting font");
+            print_report(&report);
+            fs::write(&m.out, data).expect("error writing font");
+            println!("wrote {}", m.out.display());
+        }
+        Cmd::Instance(m) => {
+            let mut font = read_font(&m.font);
+            let method = match m.basis {
+                Some(basis) => Method::Rbf(RbfParams {
+                    basis,
+                    radius: m.radius,
+                    order: m.order,
+                    smoothing: m.smoothing,
+                }),
+                None => Method::Linear,
+            };
+            let model = Model::new(&font, method);
+            interp::interpolate_font(&mut font, &m.location, &model)
+                .expect("error interpolating font");
+            write_font(&m.out, font).expect("error writing font");
         }
     }
     /*
@@ -0,0 +1,177 @@
+//! Interpolation in polar coordinates.
+//!
+//! Moving each point in a straight line shrinks features that rotate, such
+//! as the dot of an /i. Here a contour is represented by the centroid of
+//! its on-curve points, each on-curve point by its angle and distance from
+//! the centroid, and each off-curve point by its angle and distance from
+//! its on-curve point. These are interpolated in place of the coordinates,
+//! so that a rotation between masters stays a rotation.
+
+use std::f64::consts::PI;
+
+use kurbo::{Point, Vec2};
+
+use crate::font::NodeType;
+use crate::interp::Sample;
+
+/// Vectors shorter than this have no meaningful angle.
+const MIN_LENGTH: f64 = 1e-9;
+
+/// What a point is relative to while interpolating.
+#[derive(Clone, Copy)]
+enum Origin {
+    /// Nothing: its coordinates are interpolated directly.
+    None,
+    /// The centroid with the given channel.
+    Centroid(usize),
+    /// Another point, which is relative to a centroid.
+    Point(usize),
+}
+
+/// Whether all points of a contour have samples at the same locations, so
+/// the contour can be converted at each.
+pub fn is_compatible(contour: &[Vec<Sample>]) -> bool {
+    let locations =
+        |pt: &Vec<Sample>| -> Vec<Vec<f64>> { pt.iter().map(|s| s.location.clone()).collect() };
+    match contour.first() {
+        Some(first) if !first.is_empty() => {
+            let first = locations(first);
+            contour.iter().all(|pt| locations(pt) == first)
+        }
+        _ => false,
+    }
+}
+
+/// The on-curve point a handle is relative to: the previous node if it is
+/// on-curve, else the next. `None` for runs of off-curve points, which are
+/// relative to the centroid instead.
+fn parent(subpath: &[NodeType], i: usize) -> Option<usize> {
+    let n = subpath.len();
+    let prev = (i + n - 1) % n;
+    let next = (i + 1) % n;
+    if subpath[prev] != NodeType::OffCurve {
+        Some(prev)
+    } else if subpath[next] != NodeType::OffCurve {
+        Some(next)
+    } else {
+        None
+    }
+}
+
+/// Represent a point by its angle (as x) and distance (as y) from an
+/// origin, with one origin per sample.
+///
+/// Angles are unwrapped so that each differs from the one of the previous
+/// sample by at most half a turn. Where the point is at its origin, the
+/// angle is taken from the previous sample (or the first with an angle).
+fn to_polar(pt: &[Sample], origins: &[Point]) -> Vec<Sample> {
+    let vecs: Vec<Vec2> = pt
+        .iter()
+        .zip(origins)
+        .map(|(s, &origin)| s.pt - origin)
+        .collect();
+    let first_angle = vecs
+        .iter()
+        .find(|v| v.hypot() > MIN_LENGTH)
+        .map(|v| v.y.atan2(v.x))
+        .unwrap_or(0.0);
+    let mut prev = first_angle;
+    pt.iter()
+        .zip(&vecs)
+        .map(|(s, v)| {
+            let mut angle = if v.hypot() > MIN_LENGTH {
+                v.y.atan2(v.x)
+            } else {
+                prev
+            };
+            angle += 2.0 * PI * ((prev - angle) / (2.0 * PI)).round();
+            prev = angle;
+            Sample {
+                pt: Point::new(angle, v.hypot()),
+                location: s.location.clone(),
+            }
+        })
+        .collect()
+}
+
+/// The point at an interpolated angle (x) and distance (y) from an origin.
+/// Negative distances, which a kernel can overshoot to, are clamped.
+fn from_polar(origin: Point, polar: Point) -> Point {
+    origin + Vec2::new(polar.x.cos(), polar.x.sin()) * polar.y.max(0.0)
+}
+
+/// Evaluate points in polar coordinates. `interp` interpolates a set of
+/// points (with coordinates as given) at the desired location, given the
+/// point each belongs to; a centroid belongs to the first point of its
+/// contour.
+///
+/// Contours whose points don't all have samples at the same locations,
+/// and points not covered by the structure, are interpolated directly.
+pub fn eval(
+    pts: &[Vec<Sample>],
+    structure: &[Vec<NodeType>],
+    interp: impl FnOnce(&[Vec<Sample>], &[usize]) -> Result<Vec<Point>, String>,
+) -> Result<Vec<Point>, String> {
+    // Channel i is point i, converted or not, followed by the centroids.
+    let mut channels = pts.to_vec();
+    let mut owners: Vec<usize> = (0..pts.len()).collect();
+    let mut origins = vec![Origin::None; pts.len()];
+    let mut j = 0;
+    for subpath in structure {
+        let n = subpath.len();
+        if j + n > pts.len() {
+            break;
+        }
+        let contour = &pts[j..j + n];
+        let on: Vec<usize> = (0..n)
+            .filter(|&i| subpath[i] != NodeType::OffCurve)
+            .collect();
+        if !on.is_empty() && is_compatible(contour) {
+            let centroid_samples: Vec<Sample> = (0..contour[0].len())
+                .map(|k| {
+                    let (x, y) = on.iter().fold((0.0, 0.0), |(x, y), &i| {
+                        let pt = contour[i][k].pt;
+                        (x + pt.x, y + pt.y)
+                    });
+                    Sample {
+                        pt: Point::new(x / on.len() as f64, y / on.len() as f64),
+                        location: contour[0][k].location.clone(),
+                    }
+                })
+                .collect();
+            let centroid = channels.len();
+            for i in 0..n {
+                let (origin, origin_pts): (Origin, Vec<Point>) =
+                    match parent(subpath, i).filter(|_| subpath[i] == NodeType::OffCurve) {
+                        Some(p) => (
+                            Origin::Point(j + p),
+                            contour[p].iter().map(|s| s.pt).collect(),
+                        ),
+                        None => (
+                            Origin::Centroid(centroid),
+                            centroid_samples.iter().map(|s| s.pt).collect(),
+                        ),
+                    };
+                origins[j + i] = origin;
+                channels[j + i] = to_polar(&contour[i], &origin_pts);
+            }
+            channels.push(centroid_samples);
+            owners.push(j);
+        }
+        j += n;
+    }
+    let values = interp(&channels, &owners)?;
+    let mut result = values[..pts.len()].to_vec();
+    // Points relative to a centroid first, as the others depend on them.
+    for (i, &origin) in origins.iter().enumerate() {
+        if let Origin::Centroid(c) = origin {
+            result[i] = from_polar(values[c], values[i]);
+        }
+    }
+    for (i, &origin) in origins.iter().enumerate() {
+        if let Origin::Point(p) = origin {
+            result[i] = from_polar(result[p], values[i]);
+        }
+    }
+    Ok(result)
+}
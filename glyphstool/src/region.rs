@@ -155,7 +155,7 @@ impl Region {
                 tracer.process_line(&slice.intervals, &[], y1);
             }
         }
-        tracer.to_paths()
+        tracer.trace_paths()
     }
 }
 
@@ -301,7 +301,7 @@ impl PathTracer {
         }
     }
 
-    fn to_paths(&mut self) -> Vec<Path> {
+    fn trace_paths(&mut self) -> Vec<Path> {
         self.pending_edges = (0..self.edges.len()).collect();
         let mut result = Vec::new();
         while let Some(path) = self.trace_path() {
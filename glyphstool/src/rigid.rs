@@ -0,0 +1,210 @@
+//! Interpolation of the rigid motion of each contour.
+//!
+//! Features such as a rotating dot or a swinging terminal move as a whole.
+//! Here the motion of each contour from its shape in the first sample to
+//! its shape in each sample is fit by a similarity transform (Procrustes
+//! analysis). Its translation, rotation and scale are interpolated as
+//! parameters, and only the residual deformation, in the frame of the
+//! contour, is interpolated point by point. A contour that rotates between
+//! two masters then stays rigid in between.
+
+use std::f64::consts::PI;
+
+use kurbo::{Point, Vec2};
+
+use crate::font::NodeType;
+use crate::interp::Sample;
+use crate::polar::is_compatible;
+
+/// Contours smaller than this (in the sum of squared distances from the
+/// centroid) have no meaningful rotation or scale.
+const MIN_SPREAD: f64 = 1e-9;
+
+/// A similarity transform, as the complex number it multiplies by.
+#[derive(Clone, Copy)]
+struct Similarity {
+    re: f64,
+    im: f64,
+}
+
+impl Similarity {
+    const IDENTITY: Similarity = Similarity { re: 1.0, im: 0.0 };
+
+    /// The similarity transform from angle and log scale.
+    fn from_params(angle: f64, log_scale: f64) -> Similarity {
+        let scale = log_scale.exp();
+        Similarity {
+            re: scale * angle.cos(),
+            im: scale * angle.sin(),
+        }
+    }
+
+    /// The similarity transform (about the origin) that best maps the
+    /// points `from` to the points `to` in the least squares sense.
+    ///
+    /// If either set of points is collapsed (or the best fit scales them
+    /// to nothing) this is the identity, as a zero scale has no log and no
+    /// inverse.
+    fn fit(from: &[Vec2], to: &[Vec2]) -> Similarity {
+        let spread = |pts: &[Vec2]| -> f64 { pts.iter().map(|a| a.x * a.x + a.y * a.y).sum() };
+        let from_spread = spread(from);
+        if from_spread < MIN_SPREAD || spread(to) < MIN_SPREAD {
+            return Similarity::IDENTITY;
+        }
+        let (re, im) = from.iter().zip(to).fold((0.0, 0.0), |(re, im), (a, b)| {
+            (re + a.x * b.x + a.y * b.y, im + a.x * b.y - a.y * b.x)
+        });
+        let sim = Similarity {
+            re: re / from_spread,
+            im: im / from_spread,
+        };
+        if sim.re * sim.re + sim.im * sim.im < MIN_SPREAD {
+            return Similarity::IDENTITY;
+        }
+        sim
+    }
+
+    fn angle(self) -> f64 {
+        self.im.atan2(self.re)
+    }
+
+    fn log_scale(self) -> f64 {
+        self.re.hypot(self.im).ln()
+    }
+
+    fn apply(self, v: Vec2) -> Vec2 {
+        Vec2::new(self.re * v.x - self.im * v.y, self.re * v.y + self.im * v.x)
+    }
+
+    fn apply_inverse(self, v: Vec2) -> Vec2 {
+        let norm = self.re * self.re + self.im * self.im;
+        Vec2::new(
+            (self.re * v.x + self.im * v.y) / norm,
+            (self.re * v.y - self.im * v.x) / norm,
+        )
+    }
+}
+
+/// The centroid of some points.
+fn centroid(pts: &[Point]) -> Point {
+    let (x, y) = pts.iter().fold((0.0, 0.0), |(x, y), p| (x + p.x, y + p.y));
+    Point::new(x / pts.len() as f64, y / pts.len() as f64)
+}
+
+/// The channels of a contour being interpolated.
+struct Contour {
+    /// The first point.
+    start: usize,
+    /// The points of the first sample, relative to their centroid.
+    reference: Vec<Vec2>,
+    /// The channel of the centroid.
+    centroid: usize,
+    /// The channel of the angle (as x) and log scale (as y).
+    motion: usize,
+}
+
+/// Evaluate points by interpolating the rigid motion of each contour and
+/// the residual deformation separately. `interp` interpolates a set of
+/// points (with coordinates as given) at the desired location, given the
+/// point each belongs to; the motion belongs to the first point of its
+/// contour.
+///
+/// Contours whose points don't all have samples at the same locations,
+/// and points not covered by the structure, are interpolated directly.
+pub fn eval(
+    pts: &[Vec<Sample>],
+    structure: &[Vec<NodeType>],
+    interp: impl FnOnce(&[Vec<Sample>], &[usize]) -> Result<Vec<Point>, String>,
+) -> Result<Vec<Point>, String> {
+    // Channel i is point i, or its residual, followed by the motion of
+    // each contour.
+    let mut channels = pts.to_vec();
+    let mut owners: Vec<usize> = (0..pts.len()).collect();
+    let mut contours = Vec::new();
+    let mut j = 0;
+    for subpath in structure {
+        let n = subpath.len();
+        if j + n > pts.len() {
+            break;
+        }
+        let contour = &pts[j..j + n];
+        if is_compatible(contour) {
+            let n_samples = contour[0].len();
+            let sample_pts =
+                |k: usize| -> Vec<Point> { contour.iter().map(|pt| pt[k].pt).collect() };
+            let first = sample_pts(0);
+            let first_centroid = centroid(&first);
+            let reference: Vec<Vec2> = first.iter().map(|&p| p - first_centroid).collect();
+            let mut centroid_samples = Vec::with_capacity(n_samples);
+            let mut motion_samples = Vec::with_capacity(n_samples);
+            let mut prev_angle = 0.0;
+            for k in 0..n_samples {
+                let location = &contour[0][k].location;
+                let pts_k = sample_pts(k);
+                let c = centroid(&pts_k);
+                let relative: Vec<Vec2> = pts_k.iter().map(|&p| p - c).collect();
+                let sim = Similarity::fit(&reference, &relative);
+                // Unwrap so that each angle is within half a turn of the
+                // previous sample's.
+                let mut angle = sim.angle();
+                angle += 2.0 * PI * ((prev_angle - angle) / (2.0 * PI)).round();
+                prev_angle = angle;
+                for (i, (&r, &a)) in relative.iter().zip(&reference).enumerate() {
+                    let residual = sim.apply_inverse(r) - a;
+                    channels[j + i][k].pt = Point::new(residual.x, residual.y);
+                }
+                centroid_samples.push(Sample {
+                    pt: c,
+                    location: location.clone(),
+                });
+                motion_samples.push(Sample {
+                    pt: Point::new(angle, sim.log_scale()),
+                    location: location.clone(),
+                });
+            }
+            contours.push(Contour {
+                start: j,
+                reference,
+                centroid: channels.len(),
+                motion: channels.len() + 1,
+            });
+            channels.push(centroid_samples);
+            channels.push(motion_samples);
+            owners.extend(&[j, j]);
+        }
+        j += n;
+    }
+    let values = interp(&channels, &owners)?;
+    let mut result = values[..pts.len()].to_vec();
+    for contour in &contours {
+        let c = values[contour.centroid];
+        let motion = values[contour.motion];
+        let sim = Similarity::from_params(motion.x, motion.y);
+        for (i, &a) in contour.reference.iter().enumerate() {
+            let k = contour.start + i;
+            result[k] = c + sim.apply(a + values[k].to_vec2());
+        }
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A contour collapsed to a point fits as the identity, not a zero
+    /// scale.
+    #[test]
+    fn fit_collapsed() {
+        let from = [
+            Vec2::new(-1.0, -1.0),
+            Vec2::new(1.0, -1.0),
+            Vec2::new(0.0, 2.0),
+        ];
+        let to = [Vec2::new(0.0, 0.0); 3];
+        let sim = Similarity::fit(&from, &to);
+        assert_eq!(sim.log_scale(), 0.0);
+        let v = sim.apply_inverse(Vec2::new(3.0, 4.0));
+        assert_eq!((v.x, v.y), (3.0, 4.0));
+    }
+}
@@ -0,0 +1,280 @@
+//! Interpolation settings recorded in the userData of a font.
+//!
+//! The interpolation toy records, under the "interpToy" key, the metric of
+//! the axes for the font, and for each glyph the interpolation method, the
+//! coordinates, the extrapolation policy, and overrides of the method for
+//! some points or whole contours. Instance export uses the same settings.
+
+use std::collections::HashMap;
+
+use crate::font::{Font, Glyph, NodeType};
+use crate::interp::{Basis, Coords, Extrapolation, Method, Metric, RbfParams};
+use crate::plist::Plist;
+
+/// The userData key under which settings are recorded.
+pub const USER_DATA_KEY: &str = "interpToy";
+
+/// An interpolation method as recorded, with the kernel parameters kept
+/// even when they are unused.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Kernel {
+    /// The radial basis function, or `None` for the variation model.
+    pub basis: Option<Basis>,
+    pub radius: f64,
+    /// Order of the polynomial term, or `None` for the default of the
+    /// basis: 1 for linear, else 2.
+    pub order: Option<usize>,
+    pub smoothing: f64,
+}
+
+impl Default for Kernel {
+    fn default() -> Self {
+        Kernel {
+            basis: Some(Basis::Gaussian),
+            radius: 1.0,
+            order: None,
+            smoothing: 0.0,
+        }
+    }
+}
+
+impl Kernel {
+    /// The interpolation method.
+    pub fn method(self) -> Method {
+        match self.basis {
+            Some(basis) => Method::Rbf(RbfParams {
+                basis,
+                radius: self.radius,
+                order: self.order.unwrap_or(match basis {
+                    Basis::Linear => 1,
+                    _ => 2,
+                }),
+                smoothing: self.smoothing,
+            }),
+            None => Method::Linear,
+        }
+    }
+}
+
+/// The dictionary of the settings in the userData of a glyph or font,
+/// given its other fields, created if needed.
+pub fn user_data_dict(
+    other_stuff: &mut HashMap<String, Plist>,
+) -> Option<&mut HashMap<String, Plist>> {
+    let user_data = other_stuff
+        .entry("userData".to_string())
+        .or_insert_with(|| HashMap::new().into());
+    match user_data {
+        Plist::Dictionary(user_data) => match user_data
+            .entry(USER_DATA_KEY.to_string())
+            .or_insert_with(|| HashMap::new().into())
+        {
+            Plist::Dictionary(dict) => Some(dict),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// The recorded settings of a glyph or font, given its other fields.
+pub fn settings(other_stuff: &HashMap<String, Plist>) -> Option<&Plist> {
+    other_stuff.get("userData")?.get(USER_DATA_KEY)
+}
+
+fn insert_kernel(dict: &mut HashMap<String, Plist>, kernel: Kernel) {
+    let name = kernel.basis.map_or("variation", Basis::name);
+    dict.insert("interp".to_string(), name.to_string().into());
+    dict.insert("radius".to_string(), kernel.radius.into());
+    dict.insert("smoothing".to_string(), kernel.smoothing.into());
+    match kernel.order {
+        Some(order) => dict.insert("order".to_string(), (order as i64).into()),
+        None => dict.remove("order"),
+    };
+}
+
+fn parse_kernel(dict: &Plist) -> Option<Kernel> {
+    let basis = match dict.get("interp")?.as_str()? {
+        "variation" => None,
+        name => Some(name.parse().ok()?),
+    };
+    let defaults = Kernel::default();
+    Some(Kernel {
+        basis,
+        radius: dict
+            .get("radius")
+            .and_then(Plist::as_f64)
+            .unwrap_or(defaults.radius),
+        order: dict
+            .get("order")
+            .and_then(Plist::as_i64)
+            .map(|order| order as usize),
+        smoothing: dict
+            .get("smoothing")
+            .and_then(Plist::as_f64)
+            .unwrap_or(defaults.smoothing),
+    })
+}
+
+/// Record the interpolation method of a glyph.
+pub fn record_kernel(glyph: &mut Glyph, kernel: Kernel) {
+    if let Some(dict) = user_data_dict(&mut glyph.other_stuff) {
+        insert_kernel(dict, kernel);
+    }
+}
+
+/// The interpolation method recorded for a glyph.
+pub fn recorded_kernel(glyph: &Glyph) -> Option<Kernel> {
+    parse_kernel(settings(&glyph.other_stuff)?)
+}
+
+/// Record the coordinates of a glyph.
+pub fn record_coords(glyph: &mut Glyph, coords: Coords) {
+    if let Some(dict) = user_data_dict(&mut glyph.other_stuff) {
+        dict.insert("coords".to_string(), coords.name().to_string().into());
+    }
+}
+
+/// The coordinates recorded for a glyph. It is an error if they aren't
+/// supported.
+pub fn recorded_coords(glyph: &Glyph) -> Result<Option<Coords>, String> {
+    let name = settings(&glyph.other_stuff)
+        .and_then(|dict| dict.get("coords"))
+        .and_then(Plist::as_str);
+    name.map(str::parse).transpose()
+}
+
+/// Record the extrapolation policy of a glyph.
+pub fn record_extrapolation(glyph: &mut Glyph, extrapolation: Extrapolation) {
+    if let Some(dict) = user_data_dict(&mut glyph.other_stuff) {
+        let name = extrapolation.name().to_string();
+        dict.insert("extrapolation".to_string(), name.into());
+    }
+}
+
+/// The extrapolation policy recorded for a glyph.
+pub fn recorded_extrapolation(glyph: &Glyph) -> Option<Extrapolation> {
+    let dict = settings(&glyph.other_stuff)?;
+    dict.get("extrapolation")?.as_str()?.parse().ok()
+}
+
+/// Record the overrides of a glyph, by node index, for the given node
+/// types of its contours. Each contour whose nodes all have the same
+/// override is recorded as a whole.
+pub fn record_overrides(
+    glyph: &mut Glyph,
+    overrides: &[Option<Kernel>],
+    structure: &[Vec<NodeType>],
+) {
+    let dict = match user_data_dict(&mut glyph.other_stuff) {
+        Some(dict) => dict,
+        None => return,
+    };
+    let mut values: Vec<Kernel> = Vec::new();
+    for value in overrides.iter().flatten() {
+        if !values.contains(value) {
+            values.push(*value);
+        }
+    }
+    let mut entries = Vec::new();
+    for &kernel in &values {
+        let mut points: Vec<usize> = (0..overrides.len())
+            .filter(|&i| overrides[i] == Some(kernel))
+            .collect();
+        let mut j = 0;
+        for (contour, subpath) in structure.iter().enumerate() {
+            let range = j..j + subpath.len();
+            j = range.end;
+            if !range.is_empty() && range.clone().all(|i| points.contains(&i)) {
+                points.retain(|i| !range.contains(i));
+                let mut entry = HashMap::new();
+                entry.insert("contour".to_string(), (contour as i64).into());
+                insert_kernel(&mut entry, kernel);
+                entries.push(entry.into());
+            }
+        }
+        if !points.is_empty() {
+            let mut entry = HashMap::new();
+            let points: Vec<Plist> = points.into_iter().map(|i| (i as i64).into()).collect();
+            entry.insert("points".to_string(), points.into());
+            insert_kernel(&mut entry, kernel);
+            entries.push(entry.into());
+        }
+    }
+    if entries.is_empty() {
+        dict.remove("overrides");
+    } else {
+        dict.insert("overrides".to_string(), entries.into());
+    }
+}
+
+/// The overrides recorded for a glyph with the given node types of its
+/// contours, by node index.
+pub fn recorded_overrides(glyph: &Glyph, structure: &[Vec<NodeType>]) -> Vec<Option<Kernel>> {
+    let n_pts = structure.iter().map(Vec::len).sum();
+    let mut overrides = vec![None; n_pts];
+    let entries = settings(&glyph.other_stuff)
+        .and_then(|dict| dict.get("overrides"))
+        .and_then(Plist::as_array)
+        .unwrap_or_default();
+    for entry in entries {
+        let value = match parse_kernel(entry) {
+            Some(value) => value,
+            None => continue,
+        };
+        let mut points: Vec<usize> = entry
+            .get("points")
+            .and_then(Plist::as_array)
+            .unwrap_or_default()
+            .iter()
+            .filter_map(Plist::as_i64)
+            .map(|i| i as usize)
+            .collect();
+        if let Some(contour) = entry.get("contour").and_then(Plist::as_i64) {
+            let start: usize = structure.iter().take(contour as usize).map(Vec::len).sum();
+            if let Some(subpath) = structure.get(contour as usize) {
+                points.extend(start..start + subpath.len());
+            }
+        }
+        for i in points {
+            if let Some(slot) = overrides.get_mut(i) {
+                *slot = Some(value);
+            }
+        }
+    }
+    overrides
+}
+
+/// Record the metric of the axes of a font, for all of its glyphs.
+pub fn record_metric(font: &mut Font, metric: &Metric) {
+    if let Some(dict) = user_data_dict(&mut font.other_stuff) {
+        let scales: Vec<Plist> = metric.scales.iter().map(|&x| x.into()).collect();
+        dict.insert("axisScales".to_string(), scales.into());
+        match &metric.matrix {
+            Some(matrix) => {
+                let rows: Vec<Plist> = matrix
+                    .iter()
+                    .map(|row| {
+                        let row: Vec<Plist> = row.iter().map(|&x| x.into()).collect();
+                        row.into()
+                    })
+                    .collect();
+                dict.insert("metric".to_string(), rows.into())
+            }
+            None => dict.remove("metric"),
+        };
+    }
+}
+
+/// The metric recorded for a font. Scales are empty if none were recorded.
+pub fn recorded_metric(font: &Font) -> Option<Metric> {
+    let dict = settings(&font.other_stuff)?;
+    let numbers = |plist: &Plist| -> Option<Vec<f64>> {
+        plist.as_array()?.iter().map(Plist::as_f64).collect()
+    };
+    let scales = dict.get("axisScales").and_then(numbers).unwrap_or_default();
+    let matrix = dict
+        .get("metric")
+        .and_then(Plist::as_array)
+        .and_then(|rows| rows.iter().map(numbers).collect());
+    Some(Metric { scales, matrix })
+}
@@ -0,0 +1,98 @@
+//! Restoring the tangent continuity of smooth nodes.
+//!
+//! Interpolating each point independently keeps the neighbours of a smooth
+//! node collinear only at the masters, so in-between instances can have a
+//! kink at the node. This moves points as little as possible to remove it.
+
+use kurbo::{Point, Vec2};
+
+use crate::font::{NodeType, Path};
+
+/// Vectors shorter than this have no meaningful direction.
+const MIN_LENGTH: f64 = 1e-9;
+
+fn normalize(v: Vec2) -> Option<Vec2> {
+    let len = v.hypot();
+    if len < MIN_LENGTH {
+        None
+    } else {
+        Some(v * (1.0 / len))
+    }
+}
+
+/// The direction of the line through the origin that best fits two
+/// vectors, pointing the same way as their sum.
+fn principal_direction(a: Vec2, b: Vec2) -> Option<Vec2> {
+    let sxx = a.x * a.x + b.x * b.x;
+    let sxy = a.x * a.y + b.x * b.y;
+    let syy = a.y * a.y + b.y * b.y;
+    if sxx + syy < MIN_LENGTH * MIN_LENGTH {
+        return None;
+    }
+    let angle = 0.5 * (2.0 * sxy).atan2(sxx - syy);
+    let d = Vec2::new(angle.cos(), angle.sin());
+    if d.dot(a + b) < 0.0 {
+        Some(Vec2::new(-d.x, -d.y))
+    } else {
+        Some(d)
+    }
+}
+
+/// Make the neighbours of each smooth node of a contour collinear with it.
+///
+/// Between two handles, both are projected onto the line through the node
+/// that best fits them. Between a handle and a line, the handle is projected
+/// onto the extension of the line. Between two lines, the node is projected
+/// onto the line through its neighbours. Handles are shortened to nothing
+/// rather than crossing to the wrong side of their node.
+pub fn restore_smooth(pts: &mut [Point], node_types: &[NodeType], closed: bool) {
+    let n = pts.len().min(node_types.len());
+    if n < 3 {
+        return;
+    }
+    let is_off = |j: usize| node_types[j] == NodeType::OffCurve;
+    for i in 0..n {
+        if !node_types[i].is_smooth() || (!closed && (i == 0 || i == n - 1)) {
+            continue;
+        }
+        let prev = (i + n - 1) % n;
+        let next = (i + 1) % n;
+        let node = pts[i];
+        let into = node - pts[prev];
+        let out = pts[next] - node;
+        match (is_off(prev), is_off(next)) {
+            (true, true) => {
+                if let Some(d) = principal_direction(into, out) {
+                    pts[prev] = node - d * into.dot(d).max(0.0);
+                    pts[next] = node + d * out.dot(d).max(0.0);
+                }
+            }
+            (true, false) => {
+                if let Some(d) = normalize(out) {
+                    pts[prev] = node - d * into.dot(d).max(0.0);
+                }
+            }
+            (false, true) => {
+                if let Some(d) = normalize(into) {
+                    pts[next] = node + d * out.dot(d).max(0.0);
+                }
+            }
+            (false, false) => {
+                if let Some(d) = normalize(pts[next] - pts[prev]) {
+                    pts[i] = pts[prev] + d * into.dot(d);
+                }
+            }
+        }
+    }
+}
+
+/// Make the neighbours of each smooth node of a path collinear with it. See
+/// `restore_smooth`.
+pub fn restore_smooth_path(path: &mut Path) {
+    let mut pts: Vec<Point> = path.nodes.iter().map(|node| node.pt).collect();
+    let node_types: Vec<NodeType> = path.nodes.iter().map(|node| node.node_type).collect();
+    restore_smooth(&mut pts, &node_types, path.closed);
+    for (node, pt) in path.nodes.iter_mut().zip(pts) {
+        node.pt = pt;
+    }
+}
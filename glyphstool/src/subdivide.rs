@@ -0,0 +1,524 @@
+//! Subdivision of segments so that corresponding contours in different
+//! layers have the same number of nodes.
+//!
+//! When one master has an extra on-curve node, the contour in the other
+//! masters is split (without changing its shape) at the points that best
+//! correspond to the extra node. This is intended to be run after
+//! `fix_compat`, so contours are already in the same order and have
+//! matching start points.
+
+use kurbo::{CubicBez, ParamCurve, Point, Vec2};
+
+use crate::compat::CompatReport;
+use crate::font::{Font, Glyph, Node, NodeType, Path};
+
+/// Cost assigned to alignments that can't preserve the segment types.
+const MISMATCH_COST: f64 = 10.0;
+
+/// A single segment between two on-curve nodes.
+#[derive(Clone)]
+struct Seg {
+    p0: Point,
+    /// The off-curve points; `None` for a line.
+    ctrl: Option<(Point, Point)>,
+    p3: Point,
+    end_type: NodeType,
+}
+
+/// A contour broken into segments.
+struct Segments {
+    segs: Vec<Seg>,
+    start: Node,
+    /// For closed paths, the number of nodes before the first on-curve node.
+    lead: usize,
+    closed: bool,
+}
+
+impl Seg {
+    fn cubic(&self) -> CubicBez {
+        let (p1, p2) = self.ctrl.unwrap_or_else(|| {
+            (
+                self.p0.lerp(self.p3, 1.0 / 3.0),
+                self.p0.lerp(self.p3, 2.0 / 3.0),
+            )
+        });
+        CubicBez::new(self.p0, p1, p2, self.p3)
+    }
+
+    /// A cheap approximation to arc length.
+    fn approx_len(&self) -> f64 {
+        let chord = (self.p3 - self.p0).hypot();
+        match self.ctrl {
+            None => chord,
+            Some((p1, p2)) => {
+                let poly = (p1 - self.p0).hypot() + (p2 - p1).hypot() + (self.p3 - p2).hypot();
+                0.5 * (chord + poly)
+            }
+        }
+    }
+
+    fn eval(&self, t: f64) -> Point {
+        match self.ctrl {
+            None => self.p0.lerp(self.p3, t),
+            Some(_) => self.cubic().eval(t),
+        }
+    }
+
+    /// Find the parameter of the point on the segment nearest to `p`,
+    /// restricted to the range `t0..1`.
+    fn nearest(&self, p: Point, t0: f64) -> f64 {
+        const N: usize = 32;
+        let mut lo = t0;
+        let mut hi = 1.0;
+        let mut best = t0;
+        // Coarse sampling, then repeatedly refine around the best sample.
+        for _ in 0..4 {
+            let mut best_d = f64::INFINITY;
+            for i in 0..=N {
+                let t = lo + (hi - lo) * (i as f64) / (N as f64);
+                let d = (self.eval(t) - p).hypot2();
+                if d < best_d {
+                    best_d = d;
+                    best = t;
+                }
+            }
+            let step = (hi - lo) / (N as f64);
+            lo = (best - step).max(t0);
+            hi = (best + step).min(1.0);
+        }
+        best
+    }
+
+    /// Split the segment at the given (increasing) parameters.
+    ///
+    /// The returned pieces are cubic if the segment is a curve, or if
+    /// `force_cubic` is set; otherwise they are lines.
+    fn split(&self, ts: &[f64], force_cubic: bool) -> Vec<Seg> {
+        let mut result = Vec::with_capacity(ts.len() + 1);
+        let mut t_prev = 0.0;
+        let is_cubic = self.ctrl.is_some() || force_cubic;
+        let c = self.cubic();
+        for i in 0..=ts.len() {
+            let t = if i < ts.len() { ts[i] } else { 1.0 };
+            let (p0, ctrl, p3) = if is_cubic {
+                let sub = c.subsegment(t_prev..t);
+                (sub.p0, Some((sub.p1, sub.p2)), sub.p3)
+            } else {
+                (self.eval(t_prev), None, self.eval(t))
+            };
+            result.push(Seg {
+                p0,
+                ctrl,
+                p3,
+                end_type: self.end_type,
+            });
+            t_prev = t;
+        }
+        // Snap endpoints so rounding in subsegment doesn't move them.
+        result[0].p0 = self.p0;
+        result.last_mut().unwrap().p3 = self.p3;
+        result
+    }
+}
+
+impl Segments {
+    fn new(path: &Path) -> Option<Segments> {
+        let n = path.nodes.len();
+        let first_on = path
+            .nodes
+            .iter()
+            .position(|node| node.node_type != NodeType::OffCurve)?;
+        let start = path.nodes[first_on].clone();
+        let count = if path.closed { n } else { n - 1 - first_on };
+        let mut segs = Vec::new();
+        let mut p0 = start.pt;
+        let mut ctrl = Vec::new();
+        for i in 1..=count {
+            let node = &path.nodes[(first_on + i) % n];
+            if node.node_type == NodeType::OffCurve {
+                ctrl.push(node.pt);
+                continue;
+            }
+            if node.node_type.is_quadratic() {
+                // Quadratic segments are not handled here.
+                return None;
+            }
+            let ctrl_pts = match ctrl.len() {
+                0 => None,
+                2 => Some((ctrl[0], ctrl[1])),
+                _ => return None,
+            };
+            segs.push(Seg {
+                p0,
+                ctrl: ctrl_pts,
+                p3: node.pt,
+                end_type: node.node_type,
+            });
+            p0 = node.pt;
+            ctrl.clear();
+        }
+        Some(Segments {
+            segs,
+            start,
+            lead: first_on,
+            closed: path.closed,
+        })
+    }
+
+    /// Normalized arc length position of the end of each segment.
+    fn positions(&self) -> Vec<f64> {
+        let mut total = 0.0;
+        let mut result = Vec::with_capacity(self.segs.len());
+        for seg in &self.segs {
+            total += seg.approx_len();
+            result.push(total);
+        }
+        let total = total.max(1e-9);
+        result.iter().map(|s| s / total).collect()
+    }
+
+    fn centroid(&self) -> Point {
+        let n = self.segs.len().max(1) as f64;
+        let sum = self
+            .segs
+            .iter()
+            .fold(Vec2::new(0.0, 0.0), |sum, seg| sum + seg.p3.to_vec2());
+        Point::new(sum.x / n, sum.y / n)
+    }
+
+    /// Convert back into a path, using `lead` to determine the start index.
+    fn to_path(&self, lead: usize) -> Path {
+        let mut path = Path::new(self.closed);
+        if !self.closed {
+            path.add(self.start.pt, self.start.node_type);
+        }
+        for seg in &self.segs {
+            if let Some((p1, p2)) = seg.ctrl {
+                path.add(p1, NodeType::OffCurve);
+                path.add(p2, NodeType::OffCurve);
+            }
+            path.add(seg.p3, seg.end_type);
+        }
+        if self.closed {
+            path.nodes.rotate_right(lead + 1);
+        }
+        path.fix_node_types();
+        path
+    }
+}
+
+/// Align the segment ends of `a` to a subset of those of `b`.
+///
+/// Returns, for each segment end in `a`, the index of the corresponding
+/// segment end in `b`. The final ends always correspond.
+fn align(a: &Segments, b: &Segments) -> Vec<usize> {
+    let m = a.segs.len();
+    let n = b.segs.len();
+    let sa = a.positions();
+    let sb = b.positions();
+    let ca = a.centroid();
+    let cb = b.centroid();
+    let scale = b
+        .segs
+        .iter()
+        .map(|seg| (seg.p3 - cb).hypot())
+        .fold(1.0, f64::max);
+    let cost = |i: usize, j: usize| {
+        let ds = sa[i] - sb[j];
+        let dp = ((a.segs[i].p3 - ca) - (b.segs[j].p3 - cb)).hypot() / scale;
+        ds * ds + dp * dp
+    };
+    // Number of lines among the first j segments of b.
+    let mut n_lines = vec![0; n + 1];
+    for j in 0..n {
+        n_lines[j + 1] = n_lines[j] + b.segs[j].ctrl.is_none() as usize;
+    }
+    // Cost of covering segments k..=j of b with segment i of a. A curve
+    // can't be split into lines, while promoting a line to a curve is ok
+    // but mildly discouraged.
+    let group_cost = |i: usize, k: usize, j: usize| {
+        let lines = n_lines[j + 1] - n_lines[k];
+        match a.segs[i].ctrl {
+            Some(_) if lines > 0 => MISMATCH_COST,
+            None if lines < j + 1 - k => 0.1,
+            _ => 0.0,
+        }
+    };
+    // dp[i][j]: best cost with end i of a mapped to end j of b.
+    let mut dp = vec![vec![f64::INFINITY; n]; m];
+    let mut back = vec![vec![0; n]; m];
+    for (j, c) in dp[0].iter_mut().enumerate() {
+        *c = group_cost(0, 0, j) + cost(0, j);
+    }
+    for i in 1..m {
+        for j in i..n {
+            for k in (i - 1)..j {
+                let c = dp[i - 1][k] + group_cost(i, k + 1, j) + cost(i, j);
+                if c < dp[i][j] {
+                    dp[i][j] = c;
+                    back[i][j] = k;
+                }
+            }
+        }
+    }
+    let mut result = vec![0; m];
+    let mut j = n - 1;
+    for i in (0..m).rev() {
+        result[i] = j;
+        if i > 0 {
+            j = back[i][j];
+        }
+    }
+    result
+}
+
+/// Map `p` by the similarity transform taking `b0`, `b1` to `a0`, `a1`.
+fn map_similar(p: Point, b0: Point, b1: Point, a0: Point, a1: Point) -> Point {
+    let vb = b1 - b0;
+    let va = a1 - a0;
+    let denom = vb.hypot2();
+    if denom < 1e-9 {
+        return a0 + (p - b0);
+    }
+    // Complex division va / vb.
+    let re = (va.x * vb.x + va.y * vb.y) / denom;
+    let im = (va.y * vb.x - va.x * vb.y) / denom;
+    let v = p - b0;
+    a0 + Vec2::new(re * v.x - im * v.y, im * v.x + re * v.y)
+}
+
+/// Subdivide `a` so that it has the same segment structure as `b`.
+fn equalize_segments(a: &Segments, b: &Segments) -> Result<Segments, String> {
+    let map = align(a, b);
+    let mut segs = Vec::with_capacity(b.segs.len());
+    let mut j0 = 0;
+    for (i, seg) in a.segs.iter().enumerate() {
+        let j1 = map[i] + 1;
+        let group = &b.segs[j0..j1];
+        let b_start = group[0].p0;
+        let b_end = group.last().unwrap().p3;
+        let mut ts = Vec::with_capacity(group.len() - 1);
+        let mut t0 = 0.0;
+        for b_seg in &group[..group.len() - 1] {
+            let target = map_similar(b_seg.p3, b_start, b_end, seg.p0, seg.p3);
+            let t = seg.nearest(target, t0);
+            // Keep parameters strictly increasing.
+            let t = t.max(t0 + 1e-3).min(1.0 - 1e-3);
+            ts.push(t);
+            t0 = t;
+        }
+        let any_curve = group.iter().any(|b_seg| b_seg.ctrl.is_some());
+        if seg.ctrl.is_some() && group.iter().any(|b_seg| b_seg.ctrl.is_none()) {
+            return Err(format!(
+                "segment {} is a curve, but the other layer has lines",
+                i
+            ));
+        }
+        let mut pieces = seg.split(&ts, any_curve);
+        for (k, (piece, b_seg)) in pieces.iter_mut().zip(group).enumerate() {
+            if seg.ctrl.is_none() && b_seg.ctrl.is_none() {
+                // Pieces of a line stay lines.
+                piece.ctrl = None;
+            }
+            if k + 1 < group.len() {
+                piece.end_type = b_seg.end_type;
+            }
+        }
+        segs.extend(pieces);
+        j0 = j1;
+    }
+    Ok(Segments {
+        segs,
+        start: a.start.clone(),
+        lead: a.lead,
+        closed: a.closed,
+    })
+}
+
+/// Subdivide contours in all layers of the glyph so that they have the same
+/// number of nodes as the corresponding contour with the most nodes.
+pub fn equalize_glyph(glyph: &mut Glyph, report: &mut CompatReport) {
+    let n_paths = glyph
+        .layers
+        .iter()
+        .filter_map(|layer| layer.paths.as_ref().map(Vec::len))
+        .max()
+        .unwrap_or(0);
+    for layer in &glyph.layers {
+        let len = layer.paths.as_ref().map(Vec::len).unwrap_or(0);
+        if len != n_paths {
+            report.unfixed.push(format!(
+                "{} layer {}: {} contours, expected {}",
+                glyph.glyphname, layer.layer_id, len, n_paths
+            ));
+            return;
+        }
+    }
+    for path_ix in 0..n_paths {
+        let segments: Vec<_> = glyph
+            .layers
+            .iter()
+            .map(|layer| Segments::new(&layer.paths.as_ref().unwrap()[path_ix]))
+            .collect();
+        if segments.iter().any(Option::is_none) {
+            report.unfixed.push(format!(
+                "{} contour {}: unsupported segment type",
+                glyph.glyphname, path_ix
+            ));
+            continue;
+        }
+        let segments: Vec<_> = segments.into_iter().map(Option::unwrap).collect();
+        let target_ix = (0..segments.len())
+            .max_by_key(|&i| segments[i].segs.len())
+            .unwrap();
+        let target = &segments[target_ix];
+        let target_id = glyph.layers[target_ix].layer_id.clone();
+        for (layer, a) in glyph.layers.iter_mut().zip(&segments) {
+            let what = format!(
+                "{} layer {} contour {}",
+                glyph.glyphname, layer.layer_id, path_ix
+            );
+            if a.closed != target.closed {
+                let state = |closed| if closed { "closed" } else { "open" };
+                report.unfixed.push(format!(
+                    "{}: {}, but {} in layer {}",
+                    what,
+                    state(a.closed),
+                    state(target.closed),
+                    target_id
+                ));
+                continue;
+            }
+            if a.segs.len() == target.segs.len() {
+                continue;
+            }
+            match equalize_segments(a, target) {
+                Ok(segs) => {
+                    let path = segs.to_path(target.lead);
+                    report.fixed.push(format!(
+                        "{}: subdivided from {} to {} segments",
+                        what,
+                        a.segs.len(),
+                        target.segs.len()
+                    ));
+                    layer.paths.as_mut().unwrap()[path_ix] = path;
+                }
+                Err(e) => report.unfixed.push(format!("{}: {}", what, e)),
+            }
+        }
+    }
+}
+
+/// Subdivide segments so that contours have the same number of nodes across
+/// layers. If `glyphnames` is nonempty, only those glyphs are touched.
+pub fn equalize_nodes(font: &mut Font, glyphnames: &[String]) -> CompatReport {
+    let mut report = CompatReport::default();
+    for glyph in &mut font.glyphs {
+        if glyphnames.is_empty() || glyphnames.contains(&glyph.glyphname) {
+            equalize_glyph(glyph, &mut report);
+        }
+    }
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::font::Layer;
+
+    fn polygon(closed: bool, pts: &[(f64, f64)]) -> Path {
+        let mut path = Path::new(closed);
+        for &pt in pts {
+            path.add(pt, NodeType::Line);
+        }
+        path
+    }
+
+    fn glyph(paths: Vec<Path>) -> Glyph {
+        let layers = paths
+            .into_iter()
+            .zip(&["light", "bold"])
+            .map(|(path, id)| Layer {
+                layer_id: id.to_string(),
+                width: 600.0,
+                paths: Some(vec![path]),
+                components: None,
+                anchors: None,
+                guide_lines: None,
+                other_stuff: HashMap::new(),
+            })
+            .collect();
+        Glyph {
+            layers,
+            glyphname: "a".to_string(),
+            other_stuff: HashMap::new(),
+        }
+    }
+
+    fn points(glyph: &Glyph, layer_id: &str) -> Vec<Point> {
+        let layer = glyph.get_layer(layer_id).unwrap();
+        let path = &layer.paths.as_ref().unwrap()[0];
+        path.nodes.iter().map(|node| node.pt).collect()
+    }
+
+    #[test]
+    fn subdivide_line() {
+        let light = polygon(
+            true,
+            &[(0.0, 0.0), (100.0, 0.0), (100.0, 100.0), (0.0, 100.0)],
+        );
+        let bold = polygon(
+            true,
+            &[
+                (0.0, 0.0),
+                (60.0, 0.0),
+                (120.0, 0.0),
+                (120.0, 120.0),
+                (0.0, 120.0),
+            ],
+        );
+        let mut glyph = glyph(vec![light, bold]);
+        let mut report = CompatReport::default();
+        equalize_glyph(&mut glyph, &mut report);
+        assert_eq!(
+            report.fixed,
+            vec!["a layer light contour 0: subdivided from 4 to 5 segments"]
+        );
+        assert!(report.unfixed.is_empty());
+        let pts = points(&glyph, "light");
+        assert_eq!(pts.len(), 5);
+        // The new node is on the bottom edge, near its middle.
+        let (start, rest) = pts.split_at(1);
+        assert_eq!(start[0], Point::new(0.0, 0.0));
+        assert!(rest[0].y.abs() < 1e-9 && (rest[0].x - 50.0).abs() < 1.0);
+        assert_eq!(
+            &rest[1..],
+            &[
+                Point::new(100.0, 0.0),
+                Point::new(100.0, 100.0),
+                Point::new(0.0, 100.0)
+            ]
+        );
+    }
+
+    #[test]
+    fn open_and_closed_contours() {
+        let light = polygon(false, &[(0.0, 0.0), (100.0, 0.0), (100.0, 100.0)]);
+        let bold = polygon(
+            true,
+            &[(0.0, 0.0), (120.0, 0.0), (120.0, 120.0), (0.0, 120.0)],
+        );
+        let mut glyph = glyph(vec![light.clone(), bold]);
+        let mut report = CompatReport::default();
+        equalize_glyph(&mut glyph, &mut report);
+        assert!(report.fixed.is_empty());
+        assert_eq!(
+            report.unfixed,
+            vec!["a layer light contour 0: open, but closed in layer bold"]
+        );
+        let unchanged: Vec<_> = light.nodes.iter().map(|node| node.pt).collect();
+        assert_eq!(points(&glyph, "light"), unchanged);
+    }
+}
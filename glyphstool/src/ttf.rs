@@ -0,0 +1,2495 @@
+//! Export to and import from variable TrueType fonts.
+//!
+//! On export, outlines are converted to quadratic (keeping masters
+//! compatible) and components are decomposed. Each glyph gets its own
+//! variation model over the masters and brace layers it has, and the deltas
+//! are written to gvar. On import, glyphs are evaluated at the masters'
+//! locations by applying the gvar deltas.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path as FsPath;
+
+use kurbo::{Affine, Point, Rect, Vec2};
+
+use crate::compat::CompatReport;
+use crate::cu2qu::glyph_to_quadratic;
+use crate::font::{
+    axes_parameter, instance_plist, Axis, Component, Font, FontMaster, Glyph, Layer, Node,
+    NodeType, Path,
+};
+use crate::iup::{iup_delta, iup_delta_optimize};
+use crate::plist::Plist;
+use crate::ufo::brace_name;
+use crate::varmodel::{normalize_value, support_scalar, Support, VariationModel};
+
+/// Options for variable TrueType export.
+#[derive(Clone, Debug)]
+pub struct TtfOptions {
+    /// Maximum error of the cubic to quadratic conversion, in font units.
+    pub cu2qu_tolerance: f64,
+    /// Drop deltas that interpolation of untouched points reproduces.
+    pub iup: bool,
+    /// Maximum error allowed for IUP, in font units.
+    pub iup_tolerance: f64,
+}
+
+impl Default for TtfOptions {
+    fn default() -> TtfOptions {
+        TtfOptions {
+            cu2qu_tolerance: 1.0,
+            iup: false,
+            iup_tolerance: 0.5,
+        }
+    }
+}
+
+/// Maximum depth of nested components.
+const MAX_COMPONENT_DEPTH: usize = 10;
+
+/// Number of phantom points appended to each glyph's points in gvar.
+const N_PHANTOM: usize = 4;
+
+/// A simple glyph outline: contours of (point, on-curve) pairs.
+#[derive(Clone, Default)]
+struct Outline {
+    contours: Vec<Vec<(Point, bool)>>,
+    advance: f64,
+}
+
+impl Outline {
+    fn points(&self) -> Vec<Point> {
+        self.contours.iter().flatten().map(|&(p, _)| p).collect()
+    }
+
+    fn is_compatible(&self, other: &Outline) -> bool {
+        self.contours.len() == other.contours.len()
+            && self
+                .contours
+                .iter()
+                .zip(&other.contours)
+                .all(|(a, b)| a.len() == b.len() && a.iter().zip(b).all(|(p, q)| p.1 == q.1))
+    }
+
+    fn bbox(&self) -> Option<Rect> {
+        let mut pts = self.points().into_iter();
+        let first = pts.next()?;
+        Some(pts.fold(Rect::from_points(first, first), |r, p| {
+            r.union(Rect::from_points(p, p))
+        }))
+    }
+}
+
+struct TtGlyph {
+    name: String,
+    unicodes: Vec<u32>,
+    /// The outline of the default master.
+    outline: Outline,
+    /// Deltas for each region, including phantom points; `None` entries
+    /// are left to IUP.
+    variations: Vec<(Support, Vec<Option<Vec2>>)>,
+}
+
+/// The design space: axis ranges in design and user coordinates.
+struct DesignSpace {
+    tags: Vec<String>,
+    names: Vec<String>,
+    /// (minimum, default, maximum) of each axis, in design coordinates.
+    design: Vec<(f64, f64, f64)>,
+    /// (minimum, default, maximum) of each axis, in user coordinates.
+    user: Vec<(f64, f64, f64)>,
+    /// (design, user) pairs for each axis, sorted, from master locations.
+    mapping: Vec<Vec<(f64, f64)>>,
+}
+
+fn axis_range(values: impl Iterator<Item = f64> + Clone, default: f64) -> (f64, f64, f64) {
+    let min = values.clone().fold(default, f64::min);
+    let max = values.fold(default, f64::max);
+    (min, default, max)
+}
+
+/// The user space location of a master, from its "Axis Location" custom
+/// parameter.
+fn user_location(master: &FontMaster, axis_names: &[String]) -> Option<Vec<f64>> {
+    let params = master.other_stuff.get("customParameters")?.as_array()?;
+    let locations = params
+        .iter()
+        .find(|p| p.get("name").and_then(Plist::as_str) == Some("Axis Location"))?
+        .get("value")?
+        .as_array()?;
+    axis_names
+        .iter()
+        .map(|name| {
+            locations
+                .iter()
+                .find(|l| l.get("Axis").and_then(Plist::as_str) == Some(name))?
+                .get("Location")?
+                .as_f64()
+        })
+        .collect()
+}
+
+impl DesignSpace {
+    fn new(font: &Font) -> Result<DesignSpace, String> {
+        if font.font_master.is_empty() {
+            return Err("font has no masters".to_string());
+        }
+        let axes = font.axes();
+        let names: Vec<String> = axes.iter().map(|a| a.name.clone()).collect();
+        let design_locs: Vec<Vec<f64>> =
+            font.font_master.iter().map(|m| m.location(&axes)).collect();
+        let user_locs: Vec<Vec<f64>> = font
+            .font_master
+            .iter()
+            .zip(&design_locs)
+            .map(|(m, design)| user_location(m, &names).unwrap_or_else(|| design.clone()))
+            .collect();
+        let mut design = Vec::new();
+        let mut user = Vec::new();
+        let mut mapping = Vec::new();
+        for i in 0..axes.len() {
+            design.push(axis_range(
+                design_locs.iter().map(|l| l[i]),
+                design_locs[0][i],
+            ));
+            user.push(axis_range(user_locs.iter().map(|l| l[i]), user_locs[0][i]));
+            let mut pairs: Vec<(f64, f64)> = design_locs
+                .iter()
+                .zip(&user_locs)
+                .map(|(d, u)| (d[i], u[i]))
+                .collect();
+            pairs.sort_by(|a, b| a.partial_cmp(b).unwrap());
+            pairs.dedup();
+            mapping.push(pairs);
+        }
+        Ok(DesignSpace {
+            tags: axes.iter().map(|a| a.tag.clone()).collect(),
+            names,
+            design,
+            user,
+            mapping,
+        })
+    }
+
+    fn normalize(&self, design_loc: &[f64]) -> Vec<f64> {
+        self.design
+            .iter()
+            .enumerate()
+            .map(|(i, &range)| {
+                normalize_value(design_loc.get(i).copied().unwrap_or(range.1), range)
+            })
+            .collect()
+    }
+
+    /// Map a design coordinate to user space, piecewise linearly between
+    /// masters.
+    fn design_to_user(&self, axis: usize, v: f64) -> f64 {
+        let pairs = &self.mapping[axis];
+        if pairs.len() < 2 {
+            return pairs.first().map(|p| v - p.0 + p.1).unwrap_or(v);
+        }
+        let k = pairs
+            .windows(2)
+            .position(|w| v <= w[1].0)
+            .unwrap_or(pairs.len() - 2);
+        let (d0, u0) = pairs[k];
+        let (d1, u1) = pairs[k + 1];
+        if d1 == d0 {
+            u0
+        } else {
+            u0 + (v - d0) * (u1 - u0) / (d1 - d0)
+        }
+    }
+
+    /// The avar segment maps, or `None` if design and user space are
+    /// related linearly on every axis.
+    fn avar_maps(&self) -> Option<Vec<Vec<(f64, f64)>>> {
+        let maps: Vec<Vec<(f64, f64)>> = (0..self.design.len())
+            .map(|i| {
+                let mut map = vec![(-1.0, -1.0), (0.0, 0.0), (1.0, 1.0)];
+                for &(d, u) in &self.mapping[i] {
+                    map.push((
+                        normalize_value(u, self.user[i]),
+                        normalize_value(d, self.design[i]),
+                    ));
+                }
+                map.sort_by(|a, b| a.partial_cmp(b).unwrap());
+                map.dedup_by(|a, b| (a.0 - b.0).abs() < 1e-9);
+                map
+            })
+            .collect();
+        let identity = maps
+            .iter()
+            .all(|map| map.iter().all(|&(from, to)| (from - to).abs() < 1e-6));
+        if identity {
+            None
+        } else {
+            Some(maps)
+        }
+    }
+}
+
+fn layer_outline(
+    glyphs: &HashMap<&str, &Glyph>,
+    glyph: &Glyph,
+    layer: &Layer,
+    fallback_id: &str,
+    transform: Affine,
+    depth: usize,
+    outline: &mut Outline,
+) -> Result<(), String> {
+    let flip = {
+        let c = transform.as_coeffs();
+        c[0] * c[3] - c[1] * c[2] < 0.0
+    };
+    for path in layer.paths.iter().flatten().filter(|p| p.closed) {
+        let mut contour: Vec<(Point, bool)> = path
+            .nodes
+            .iter()
+            .map(|n| (transform * n.pt, n.node_type != NodeType::OffCurve))
+            .collect();
+        if path
+            .nodes
+            .iter()
+            .any(|n| n.node_type == NodeType::Curve || n.node_type == NodeType::CurveSmooth)
+        {
+            return Err(format!("{}: cubic curves remain", glyph.glyphname));
+        }
+        // TrueType contours run in the opposite direction to PostScript.
+        if !flip {
+            contour.reverse();
+        }
+        outline.contours.push(contour);
+    }
+    for component in layer.components.iter().flatten() {
+        if depth >= MAX_COMPONENT_DEPTH {
+            return Err(format!("{}: components nested too deeply", glyph.glyphname));
+        }
+        let base = glyphs
+            .get(component.name.as_str())
+            .ok_or_else(|| format!("{}: missing component {}", glyph.glyphname, component.name))?;
+        let base_layer = base
+            .get_layer(&layer.layer_id)
+            .or_else(|| base.get_layer(fallback_id))
+            .ok_or_else(|| {
+                format!(
+                    "{}: component {} lacks layer",
+                    glyph.glyphname, base.glyphname
+                )
+            })?;
+        let component_transform = transform * component.transform.unwrap_or_default();
+        layer_outline(
+            glyphs,
+            base,
+            base_layer,
+            fallback_id,
+            component_transform,
+            depth + 1,
+            outline,
+        )?;
+    }
+    Ok(())
+}
+
+fn round_outline(outline: &mut Outline) {
+    for contour in &mut outline.contours {
+        for (p, _) in contour {
+            *p = Point::new(p.x.round(), p.y.round());
+        }
+    }
+    outline.advance = outline.advance.round();
+}
+
+fn unicodes(glyph: &Glyph) -> Vec<u32> {
+    match glyph.other_stuff.get("unicode") {
+        Some(Plist::String(s)) => s
+            .split(',')
+            .filter_map(|u| u32::from_str_radix(u.trim(), 16).ok())
+            .collect(),
+        // Hex values that happen to be all digits parse as integers.
+        Some(Plist::Integer(i)) => u32::from_str_radix(&i.to_string(), 16)
+            .into_iter()
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+fn is_exported(glyph: &Glyph) -> bool {
+    glyph.other_stuff.get("export").and_then(Plist::as_i64) != Some(0)
+}
+
+/// Gather the outlines and deltas of all exported glyphs, with .notdef
+/// first as TrueType requires.
+fn build_glyphs(
+    font: &Font,
+    space: &DesignSpace,
+    default_id: &str,
+    options: &TtfOptions,
+    report: &mut CompatReport,
+) -> Vec<TtGlyph> {
+    let master_ids: Vec<&str> = font.font_master.iter().map(|m| m.id.as_str()).collect();
+    let n_axes = space.design.len();
+    let axes = font.axes();
+    let master_locs: HashMap<&str, Vec<f64>> = font
+        .font_master
+        .iter()
+        .map(|m| (m.id.as_str(), m.location(&axes)))
+        .collect();
+
+    let converted: Vec<Glyph> = font
+        .glyphs
+        .iter()
+        .map(|glyph| {
+            let mut glyph = glyph.clone();
+            let mut glyph_report = CompatReport::default();
+            glyph_to_quadratic(
+                &mut glyph,
+                &master_ids,
+                options.cu2qu_tolerance,
+                &mut glyph_report,
+            );
+            if !glyph_report.unfixed.is_empty() {
+                // Fall back to converting the default master on its own.
+                glyph.layers.retain(|l| l.layer_id == default_id);
+                glyph_to_quadratic(
+                    &mut glyph,
+                    &[default_id],
+                    options.cu2qu_tolerance,
+                    &mut glyph_report,
+                );
+                report.unfixed.extend(glyph_report.unfixed);
+            }
+            glyph
+        })
+        .collect();
+    let by_name: HashMap<&str, &Glyph> = converted
+        .iter()
+        .map(|g| (g.glyphname.as_str(), g))
+        .collect();
+
+    let mut result = Vec::new();
+    for glyph in converted.iter().filter(|g| is_exported(g)) {
+        // The layers taking part in interpolation, with their locations.
+        let mut layers = Vec::new();
+        for layer in &glyph.layers {
+            if let Some(loc) = master_locs.get(layer.layer_id.as_str()) {
+                layers.push((layer, loc.clone(), layer.layer_id.as_str()));
+            } else if let Some(coords) = layer.brace_coords() {
+                let master_id = layer
+                    .other_stuff
+                    .get("associatedMasterId")
+                    .and_then(Plist::as_str)
+                    .unwrap_or(default_id);
+                let master_loc = &master_locs[master_id];
+                let loc = (0..n_axes)
+                    .map(|i| coords.get(i).copied().unwrap_or(master_loc[i]))
+                    .collect();
+                layers.push((layer, loc, master_id));
+            }
+        }
+        // Put the default master first.
+        layers.sort_by_key(|(layer, _, _)| layer.layer_id != default_id);
+        if layers
+            .first()
+            .map(|l| l.0.layer_id != default_id)
+            .unwrap_or(true)
+        {
+            report
+                .unfixed
+                .push(format!("{}: no default master layer", glyph.glyphname));
+            continue;
+        }
+        let mut outlines = Vec::new();
+        for (layer, _, fallback_id) in &layers {
+            let mut outline = Outline {
+                contours: Vec::new(),
+                advance: layer.width,
+            };
+            match layer_outline(
+                &by_name,
+                glyph,
+                layer,
+                fallback_id,
+                Affine::default(),
+                0,
+                &mut outline,
+            ) {
+                Ok(()) => {
+                    round_outline(&mut outline);
+                    outlines.push(outline);
+                }
+                Err(e) => {
+                    report.unfixed.push(e);
+                    break;
+                }
+            }
+        }
+        if outlines.is_empty() {
+            continue;
+        }
+        let default = outlines[0].clone();
+        let compatible =
+            outlines.len() == layers.len() && outlines.iter().all(|o| o.is_compatible(&default));
+        let mut variations = Vec::new();
+        if !compatible {
+            report.unfixed.push(format!(
+                "{}: incompatible layers, exported without variations",
+                glyph.glyphname
+            ));
+        } else if layers.len() > 1 {
+            let locations: Vec<Vec<f64>> = layers
+                .iter()
+                .map(|(_, loc, _)| space.normalize(loc))
+                .collect();
+            match VariationModel::new(&locations) {
+                Ok(model) => {
+                    variations = glyph_deltas(&model, &outlines, options);
+                }
+                Err(e) => report.unfixed.push(format!("{}: {}", glyph.glyphname, e)),
+            }
+        }
+        result.push(TtGlyph {
+            name: glyph.glyphname.clone(),
+            unicodes: unicodes(glyph),
+            outline: default,
+            variations,
+        });
+    }
+    let notdef = match result.iter().position(|g| g.name == ".notdef") {
+        Some(i) => result.remove(i),
+        None => TtGlyph {
+            name: ".notdef".to_string(),
+            unicodes: Vec::new(),
+            outline: Outline {
+                contours: Vec::new(),
+                advance: 500.0,
+            },
+            variations: Vec::new(),
+        },
+    };
+    result.insert(0, notdef);
+    result
+}
+
+/// Points including the four phantom points, which carry the advance.
+fn points_with_phantoms(outline: &Outline) -> Vec<Point> {
+    let mut pts = outline.points();
+    pts.push(Point::new(0.0, 0.0));
+    pts.push(Point::new(outline.advance, 0.0));
+    pts.push(Point::new(0.0, 0.0));
+    pts.push(Point::new(0.0, 0.0));
+    pts
+}
+
+fn glyph_deltas(
+    model: &VariationModel,
+    outlines: &[Outline],
+    options: &TtfOptions,
+) -> Vec<(Support, Vec<Option<Vec2>>)> {
+    let points: Vec<Vec<Point>> = outlines.iter().map(points_with_phantoms).collect();
+    let n = points[0].len();
+    // Deltas per point, each in model order.
+    let per_point: Vec<Vec<Vec2>> = (0..n)
+        .map(|i| {
+            let values: Vec<Vec2> = points.iter().map(|p| p[i].to_vec2()).collect();
+            model.get_deltas(&values)
+        })
+        .collect();
+    let default_points = &points[model.reverse_mapping[0]];
+    let mut ends = Vec::new();
+    let mut end = 0;
+    for contour in &outlines[0].contours {
+        end += contour.len();
+        ends.push(end - 1);
+    }
+    for _ in 0..N_PHANTOM {
+        end += 1;
+        ends.push(end - 1);
+    }
+    let mut result = Vec::new();
+    for (k, support) in model.supports.iter().enumerate().skip(1) {
+        let deltas: Vec<Vec2> = per_point
+            .iter()
+            .map(|d| Vec2::new(d[k].x.round(), d[k].y.round()))
+            .collect();
+        let deltas: Vec<Option<Vec2>> = if options.iup {
+            iup_delta_optimize(&deltas, default_points, &ends, options.iup_tolerance)
+        } else {
+            deltas.into_iter().map(Some).collect()
+        };
+        let all_zero = deltas
+            .iter()
+            .all(|d| d.map(|d| d.x == 0.0 && d.y == 0.0).unwrap_or(true));
+        if !all_zero {
+            result.push((support.clone(), deltas));
+        }
+    }
+    result
+}
+
+/// Big-endian binary writing.
+trait WriteBe {
+    fn u8(&mut self, v: u8);
+    fn u16(&mut self, v: u16);
+    fn i16(&mut self, v: i16);
+    fn u32(&mut self, v: u32);
+    fn fixed(&mut self, v: f64);
+    fn f2dot14(&mut self, v: f64);
+    fn tag(&mut self, tag: &str);
+    fn pad4(&mut self);
+}
+
+impl WriteBe for Vec<u8> {
+    fn u8(&mut self, v: u8) {
+        self.push(v);
+    }
+
+    fn u16(&mut self, v: u16) {
+        self.extend_from_slice(&v.to_be_bytes());
+    }
+
+    fn i16(&mut self, v: i16) {
+        self.extend_from_slice(&v.to_be_bytes());
+    }
+
+    fn u32(&mut self, v: u32) {
+        self.extend_from_slice(&v.to_be_bytes());
+    }
+
+    fn fixed(&mut self, v: f64) {
+        self.extend_from_slice(&((v * 65536.0).round() as i32).to_be_bytes());
+    }
+
+    fn f2dot14(&mut self, v: f64) {
+        self.i16((v * 16384.0).round() as i16);
+    }
+
+    fn tag(&mut self, tag: &str) {
+        let mut bytes = [b' '; 4];
+        for (b, c) in bytes.iter_mut().zip(tag.bytes()) {
+            *b = c;
+        }
+        self.extend_from_slice(&bytes);
+    }
+
+    fn pad4(&mut self) {
+        while self.len() & 3 != 0 {
+            self.push(0);
+        }
+    }
+}
+
+fn clamp_i16(v: f64) -> i16 {
+    v.round().clamp(-32768.0, 32767.0) as i16
+}
+
+const ON_CURVE: u8 = 0x01;
+const X_SHORT: u8 = 0x02;
+const Y_SHORT: u8 = 0x04;
+const X_SAME_OR_POSITIVE: u8 = 0x10;
+const Y_SAME_OR_POSITIVE: u8 = 0x20;
+
+/// Encode a simple glyph for the glyf table. Empty glyphs have no data.
+fn encode_glyph(outline: &Outline) -> Vec<u8> {
+    let bbox = match outline.bbox() {
+        Some(bbox) => bbox,
+        None => return Vec::new(),
+    };
+    let mut data = Vec::new();
+    data.i16(outline.contours.len() as i16);
+    data.i16(clamp_i16(bbox.x0));
+    data.i16(clamp_i16(bbox.y0));
+    data.i16(clamp_i16(bbox.x1));
+    data.i16(clamp_i16(bbox.y1));
+    let mut end = 0;
+    for contour in &outline.contours {
+        end += contour.len();
+        data.u16((end - 1) as u16);
+    }
+    // No instructions.
+    data.u16(0);
+    let mut flags = Vec::new();
+    let mut xs = Vec::new();
+    let mut ys = Vec::new();
+    let mut last = (0i32, 0i32);
+    for &(p, on_curve) in outline.contours.iter().flatten() {
+        let (x, y) = (p.x as i32, p.y as i32);
+        let (dx, dy) = (x - last.0, y - last.1);
+        last = (x, y);
+        let mut flag = if on_curve { ON_CURVE } else { 0 };
+        if dx == 0 {
+            flag |= X_SAME_OR_POSITIVE;
+        } else if dx.abs() < 256 {
+            flag |= X_SHORT;
+            if dx > 0 {
+                flag |= X_SAME_OR_POSITIVE;
+            }
+            xs.u8(dx.unsigned_abs() as u8);
+        } else {
+            xs.i16(dx as i16);
+        }
+        if dy == 0 {
+            flag |= Y_SAME_OR_POSITIVE;
+        } else if dy.abs() < 256 {
+            flag |= Y_SHORT;
+            if dy > 0 {
+                flag |= Y_SAME_OR_POSITIVE;
+            }
+            ys.u8(dy.unsigned_abs() as u8);
+        } else {
+            ys.i16(dy as i16);
+        }
+        flags.push(flag);
+    }
+    data.extend(flags);
+    data.extend(xs);
+    data.extend(ys);
+    data
+}
+
+/// Encode packed point numbers; an empty list means all points.
+fn pack_points(points: &[usize]) -> Vec<u8> {
+    let mut data = Vec::new();
+    if points.len() < 0x80 {
+        data.u8(points.len() as u8);
+    } else {
+        data.u16(points.len() as u16 | 0x8000);
+    }
+    let mut last = 0;
+    let diffs: Vec<usize> = points
+        .iter()
+        .map(|&p| {
+            let d = p - last;
+            last = p;
+            d
+        })
+        .collect();
+    for run in diffs.chunks(128) {
+        if run.iter().all(|&d| d < 256) {
+            data.u8(run.len() as u8 - 1);
+            for &d in run {
+                data.u8(d as u8);
+            }
+        } else {
+            data.u8(0x80 | (run.len() as u8 - 1));
+            for &d in run {
+                data.u16(d as u16);
+            }
+        }
+    }
+    data
+}
+
+const DELTAS_ARE_ZERO: u8 = 0x80;
+const DELTAS_ARE_WORDS: u8 = 0x40;
+const MAX_DELTA_RUN: usize = 64;
+
+/// Encode packed deltas, choosing runs of zeros, bytes, or words.
+fn pack_deltas(deltas: &[i16], data: &mut Vec<u8>) {
+    let mut i = 0;
+    while i < deltas.len() {
+        let kind = |d: i16| {
+            if d == 0 {
+                0
+            } else if (-128..=127).contains(&d) {
+                1
+            } else {
+                2
+            }
+        };
+        let k = kind(deltas[i]);
+        let mut j = i + 1;
+        while j < deltas.len() && j - i < MAX_DELTA_RUN && kind(deltas[j]) == k {
+            j += 1;
+        }
+        let run = &deltas[i..j];
+        match k {
+            0 => data.u8(DELTAS_ARE_ZERO | (run.len() as u8 - 1)),
+            1 => {
+                data.u8(run.len() as u8 - 1);
+                for &d in run {
+                    data.push(d as i8 as u8);
+                }
+            }
+            _ => {
+                data.u8(DELTAS_ARE_WORDS | (run.len() as u8 - 1));
+                for &d in run {
+                    data.i16(d);
+                }
+            }
+        }
+        i = j;
+    }
+}
+
+const EMBEDDED_PEAK_TUPLE: u16 = 0x8000;
+const INTERMEDIATE_REGION: u16 = 0x4000;
+const PRIVATE_POINT_NUMBERS: u16 = 0x2000;
+
+/// Encode the variation data of one glyph for gvar.
+fn encode_glyph_variations(variations: &[(Support, Vec<Option<Vec2>>)], n_axes: usize) -> Vec<u8> {
+    if variations.is_empty() {
+        return Vec::new();
+    }
+    let mut headers = Vec::new();
+    let mut serialized = Vec::new();
+    for (support, deltas) in variations {
+        let region: Vec<(f64, f64, f64)> = (0..n_axes)
+            .map(|i| support.get(i).copied().flatten().unwrap_or((0.0, 0.0, 0.0)))
+            .collect();
+        let intermediate = region
+            .iter()
+            .any(|&(lower, peak, upper)| lower != peak.min(0.0) || upper != peak.max(0.0));
+        let start = serialized.len();
+        let points: Vec<usize> = if deltas.iter().all(Option::is_some) {
+            Vec::new()
+        } else {
+            (0..deltas.len()).filter(|&i| deltas[i].is_some()).collect()
+        };
+        serialized.extend(pack_points(&points));
+        let present: Vec<Vec2> = deltas.iter().filter_map(|&d| d).collect();
+        let xs: Vec<i16> = present.iter().map(|d| clamp_i16(d.x)).collect();
+        let ys: Vec<i16> = present.iter().map(|d| clamp_i16(d.y)).collect();
+        pack_deltas(&xs, &mut serialized);
+        pack_deltas(&ys, &mut serialized);
+        headers.u16((serialized.len() - start) as u16);
+        let mut flags = EMBEDDED_PEAK_TUPLE | PRIVATE_POINT_NUMBERS;
+        if intermediate {
+            flags |= INTERMEDIATE_REGION;
+        }
+        headers.u16(flags);
+        for &(_, peak, _) in &region {
+            headers.f2dot14(peak);
+        }
+        if intermediate {
+            for &(lower, _, _) in &region {
+                headers.f2dot14(lower);
+            }
+            for &(_, _, upper) in &region {
+                headers.f2dot14(upper);
+            }
+        }
+    }
+    let mut data = Vec::new();
+    data.u16(variations.len() as u16);
+    data.u16((4 + headers.len()) as u16);
+    data.extend(headers);
+    data.extend(serialized);
+    if data.len() % 2 != 0 {
+        data.push(0);
+    }
+    data
+}
+
+fn int_value(plist: &HashMap<String, Plist>, key: &str, default: i64) -> i64 {
+    plist.get(key).and_then(Plist::as_i64).unwrap_or(default)
+}
+
+/// Font-wide values needed by several tables.
+struct Metrics {
+    family: String,
+    upm: u16,
+    version: (i64, i64),
+    ascender: i16,
+    descender: i16,
+    x_height: i16,
+    cap_height: i16,
+    bbox: Rect,
+}
+
+fn head_table(metrics: &Metrics) -> Vec<u8> {
+    let mut t = Vec::new();
+    t.u32(0x0001_0000);
+    t.fixed(metrics.version.0 as f64 + metrics.version.1 as f64 / 1000.0);
+    // checkSumAdjustment, filled in once the whole file is assembled.
+    t.u32(0);
+    t.u32(0x5F0F_3CF5);
+    t.u16(0x0003);
+    t.u16(metrics.upm);
+    // Created and modified dates.
+    t.extend(&[0; 16]);
+    t.i16(clamp_i16(metrics.bbox.x0));
+    t.i16(clamp_i16(metrics.bbox.y0));
+    t.i16(clamp_i16(metrics.bbox.x1));
+    t.i16(clamp_i16(metrics.bbox.y1));
+    // macStyle, lowestRecPPEM, fontDirectionHint.
+    t.u16(0);
+    t.u16(6);
+    t.i16(2);
+    // Long loca offsets, current glyph data format.
+    t.i16(1);
+    t.i16(0);
+    t
+}
+
+fn hhea_table(metrics: &Metrics, glyphs: &[TtGlyph]) -> Vec<u8> {
+    let mut advance_max = 0.0f64;
+    let mut min_lsb = 0.0f64;
+    let mut min_rsb = 0.0f64;
+    let mut x_max_extent = 0.0f64;
+    for glyph in glyphs {
+        advance_max = advance_max.max(glyph.outline.advance);
+        if let Some(bbox) = glyph.outline.bbox() {
+            min_lsb = min_lsb.min(bbox.x0);
+            min_rsb = min_rsb.min(glyph.outline.advance - bbox.x1);
+            x_max_extent = x_max_extent.max(bbox.x1);
+        }
+    }
+    let mut t = Vec::new();
+    t.u32(0x0001_0000);
+    t.i16(metrics.ascender);
+    t.i16(metrics.descender);
+    t.i16(0);
+    t.u16(advance_max.clamp(0.0, 65535.0) as u16);
+    t.i16(clamp_i16(min_lsb));
+    t.i16(clamp_i16(min_rsb));
+    t.i16(clamp_i16(x_max_extent));
+    // Caret slope rise and run, caret offset, four reserved fields.
+    t.i16(1);
+    t.i16(0);
+    t.extend(&[0; 10]);
+    // metricDataFormat, then every glyph has a full metric.
+    t.i16(0);
+    t.u16(glyphs.len() as u16);
+    t
+}
+
+fn maxp_table(glyphs: &[TtGlyph]) -> Vec<u8> {
+    let max_points = glyphs
+        .iter()
+        .map(|g| g.outline.contours.iter().map(Vec::len).sum::<usize>())
+        .max()
+        .unwrap_or(0);
+    let max_contours = glyphs
+        .iter()
+        .map(|g| g.outline.contours.len())
+        .max()
+        .unwrap_or(0);
+    let mut t = Vec::new();
+    t.u32(0x0001_0000);
+    t.u16(glyphs.len() as u16);
+    t.u16(max_points as u16);
+    t.u16(max_contours as u16);
+    // No composites.
+    t.u16(0);
+    t.u16(0);
+    // maxZones, then no hinting resources.
+    t.u16(2);
+    t.extend(&[0; 16]);
+    t
+}
+
+fn hmtx_table(glyphs: &[TtGlyph]) -> Vec<u8> {
+    let mut t = Vec::new();
+    for glyph in glyphs {
+        let lsb = glyph.outline.bbox().map(|b| b.x0).unwrap_or(0.0);
+        t.u16(glyph.outline.advance.clamp(0.0, 65535.0) as u16);
+        t.i16(clamp_i16(lsb));
+    }
+    t
+}
+
+/// The glyf and loca tables.
+fn glyf_loca_tables(glyphs: &[TtGlyph]) -> (Vec<u8>, Vec<u8>) {
+    let mut glyf = Vec::new();
+    let mut loca = Vec::new();
+    for glyph in glyphs {
+        loca.u32(glyf.len() as u32);
+        glyf.extend(encode_glyph(&glyph.outline));
+        glyf.pad4();
+    }
+    loca.u32(glyf.len() as u32);
+    (glyf, loca)
+}
+
+fn cmap_table(glyphs: &[TtGlyph]) -> Vec<u8> {
+    let mut mapping: Vec<(u32, u16)> = glyphs
+        .iter()
+        .enumerate()
+        .flat_map(|(gid, g)| g.unicodes.iter().map(move |&u| (u, gid as u16)))
+        .collect();
+    mapping.sort();
+    mapping.dedup_by_key(|m| m.0);
+    // Runs of consecutive characters mapping to consecutive glyphs.
+    let runs = |chars: &[(u32, u16)]| {
+        let mut runs: Vec<(u32, u32, u16)> = Vec::new();
+        for &(u, gid) in chars {
+            match runs.last_mut() {
+                Some(run) if run.1 + 1 == u && u32::from(run.2) + u - run.0 == u32::from(gid) => {
+                    run.1 = u;
+                }
+                _ => runs.push((u, u, gid)),
+            }
+        }
+        runs
+    };
+
+    let bmp: Vec<(u32, u16)> = mapping.iter().copied().filter(|m| m.0 < 0xFFFF).collect();
+    let mut segments = runs(&bmp);
+    segments.push((0xFFFF, 0xFFFF, 0));
+    let seg_count = segments.len() as u16;
+    let mut format4 = Vec::new();
+    format4.u16(4);
+    format4.u16((16 + 8 * segments.len()) as u16);
+    format4.u16(0);
+    let (search_range, entry_selector, range_shift) = binary_search_params(seg_count, 2);
+    format4.u16(seg_count * 2);
+    format4.u16(search_range);
+    format4.u16(entry_selector);
+    format4.u16(range_shift);
+    for &(_, end, _) in &segments {
+        format4.u16(end as u16);
+    }
+    format4.u16(0);
+    for &(start, _, _) in &segments {
+        format4.u16(start as u16);
+    }
+    for &(start, _, gid) in &segments {
+        let delta = if start == 0xFFFF {
+            1
+        } else {
+            gid.wrapping_sub(start as u16)
+        };
+        format4.u16(delta);
+    }
+    for _ in &segments {
+        format4.u16(0);
+    }
+
+    let groups = runs(&mapping);
+    let mut format12 = Vec::new();
+    format12.u16(12);
+    format12.u16(0);
+    format12.u32((16 + 12 * groups.len()) as u32);
+    format12.u32(0);
+    format12.u32(groups.len() as u32);
+    for &(start, end, gid) in &groups {
+        format12.u32(start);
+        format12.u32(end);
+        format12.u32(u32::from(gid));
+    }
+
+    let has_supplementary = mapping.iter().any(|m| m.0 > 0xFFFF);
+    let mut records: Vec<(u16, u16, bool)> = vec![(0, 3, false), (3, 1, false)];
+    if has_supplementary {
+        records = vec![(0, 3, false), (0, 4, true), (3, 1, false), (3, 10, true)];
+    }
+    let header_len = 4 + 8 * records.len();
+    let mut t = Vec::new();
+    t.u16(0);
+    t.u16(records.len() as u16);
+    for &(platform, encoding, is_format12) in &records {
+        t.u16(platform);
+        t.u16(encoding);
+        let offset = if is_format12 {
+            header_len + format4.len()
+        } else {
+            header_len
+        };
+        t.u32(offset as u32);
+    }
+    t.extend(format4);
+    if has_supplementary {
+        t.extend(format12);
+    }
+    t
+}
+
+/// searchRange, entrySelector and rangeShift for binary searches over `n`
+/// items of `size` bytes.
+fn binary_search_params(n: u16, size: u16) -> (u16, u16, u16) {
+    let mut entry_selector = 0;
+    while (2u32 << entry_selector) <= u32::from(n) {
+        entry_selector += 1;
+    }
+    let search_range = (1 << entry_selector) * size;
+    (search_range, entry_selector, n * size - search_range)
+}
+
+fn name_table(names: &[(u16, String)]) -> Vec<u8> {
+    let mut strings = Vec::new();
+    let mut records = Vec::new();
+    for (name_id, s) in names {
+        let offset = strings.len();
+        for unit in s.encode_utf16() {
+            strings.u16(unit);
+        }
+        // Windows, Unicode BMP, English (US).
+        records.u16(3);
+        records.u16(1);
+        records.u16(0x409);
+        records.u16(*name_id);
+        records.u16((strings.len() - offset) as u16);
+        records.u16(offset as u16);
+    }
+    let mut t = Vec::new();
+    t.u16(0);
+    t.u16(names.len() as u16);
+    t.u16((6 + records.len()) as u16);
+    t.extend(records);
+    t.extend(strings);
+    t
+}
+
+fn post_table(glyphs: &[TtGlyph]) -> Vec<u8> {
+    let mut t = Vec::new();
+    t.u32(0x0002_0000);
+    // italicAngle, underlinePosition, underlineThickness, isFixedPitch,
+    // and memory usage hints.
+    t.fixed(0.0);
+    t.i16(-100);
+    t.i16(50);
+    t.extend(&[0; 20]);
+    t.u16(glyphs.len() as u16);
+    // No use is made of the standard Macintosh names.
+    for i in 0..glyphs.len() {
+        t.u16(258 + i as u16);
+    }
+    for glyph in glyphs {
+        let name: Vec<u8> = glyph
+            .name
+            .bytes()
+            .filter(u8::is_ascii_graphic)
+            .take(63)
+            .collect();
+        t.u8(name.len() as u8);
+        t.extend(name);
+    }
+    t
+}
+
+fn os2_table(metrics: &Metrics, glyphs: &[TtGlyph], weight_class: u16) -> Vec<u8> {
+    let advances: Vec<f64> = glyphs
+        .iter()
+        .map(|g| g.outline.advance)
+        .filter(|&a| a > 0.0)
+        .collect();
+    let avg_width = if advances.is_empty() {
+        0.0
+    } else {
+        advances.iter().sum::<f64>() / advances.len() as f64
+    };
+    let chars: Vec<u32> = glyphs
+        .iter()
+        .flat_map(|g| g.unicodes.iter().copied())
+        .collect();
+    let first_char = chars.iter().copied().min().unwrap_or(0).min(0xFFFF) as u16;
+    let last_char = chars.iter().copied().max().unwrap_or(0).min(0xFFFF) as u16;
+    let upm = f64::from(metrics.upm);
+    let mut t = Vec::new();
+    t.u16(4);
+    t.i16(clamp_i16(avg_width));
+    t.u16(weight_class);
+    // Medium width, installable embedding.
+    t.u16(5);
+    t.u16(0);
+    // Subscript and superscript size and offset.
+    for &v in &[0.65, 0.6, 0.0, 0.075, 0.65, 0.6, 0.0, 0.35] {
+        t.i16(clamp_i16(v * upm));
+    }
+    // Strikeout size and position.
+    t.i16(clamp_i16(0.05 * upm));
+    t.i16(clamp_i16(0.22 * upm));
+    // Family class, PANOSE, Unicode ranges.
+    t.extend(&[0; 28]);
+    t.tag("NONE");
+    // fsSelection: REGULAR, USE_TYPO_METRICS.
+    t.u16(0x00C0);
+    t.u16(first_char);
+    t.u16(last_char);
+    t.i16(metrics.ascender);
+    t.i16(metrics.descender);
+    t.i16(0);
+    t.u16(metrics.bbox.y1.clamp(0.0, 65535.0) as u16);
+    t.u16((-metrics.bbox.y0).clamp(0.0, 65535.0) as u16);
+    // Code page ranges: Latin 1.
+    t.u32(1);
+    t.u32(0);
+    t.i16(metrics.x_height);
+    t.i16(metrics.cap_height);
+    // Default char, break char, max context.
+    t.u16(0);
+    t.u16(32);
+    t.u16(0);
+    t
+}
+
+fn fvar_table(space: &DesignSpace, instances: &[(u16, Vec<f64>)]) -> Vec<u8> {
+    let n_axes = space.tags.len();
+    let mut t = Vec::new();
+    t.u16(1);
+    t.u16(0);
+    // Offset to axes, reserved.
+    t.u16(16);
+    t.u16(2);
+    t.u16(n_axes as u16);
+    t.u16(20);
+    t.u16(instances.len() as u16);
+    t.u16((4 + 4 * n_axes) as u16);
+    for (i, tag) in space.tags.iter().enumerate() {
+        let (min, default, max) = space.user[i];
+        t.tag(tag);
+        t.fixed(min);
+        t.fixed(default);
+        t.fixed(max);
+        t.u16(0);
+        t.u16(256 + i as u16);
+    }
+    for (name_id, coords) in instances {
+        t.u16(*name_id);
+        t.u16(0);
+        for &c in coords {
+            t.fixed(c);
+        }
+    }
+    t
+}
+
+fn avar_table(maps: &[Vec<(f64, f64)>]) -> Vec<u8> {
+    let mut t = Vec::new();
+    t.u16(1);
+    t.u16(0);
+    t.u16(0);
+    t.u16(maps.len() as u16);
+    for map in maps {
+        t.u16(map.len() as u16);
+        for &(from, to) in map {
+            t.f2dot14(from);
+            t.f2dot14(to);
+        }
+    }
+    t
+}
+
+fn gvar_table(glyphs: &[TtGlyph], n_axes: usize) -> Vec<u8> {
+    let data: Vec<Vec<u8>> = glyphs
+        .iter()
+        .map(|g| encode_glyph_variations(&g.variations, n_axes))
+        .collect();
+    let header_len = 20 + 4 * (glyphs.len() + 1);
+    let mut t = Vec::new();
+    t.u16(1);
+    t.u16(0);
+    t.u16(n_axes as u16);
+    // No shared tuples.
+    t.u16(0);
+    t.u32(header_len as u32);
+    t.u16(glyphs.len() as u16);
+    // Long offsets.
+    t.u16(1);
+    t.u32(header_len as u32);
+    let mut offset = 0;
+    for d in &data {
+        t.u32(offset as u32);
+        offset += d.len();
+    }
+    t.u32(offset as u32);
+    for d in data {
+        t.extend(d);
+    }
+    t
+}
+
+fn checksum(data: &[u8]) -> u32 {
+    data.chunks(4).fold(0u32, |sum, chunk| {
+        let mut word = [0u8; 4];
+        word[..chunk.len()].copy_from_slice(chunk);
+        sum.wrapping_add(u32::from_be_bytes(word))
+    })
+}
+
+/// Assemble tables into a font file, filling in the head checksum.
+fn assemble(mut tables: Vec<(&str, Vec<u8>)>) -> Vec<u8> {
+    tables.sort_by(|a, b| a.0.cmp(b.0));
+    let n = tables.len() as u16;
+    let mut out = Vec::new();
+    out.u32(0x0001_0000);
+    out.u16(n);
+    let (search_range, entry_selector, range_shift) = binary_search_params(n, 16);
+    out.u16(search_range);
+    out.u16(entry_selector);
+    out.u16(range_shift);
+    let mut offset = 12 + 16 * tables.len();
+    let mut head_offset = None;
+    for (tag, data) in &tables {
+        out.tag(tag);
+        out.u32(checksum(data));
+        out.u32(offset as u32);
+        out.u32(data.len() as u32);
+        if *tag == "head" {
+            head_offset = Some(offset);
+        }
+        offset += (data.len() + 3) & !3;
+    }
+    for (_, data) in &tables {
+        out.extend(data);
+        out.pad4();
+    }
+    if let Some(head_offset) = head_offset {
+        let adjustment = 0xB1B0_AFBAu32.wrapping_sub(checksum(&out));
+        out[head_offset + 8..head_offset + 12].copy_from_slice(&adjustment.to_be_bytes());
+    }
+    out
+}
+
+/// PostScript names are limited to printable ASCII, without spaces or
+/// certain punctuation.
+fn postscript_name(s: &str) -> String {
+    s.chars()
+        .filter(|c| c.is_ascii_graphic() && !"[](){}<>/%".contains(*c))
+        .collect()
+}
+
+/// Build a variable TrueType font from the masters and brace layers of a
+/// font. The first master is the default. Problems with individual glyphs
+/// are recorded in the report; such glyphs are exported without variations
+/// or left out. A font without masters is an error.
+pub fn export_variable_ttf(
+    font: &Font,
+    options: &TtfOptions,
+    report: &mut CompatReport,
+) -> Result<Vec<u8>, String> {
+    let space = DesignSpace::new(font)?;
+    let n_axes = space.tags.len();
+    let default_master = match font.font_master.first() {
+        Some(master) => master,
+        None => return Err("font has no masters".to_string()),
+    };
+    let glyphs = build_glyphs(font, &space, &default_master.id, options, report);
+
+    let master = &default_master.other_stuff;
+    let bbox = glyphs
+        .iter()
+        .filter_map(|g| g.outline.bbox())
+        .fold(None, |acc: Option<Rect>, b| {
+            Some(acc.map(|a| a.union(b)).unwrap_or(b))
+        })
+        .unwrap_or_else(|| Rect::new(0.0, 0.0, 0.0, 0.0));
+    let metrics = Metrics {
+        family: font
+            .other_stuff
+            .get("familyName")
+            .and_then(Plist::as_str)
+            .unwrap_or("Untitled")
+            .to_string(),
+        upm: int_value(&font.other_stuff, "unitsPerEm", 1000) as u16,
+        version: (
+            int_value(&font.other_stuff, "versionMajor", 1),
+            int_value(&font.other_stuff, "versionMinor", 0),
+        ),
+        ascender: int_value(master, "ascender", 800) as i16,
+        descender: int_value(master, "descender", -200) as i16,
+        x_height: int_value(master, "xHeight", 500) as i16,
+        cap_height: int_value(master, "capHeight", 700) as i16,
+        bbox,
+    };
+
+    let version = format!("Version {}.{:03}", metrics.version.0, metrics.version.1);
+    let ps_family = postscript_name(&metrics.family);
+    let mut names = vec![
+        (1, metrics.family.clone()),
+        (2, "Regular".to_string()),
+        (3, format!("{};{}-Regular", version, ps_family)),
+        (4, format!("{} Regular", metrics.family)),
+        (5, version.clone()),
+        (6, format!("{}-Regular", ps_family)),
+    ];
+    for (i, name) in space.names.iter().enumerate() {
+        names.push((256 + i as u16, name.clone()));
+    }
+    let instances: Vec<(u16, Vec<f64>)> = font
+        .instances()
+        .into_iter()
+        .enumerate()
+        .map(|(i, (name, loc))| {
+            let name_id = (256 + n_axes + i) as u16;
+            names.push((name_id, name));
+            let coords = loc
+                .iter()
+                .enumerate()
+                .map(|(axis, &v)| space.design_to_user(axis, v))
+                .collect();
+            (name_id, coords)
+        })
+        .collect();
+
+    let weight_class = space
+        .tags
+        .iter()
+        .position(|tag| tag == "wght")
+        .map(|i| space.user[i].1.clamp(1.0, 1000.0) as u16)
+        .unwrap_or(400);
+    let (glyf, loca) = glyf_loca_tables(&glyphs);
+    let mut tables = vec![
+        ("head", head_table(&metrics)),
+        ("hhea", hhea_table(&metrics, &glyphs)),
+        ("maxp", maxp_table(&glyphs)),
+        ("OS/2", os2_table(&metrics, &glyphs, weight_class)),
+        ("hmtx", hmtx_table(&glyphs)),
+        ("cmap", cmap_table(&glyphs)),
+        ("name", name_table(&names)),
+        ("post", post_table(&glyphs)),
+        ("glyf", glyf),
+        ("loca", loca),
+        ("fvar", fvar_table(&space, &instances)),
+        ("gvar", gvar_table(&glyphs, n_axes)),
+    ];
+    if let Some(maps) = space.avar_maps() {
+        tables.push(("avar", avar_table(&maps)));
+    }
+    Ok(assemble(tables))
+}
+
+/// Big-endian reading of font data, with bounds checking.
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8], pos: usize) -> Reader<'a> {
+        Reader { data, pos }
+    }
+
+    fn bytes(&mut self, n: usize) -> Result<&'a [u8], String> {
+        let end = self.pos + n;
+        if end > self.data.len() {
+            return Err("unexpected end of table".to_string());
+        }
+        let bytes = &self.data[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn i8(&mut self) -> Result<i8, String> {
+        Ok(self.u8()? as i8)
+    }
+
+    fn u16(&mut self) -> Result<u16, String> {
+        let b = self.bytes(2)?;
+        Ok(u16::from_be_bytes([b[0], b[1]]))
+    }
+
+    fn i16(&mut self) -> Result<i16, String> {
+        Ok(self.u16()? as i16)
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        let b = self.bytes(4)?;
+        Ok(u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    fn fixed(&mut self) -> Result<f64, String> {
+        Ok(f64::from(self.u32()? as i32) / 65536.0)
+    }
+
+    fn f2dot14(&mut self) -> Result<f64, String> {
+        Ok(f64::from(self.i16()?) / 16384.0)
+    }
+
+    fn tag(&mut self) -> Result<String, String> {
+        Ok(String::from_utf8_lossy(self.bytes(4)?).into_owned())
+    }
+}
+
+fn read_tables(data: &[u8]) -> Result<HashMap<String, &[u8]>, String> {
+    let mut r = Reader::new(data, 0);
+    match r.u32()? {
+        0x0001_0000 | 0x7472_7565 => (),
+        0x4F54_544F => return Err("CFF outlines are not supported".to_string()),
+        0x7474_6366 => return Err("font collections are not supported".to_string()),
+        _ => return Err("not a TrueType font".to_string()),
+    }
+    let n_tables = r.u16()?;
+    r.pos += 6;
+    let mut tables = HashMap::new();
+    for _ in 0..n_tables {
+        let tag = r.tag()?;
+        let _checksum = r.u32()?;
+        let offset = r.u32()? as usize;
+        let length = r.u32()? as usize;
+        let table = data
+            .get(offset..offset + length)
+            .ok_or_else(|| format!("table {} out of bounds", tag))?;
+        tables.insert(tag, table);
+    }
+    Ok(tables)
+}
+
+/// A glyph as stored in glyf. Components hold their offsets separately,
+/// as those are what gvar varies.
+#[derive(Default)]
+struct SfntGlyph {
+    /// xMin from the glyph header; the phantom points hang off this.
+    x_min: f64,
+    contours: Vec<Vec<(Point, bool)>>,
+    components: Vec<(u16, Affine)>,
+}
+
+const ARG_1_AND_2_ARE_WORDS: u16 = 0x0001;
+const ARGS_ARE_XY_VALUES: u16 = 0x0002;
+const WE_HAVE_A_SCALE: u16 = 0x0008;
+const MORE_COMPONENTS: u16 = 0x0020;
+const WE_HAVE_AN_X_AND_Y_SCALE: u16 = 0x0040;
+const WE_HAVE_A_TWO_BY_TWO: u16 = 0x0080;
+const REPEAT_FLAG: u8 = 0x08;
+
+fn parse_glyph(data: &[u8]) -> Result<SfntGlyph, String> {
+    let mut glyph = SfntGlyph::default();
+    if data.is_empty() {
+        return Ok(glyph);
+    }
+    let mut r = Reader::new(data, 0);
+    let n_contours = r.i16()?;
+    glyph.x_min = f64::from(r.i16()?);
+    r.pos += 6;
+    if n_contours < 0 {
+        loop {
+            let flags = r.u16()?;
+            let gid = r.u16()?;
+            let (arg1, arg2) = match (
+                flags & ARG_1_AND_2_ARE_WORDS != 0,
+                flags & ARGS_ARE_XY_VALUES != 0,
+            ) {
+                (true, true) => (f64::from(r.i16()?), f64::from(r.i16()?)),
+                (false, true) => (f64::from(r.i8()?), f64::from(r.i8()?)),
+                // Point matching isn't supported; place the component at
+                // the origin.
+                (true, false) => {
+                    r.pos += 4;
+                    (0.0, 0.0)
+                }
+                (false, false) => {
+                    r.pos += 2;
+                    (0.0, 0.0)
+                }
+            };
+            let mut coeffs = [1.0, 0.0, 0.0, 1.0, arg1, arg2];
+            if flags & WE_HAVE_A_SCALE != 0 {
+                let scale = r.f2dot14()?;
+                coeffs[0] = scale;
+                coeffs[3] = scale;
+            } else if flags & WE_HAVE_AN_X_AND_Y_SCALE != 0 {
+                coeffs[0] = r.f2dot14()?;
+                coeffs[3] = r.f2dot14()?;
+            } else if flags & WE_HAVE_A_TWO_BY_TWO != 0 {
+                coeffs[0] = r.f2dot14()?;
+                coeffs[1] = r.f2dot14()?;
+                coeffs[2] = r.f2dot14()?;
+                coeffs[3] = r.f2dot14()?;
+            }
+            glyph.components.push((gid, Affine::new(coeffs)));
+            if flags & MORE_COMPONENTS == 0 {
+                break;
+            }
+        }
+        return Ok(glyph);
+    }
+    let mut ends = Vec::new();
+    for _ in 0..n_contours {
+        ends.push(r.u16()? as usize);
+    }
+    let n_points = ends.last().map(|&e| e + 1).unwrap_or(0);
+    let instructions_len = r.u16()? as usize;
+    r.pos += instructions_len;
+    let mut flags = Vec::with_capacity(n_points);
+    while flags.len() < n_points {
+        let flag = r.u8()?;
+        flags.push(flag);
+        if flag & REPEAT_FLAG != 0 {
+            for _ in 0..r.u8()? {
+                flags.push(flag);
+            }
+        }
+    }
+    let mut read_coords = |short: u8, same_or_positive: u8| -> Result<Vec<f64>, String> {
+        let mut v = 0i32;
+        let mut coords = Vec::with_capacity(n_points);
+        for &flag in &flags[..n_points] {
+            if flag & short != 0 {
+                let d = i32::from(r.u8()?);
+                v += if flag & same_or_positive != 0 { d } else { -d };
+            } else if flag & same_or_positive == 0 {
+                v += i32::from(r.i16()?);
+            }
+            coords.push(f64::from(v));
+        }
+        Ok(coords)
+    };
+    let xs = read_coords(X_SHORT, X_SAME_OR_POSITIVE)?;
+    let ys = read_coords(Y_SHORT, Y_SAME_OR_POSITIVE)?;
+    let mut start = 0;
+    for end in ends {
+        if end < start || end >= n_points {
+            return Err("bad contour end point".to_string());
+        }
+        glyph.contours.push(
+            (start..=end)
+                .map(|i| (Point::new(xs[i], ys[i]), flags[i] & ON_CURVE != 0))
+                .collect(),
+        );
+        start = end + 1;
+    }
+    Ok(glyph)
+}
+
+/// The standard Macintosh glyph names, used by post table formats 1 and 2.
+const MAC_GLYPH_NAMES: &str =
+    ".notdef .null nonmarkingreturn space exclam quotedbl numbersign dollar percent \
+     ampersand quotesingle parenleft parenright asterisk plus comma hyphen period slash zero \
+     one two three four five six seven eight nine colon semicolon less equal greater \
+     question at A B C D E F G H I J K L M N O P Q R S T U V W X Y Z bracketleft backslash \
+     bracketright asciicircum underscore grave a b c d e f g h i j k l m n o p q r s t u v w \
+     x y z braceleft bar braceright asciitilde Adieresis Aring Ccedilla Eacute Ntilde \
+     Odieresis Udieresis aacute agrave acircumflex adieresis atilde aring ccedilla eacute \
+     egrave ecircumflex edieresis iacute igrave icircumflex idieresis ntilde oacute ograve \
+     ocircumflex odieresis otilde uacute ugrave ucircumflex udieresis dagger degree cent \
+     sterling section bullet paragraph germandbls registered copyright trademark acute \
+     dieresis notequal AE Oslash infinity plusminus lessequal greaterequal yen mu \
+     partialdiff summation product pi integral ordfeminine ordmasculine Omega ae oslash \
+     questiondown exclamdown logicalnot radical florin approxequal Delta guillemotleft \
+     guillemotright ellipsis nonbreakingspace Agrave Atilde Otilde OE oe endash emdash \
+     quotedblleft quotedblright quoteleft quoteright divide lozenge ydieresis Ydieresis \
+     fraction currency guilsinglleft guilsinglright fi fl daggerdbl periodcentered \
+     quotesinglbase quotedblbase perthousand Acircumflex Ecircumflex Aacute Edieresis Egrave \
+     Iacute Icircumflex Idieresis Igrave Oacute Ocircumflex apple Ograve Uacute Ucircumflex \
+     Ugrave dotlessi circumflex tilde macron breve dotaccent ring cedilla hungarumlaut \
+     ogonek caron Lslash lslash Scaron scaron Zcaron zcaron brokenbar Eth eth Yacute yacute \
+     Thorn thorn minus multiply onesuperior twosuperior threesuperior onehalf onequarter \
+     threequarters franc Gbreve gbreve Idotaccent Scedilla scedilla Cacute cacute Ccaron \
+     ccaron dcroat";
+
+fn glyph_names(post: Option<&[u8]>, n_glyphs: usize) -> Result<Vec<String>, String> {
+    let mut names: Vec<String> = (0..n_glyphs).map(|i| format!("glyph{:05}", i)).collect();
+    if let Some(name) = names.first_mut() {
+        *name = ".notdef".to_string();
+    }
+    let post = match post {
+        Some(post) => post,
+        None => return Ok(names),
+    };
+    let mut r = Reader::new(post, 0);
+    let mac_names: Vec<&str> = MAC_GLYPH_NAMES.split_whitespace().collect();
+    match r.u32()? {
+        0x0001_0000 => {
+            for (name, mac_name) in names.iter_mut().zip(&mac_names) {
+                *name = mac_name.to_string();
+            }
+        }
+        0x0002_0000 => {
+            r.pos = 32;
+            let n = r.u16()? as usize;
+            let mut indices = Vec::with_capacity(n);
+            for _ in 0..n {
+                indices.push(r.u16()? as usize);
+            }
+            let mut custom = Vec::new();
+            while r.pos < post.len() {
+                let len = r.u8()? as usize;
+                custom.push(String::from_utf8_lossy(r.bytes(len)?).into_owned());
+            }
+            for (name, &ix) in names.iter_mut().zip(&indices) {
+                if let Some(mac_name) = mac_names.get(ix) {
+                    *name = mac_name.to_string();
+                } else if let Some(custom_name) = custom.get(ix - mac_names.len()) {
+                    *name = custom_name.clone();
+                }
+            }
+        }
+        _ => (),
+    }
+    // Glyph names must be unique.
+    let mut seen = HashMap::new();
+    for (i, name) in names.iter_mut().enumerate() {
+        if seen.insert(name.clone(), i).is_some() {
+            *name = format!("{}.{}", name, i);
+        }
+    }
+    Ok(names)
+}
+
+/// The characters mapped to each glyph, from the best available cmap
+/// subtable.
+fn read_cmap(cmap: &[u8], n_glyphs: usize) -> Result<Vec<Vec<u32>>, String> {
+    let mut r = Reader::new(cmap, 2);
+    let n_subtables = r.u16()?;
+    let mut best: Option<(usize, usize)> = None;
+    for _ in 0..n_subtables {
+        let platform = r.u16()?;
+        let encoding = r.u16()?;
+        let offset = r.u32()? as usize;
+        let format = Reader::new(cmap, offset).u16()?;
+        let rank = match (platform, encoding, format) {
+            (3, 10, 12) | (0, 4, 12) | (0, 6, 12) => 2,
+            (3, 1, 4) | (0, _, 4) => 1,
+            _ => continue,
+        };
+        if best.map(|b| rank > b.0).unwrap_or(true) {
+            best = Some((rank, offset));
+        }
+    }
+    let mut unicodes = vec![Vec::new(); n_glyphs];
+    let offset = match best {
+        Some((_, offset)) => offset,
+        None => return Ok(unicodes),
+    };
+    let mut map = |c: u32, gid: u32| {
+        if gid != 0 {
+            if let Some(u) = unicodes.get_mut(gid as usize) {
+                u.push(c);
+            }
+        }
+    };
+    let mut r = Reader::new(cmap, offset);
+    if r.u16()? == 12 {
+        r.pos += 10;
+        let n_groups = r.u32()?;
+        for _ in 0..n_groups {
+            let start = r.u32()?;
+            let end = r.u32()?;
+            let gid = r.u32()?;
+            for c in start..=end.min(0x10_FFFF) {
+                map(c, gid + c - start);
+            }
+        }
+    } else {
+        r.pos += 4;
+        let seg_count = r.u16()? as usize / 2;
+        let ends_pos = offset + 14;
+        let starts_pos = ends_pos + 2 * seg_count + 2;
+        let deltas_pos = starts_pos + 2 * seg_count;
+        let range_offsets_pos = deltas_pos + 2 * seg_count;
+        for i in 0..seg_count {
+            let end = u32::from(Reader::new(cmap, ends_pos + 2 * i).u16()?);
+            let start = u32::from(Reader::new(cmap, starts_pos + 2 * i).u16()?);
+            let delta = u32::from(Reader::new(cmap, deltas_pos + 2 * i).u16()?);
+            let range_offset_pos = range_offsets_pos + 2 * i;
+            let range_offset = Reader::new(cmap, range_offset_pos).u16()? as usize;
+            for c in start..=end {
+                if c == 0xFFFF {
+                    continue;
+                }
+                let gid = if range_offset == 0 {
+                    (c + delta) & 0xFFFF
+                } else {
+                    let pos = range_offset_pos + range_offset + 2 * (c - start) as usize;
+                    match u32::from(Reader::new(cmap, pos).u16()?) {
+                        0 => 0,
+                        gid => (gid + delta) & 0xFFFF,
+                    }
+                };
+                map(c, gid);
+            }
+        }
+    }
+    Ok(unicodes)
+}
+
+/// Look up a string in the name table, preferring Windows English names.
+fn read_name(name: &[u8], name_id: u16) -> Result<Option<String>, String> {
+    let mut r = Reader::new(name, 2);
+    let count = r.u16()?;
+    let storage = r.u16()? as usize;
+    let mut best: Option<(bool, String)> = None;
+    for _ in 0..count {
+        let platform = r.u16()?;
+        let encoding = r.u16()?;
+        let language = r.u16()?;
+        let id = r.u16()?;
+        let len = r.u16()? as usize;
+        let offset = r.u16()? as usize;
+        if id != name_id {
+            continue;
+        }
+        let bytes = Reader::new(name, storage + offset).bytes(len)?;
+        let s = match (platform, encoding) {
+            (0, _) | (3, 1) | (3, 10) => {
+                let units: Vec<u16> = bytes
+                    .chunks(2)
+                    .map(|c| u16::from_be_bytes([c[0], *c.get(1).unwrap_or(&0)]))
+                    .collect();
+                String::from_utf16_lossy(&units)
+            }
+            (1, 0) => bytes.iter().map(|&b| b as char).collect(),
+            _ => continue,
+        };
+        let preferred = platform == 3 && language == 0x409;
+        if best.as_ref().map(|b| preferred && !b.0).unwrap_or(true) {
+            best = Some((preferred, s));
+        }
+    }
+    Ok(best.map(|b| b.1))
+}
+
+/// An axis from fvar, in user coordinates.
+struct FvarAxis {
+    tag: String,
+    range: (f64, f64, f64),
+    name_id: u16,
+}
+
+/// A named instance from fvar: its subfamily name id and user coordinates.
+type FvarInstance = (u16, Vec<f64>);
+
+fn read_fvar(fvar: &[u8]) -> Result<(Vec<FvarAxis>, Vec<FvarInstance>), String> {
+    let mut r = Reader::new(fvar, 4);
+    let axes_offset = r.u16()? as usize;
+    r.pos += 2;
+    let n_axes = r.u16()? as usize;
+    let axis_size = r.u16()? as usize;
+    let n_instances = r.u16()? as usize;
+    let instance_size = r.u16()? as usize;
+    let mut axes = Vec::new();
+    for i in 0..n_axes {
+        let mut r = Reader::new(fvar, axes_offset + i * axis_size);
+        let tag = r.tag()?;
+        let range = (r.fixed()?, r.fixed()?, r.fixed()?);
+        let _flags = r.u16()?;
+        let name_id = r.u16()?;
+        axes.push(FvarAxis {
+            tag,
+            range,
+            name_id,
+        });
+    }
+    let instances_offset = axes_offset + n_axes * axis_size;
+    let mut instances = Vec::new();
+    for i in 0..n_instances {
+        let mut r = Reader::new(fvar, instances_offset + i * instance_size);
+        let name_id = r.u16()?;
+        let _flags = r.u16()?;
+        let coords = (0..n_axes).map(|_| r.fixed()).collect::<Result<_, _>>()?;
+        instances.push((name_id, coords));
+    }
+    Ok((axes, instances))
+}
+
+fn read_avar(avar: &[u8]) -> Result<Vec<Vec<(f64, f64)>>, String> {
+    let mut r = Reader::new(avar, 6);
+    let n_axes = r.u16()?;
+    let mut maps = Vec::new();
+    for _ in 0..n_axes {
+        let n = r.u16()?;
+        let mut map = Vec::new();
+        for _ in 0..n {
+            map.push((r.f2dot14()?, r.f2dot14()?));
+        }
+        maps.push(map);
+    }
+    Ok(maps)
+}
+
+/// Apply a piecewise linear segment map, as in avar.
+fn map_segments(v: f64, map: &[(f64, f64)]) -> f64 {
+    if map.len() < 2 {
+        return v;
+    }
+    let k = map
+        .windows(2)
+        .position(|w| v <= w[1].0)
+        .unwrap_or(map.len() - 2);
+    let ((x0, y0), (x1, y1)) = (map[k], map[k + 1]);
+    if x1 == x0 {
+        y0
+    } else {
+        y0 + (v - x0) * (y1 - y0) / (x1 - x0)
+    }
+}
+
+/// Map a normalized coordinate back to the axis range.
+fn denormalize_value(v: f64, (min, default, max): (f64, f64, f64)) -> f64 {
+    if v < 0.0 {
+        default + v * (default - min)
+    } else {
+        default + v * (max - default)
+    }
+}
+
+/// A variation of one glyph: its region, and deltas for each point
+/// (including phantom points), with `None` for points left to IUP.
+struct GlyphVariation {
+    support: Support,
+    deltas: Vec<Option<Vec2>>,
+}
+
+const SHARED_POINT_NUMBERS: u16 = 0x8000;
+const TUPLE_COUNT_MASK: u16 = 0x0FFF;
+const TUPLE_INDEX_MASK: u16 = 0x0FFF;
+
+/// Read packed point numbers; `None` means all points.
+fn read_packed_points(r: &mut Reader) -> Result<Option<Vec<usize>>, String> {
+    let mut count = r.u8()? as usize;
+    if count & 0x80 != 0 {
+        count = ((count & 0x7F) << 8) | r.u8()? as usize;
+    }
+    if count == 0 {
+        return Ok(None);
+    }
+    let mut points = Vec::with_capacity(count);
+    let mut last = 0;
+    while points.len() < count {
+        let control = r.u8()?;
+        for _ in 0..(control & 0x7F) + 1 {
+            last += if control & 0x80 != 0 {
+                r.u16()? as usize
+            } else {
+                r.u8()? as usize
+            };
+            points.push(last);
+        }
+    }
+    Ok(Some(points))
+}
+
+fn read_packed_deltas(r: &mut Reader, count: usize) -> Result<Vec<f64>, String> {
+    let mut deltas = Vec::with_capacity(count);
+    while deltas.len() < count {
+        let control = r.u8()?;
+        for _ in 0..(control & 0x3F) + 1 {
+            deltas.push(if control & DELTAS_ARE_ZERO != 0 {
+                0.0
+            } else if control & DELTAS_ARE_WORDS != 0 {
+                f64::from(r.i16()?)
+            } else {
+                f64::from(r.i8()?)
+            });
+        }
+    }
+    deltas.truncate(count);
+    Ok(deltas)
+}
+
+fn read_glyph_variations(
+    data: &[u8],
+    n_axes: usize,
+    shared_tuples: &[Vec<f64>],
+    n_points: usize,
+) -> Result<Vec<GlyphVariation>, String> {
+    if data.is_empty() {
+        return Ok(Vec::new());
+    }
+    let mut r = Reader::new(data, 0);
+    let count = r.u16()?;
+    let data_offset = r.u16()? as usize;
+    let mut serialized = Reader::new(data, data_offset);
+    let shared_points = if count & SHARED_POINT_NUMBERS != 0 {
+        read_packed_points(&mut serialized)?
+    } else {
+        None
+    };
+    let mut variations = Vec::new();
+    for _ in 0..count & TUPLE_COUNT_MASK {
+        let size = r.u16()? as usize;
+        let index = r.u16()?;
+        let peak: Vec<f64> = if index & EMBEDDED_PEAK_TUPLE != 0 {
+            (0..n_axes).map(|_| r.f2dot14()).collect::<Result<_, _>>()?
+        } else {
+            shared_tuples
+                .get((index & TUPLE_INDEX_MASK) as usize)
+                .cloned()
+                .ok_or_else(|| "bad shared tuple index".to_string())?
+        };
+        let (start, end): (Vec<f64>, Vec<f64>) = if index & INTERMEDIATE_REGION != 0 {
+            (
+                (0..n_axes).map(|_| r.f2dot14()).collect::<Result<_, _>>()?,
+                (0..n_axes).map(|_| r.f2dot14()).collect::<Result<_, _>>()?,
+            )
+        } else {
+            (
+                peak.iter().map(|&p| p.min(0.0)).collect(),
+                peak.iter().map(|&p| p.max(0.0)).collect(),
+            )
+        };
+        let tuple_start = serialized.pos;
+        let points = if index & PRIVATE_POINT_NUMBERS != 0 {
+            read_packed_points(&mut serialized)?
+        } else {
+            shared_points.clone()
+        };
+        let points = points.unwrap_or_else(|| (0..n_points).collect());
+        let xs = read_packed_deltas(&mut serialized, points.len())?;
+        let ys = read_packed_deltas(&mut serialized, points.len())?;
+        serialized.pos = tuple_start + size;
+        let mut deltas = vec![None; n_points];
+        for ((&point, &x), &y) in points.iter().zip(&xs).zip(&ys) {
+            if let Some(delta) = deltas.get_mut(point) {
+                *delta = Some(Vec2::new(x, y));
+            }
+        }
+        let support = (0..n_axes)
+            .map(|i| Some((start[i], peak[i], end[i])))
+            .collect();
+        variations.push(GlyphVariation { support, deltas });
+    }
+    Ok(variations)
+}
+
+/// The outline and variations of one glyph.
+struct SourceGlyph {
+    glyph: SfntGlyph,
+    advance: f64,
+    lsb: f64,
+    variations: Vec<GlyphVariation>,
+}
+
+impl SourceGlyph {
+    /// The points gvar refers to, before the phantom points: outline points
+    /// or component offsets.
+    fn points(&self) -> Vec<Point> {
+        if self.glyph.components.is_empty() {
+            self.glyph
+                .contours
+                .iter()
+                .flatten()
+                .map(|&(p, _)| p)
+                .collect()
+        } else {
+            self.glyph
+                .components
+                .iter()
+                .map(|(_, t)| {
+                    let c = t.as_coeffs();
+                    Point::new(c[4], c[5])
+                })
+                .collect()
+        }
+    }
+
+    /// Evaluate the glyph at a normalized location (after avar), returning a
+    /// layer with the given id.
+    fn instantiate(&self, loc: &[f64], names: &[String], layer_id: &str) -> Layer {
+        let mut points = self.points();
+        let n = points.len();
+        let origin = self.glyph.x_min - self.lsb;
+        points.push(Point::new(origin, 0.0));
+        points.push(Point::new(origin + self.advance, 0.0));
+        points.push(Point::new(0.0, 0.0));
+        points.push(Point::new(0.0, 0.0));
+        // Each component offset and phantom point forms its own contour.
+        let mut ends = Vec::new();
+        if self.glyph.components.is_empty() {
+            let mut end = 0;
+            for contour in &self.glyph.contours {
+                end += contour.len();
+                ends.push(end - 1);
+            }
+        } else {
+            ends.extend(0..n);
+        }
+        ends.extend(n..n + N_PHANTOM);
+        let base = points.clone();
+        for variation in &self.variations {
+            let scalar = support_scalar(loc, &variation.support);
+            if scalar == 0.0 || variation.deltas.len() != base.len() {
+                continue;
+            }
+            let deltas = if variation.deltas.iter().all(Option::is_some) {
+                variation.deltas.iter().map(|d| d.unwrap()).collect()
+            } else {
+                iup_delta(&variation.deltas, &base, &ends)
+            };
+            for (p, d) in points.iter_mut().zip(deltas) {
+                *p += d * scalar;
+            }
+        }
+        let origin = points[n];
+        let width = points[n + 1].x - origin.x;
+        let shift = Vec2::new(-origin.x, 0.0);
+
+        let mut paths = Vec::new();
+        let mut components = Vec::new();
+        if self.glyph.components.is_empty() {
+            let mut i = 0;
+            for contour in &self.glyph.contours {
+                // Back to PostScript direction.
+                let mut pts: Vec<(Point, bool)> = contour
+                    .iter()
+                    .enumerate()
+                    .map(|(j, &(_, on_curve))| (points[i + j] + shift, on_curve))
+                    .collect();
+                i += contour.len();
+                pts.reverse();
+                let nodes = (0..pts.len())
+                    .map(|j| {
+                        let prev_on_curve = pts[(j + pts.len() - 1) % pts.len()].1;
+                        let node_type = match (pts[j].1, prev_on_curve) {
+                            (false, _) => NodeType::OffCurve,
+                            (true, true) => NodeType::Line,
+                            (true, false) => NodeType::QCurve,
+                        };
+                        Node {
+                            pt: pts[j].0,
+                            node_type,
+                        }
+                    })
+                    .collect();
+                paths.push(Path {
+                    closed: true,
+                    nodes,
+                });
+            }
+        } else {
+            for (&(gid, transform), offset) in self.glyph.components.iter().zip(&points) {
+                let c = transform.as_coeffs();
+                let offset = *offset + shift;
+                let transform = Affine::new([c[0], c[1], c[2], c[3], offset.x, offset.y]);
+                components.push(Component {
+                    name: names.get(gid as usize).cloned().unwrap_or_default(),
+                    transform: if transform.as_coeffs() == [1.0, 0.0, 0.0, 1.0, 0.0, 0.0] {
+                        None
+                    } else {
+                        Some(transform)
+                    },
+                    other_stuff: HashMap::new(),
+                });
+            }
+        }
+        Layer {
+            layer_id: layer_id.to_string(),
+            width: width.round(),
+            paths: if paths.is_empty() { None } else { Some(paths) },
+            components: if components.is_empty() {
+                None
+            } else {
+                Some(components)
+            },
+            anchors: None,
+            guide_lines: None,
+            other_stuff: HashMap::new(),
+        }
+    }
+}
+
+fn required<'a>(tables: &HashMap<String, &'a [u8]>, tag: &str) -> Result<&'a [u8], String> {
+    tables
+        .get(tag)
+        .copied()
+        .ok_or_else(|| format!("missing {} table", tag))
+}
+
+fn read_source_glyphs(
+    tables: &HashMap<String, &[u8]>,
+    n_axes: usize,
+) -> Result<Vec<SourceGlyph>, String> {
+    let head = required(tables, "head")?;
+    let long_loca = Reader::new(head, 50).i16()? != 0;
+    let n_glyphs = Reader::new(required(tables, "maxp")?, 4).u16()? as usize;
+    let n_metrics = Reader::new(required(tables, "hhea")?, 34).u16()? as usize;
+    let hmtx = required(tables, "hmtx")?;
+    let loca = required(tables, "loca")?;
+    let glyf = required(tables, "glyf")?;
+
+    let mut offsets = Vec::with_capacity(n_glyphs + 1);
+    let mut r = Reader::new(loca, 0);
+    for _ in 0..=n_glyphs {
+        offsets.push(if long_loca {
+            r.u32()? as usize
+        } else {
+            r.u16()? as usize * 2
+        });
+    }
+
+    let mut variation_data = vec![&[][..]; n_glyphs];
+    let mut shared_tuples = Vec::new();
+    if let Some(gvar) = tables.get("gvar") {
+        let mut r = Reader::new(gvar, 4);
+        if r.u16()? as usize != n_axes {
+            return Err("gvar and fvar disagree on the number of axes".to_string());
+        }
+        let n_shared = r.u16()?;
+        let shared_offset = r.u32()? as usize;
+        let gvar_glyphs = (r.u16()? as usize).min(n_glyphs);
+        let long_offsets = r.u16()? & 1 != 0;
+        let data_offset = r.u32()? as usize;
+        let mut gvar_offsets = Vec::new();
+        for _ in 0..=gvar_glyphs {
+            gvar_offsets.push(if long_offsets {
+                r.u32()? as usize
+            } else {
+                r.u16()? as usize * 2
+            });
+        }
+        let mut r = Reader::new(gvar, shared_offset);
+        for _ in 0..n_shared {
+            shared_tuples.push((0..n_axes).map(|_| r.f2dot14()).collect::<Result<_, _>>()?);
+        }
+        for (i, data) in variation_data.iter_mut().enumerate().take(gvar_glyphs) {
+            *data = gvar
+                .get(data_offset + gvar_offsets[i]..data_offset + gvar_offsets[i + 1])
+                .ok_or_else(|| format!("gvar data of glyph {} out of bounds", i))?;
+        }
+    }
+
+    let mut glyphs = Vec::with_capacity(n_glyphs);
+    for i in 0..n_glyphs {
+        let data = glyf
+            .get(offsets[i]..offsets[i + 1])
+            .ok_or_else(|| format!("glyph {} out of bounds", i))?;
+        let glyph = parse_glyph(data).map_err(|e| format!("glyph {}: {}", i, e))?;
+        let metric = i.min(n_metrics.saturating_sub(1));
+        let advance = f64::from(Reader::new(hmtx, 4 * metric).u16()?);
+        let lsb = if i < n_metrics {
+            Reader::new(hmtx, 4 * i + 2).i16()?
+        } else {
+            Reader::new(hmtx, 4 * n_metrics + 2 * (i - n_metrics)).i16()?
+        };
+        let mut source = SourceGlyph {
+            glyph,
+            advance,
+            lsb: f64::from(lsb),
+            variations: Vec::new(),
+        };
+        let n_points = source.points().len() + N_PHANTOM;
+        source.variations =
+            read_glyph_variations(variation_data[i], n_axes, &shared_tuples, n_points)
+                .map_err(|e| format!("glyph {}: {}", i, e))?;
+        glyphs.push(source);
+    }
+    Ok(glyphs)
+}
+
+fn location_name(loc: &[f64]) -> String {
+    loc.iter()
+        .map(|&v| format!("{}", (v * 1000.0).round() / 1000.0))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Read a variable TrueType font, reconstructing a font with masters at
+/// the default location and at the extremes of each axis. Regions of
+/// individual glyphs that peak elsewhere become brace layers.
+///
+/// Master locations are in design coordinates, chosen so that interpolation
+/// is linear in them: avar is applied when placing brace layers and
+/// instances.
+pub fn import_variable_ttf(path: &FsPath) -> Result<Font, String> {
+    let data = fs::read(path).map_err(|e| format!("{}: {:?}", path.display(), e))?;
+    let tables = read_tables(&data)?;
+    let fvar = tables
+        .get("fvar")
+        .ok_or_else(|| "not a variable font (no fvar table)".to_string())?;
+    let (fvar_axes, fvar_instances) = read_fvar(fvar)?;
+    let n_axes = fvar_axes.len();
+    let avar = match tables.get("avar") {
+        Some(avar) => read_avar(avar)?,
+        None => Vec::new(),
+    };
+    let name_table = tables.get("name").copied();
+    let lookup_name = |name_id: u16| -> Result<Option<String>, String> {
+        match name_table {
+            Some(name) => read_name(name, name_id),
+            None => Ok(None),
+        }
+    };
+
+    let ranges: Vec<(f64, f64, f64)> = fvar_axes.iter().map(|a| a.range).collect();
+    // Normalized coordinates (after avar) to design coordinates.
+    let to_design = |loc: &[f64]| -> Vec<f64> {
+        loc.iter()
+            .zip(&ranges)
+            .map(|(&v, &range)| denormalize_value(v, range))
+            .collect::<Vec<f64>>()
+    };
+    let normalize_user = |coords: &[f64]| -> Vec<f64> {
+        coords
+            .iter()
+            .zip(&ranges)
+            .enumerate()
+            .map(|(i, (&v, &range))| {
+                let v = normalize_value(v, range);
+                avar.get(i).map(|map| map_segments(v, map)).unwrap_or(v)
+            })
+            .collect::<Vec<f64>>()
+    };
+
+    let mut axes = Vec::new();
+    for axis in &fvar_axes {
+        axes.push(Axis {
+            name: lookup_name(axis.name_id)?.unwrap_or_else(|| axis.tag.clone()),
+            tag: axis.tag.clone(),
+        });
+    }
+    let mut instances = Vec::new();
+    for (name_id, coords) in &fvar_instances {
+        let name = lookup_name(*name_id)?.unwrap_or_else(|| "Regular".to_string());
+        instances.push((name, normalize_user(coords)));
+    }
+
+    // The default master, then the extremes of each axis.
+    let mut master_locs = vec![vec![0.0; n_axes]];
+    for (i, &(min, default, max)) in ranges.iter().enumerate() {
+        for &(v, exists) in &[(-1.0, min < default), (1.0, max > default)] {
+            if exists {
+                let mut loc = vec![0.0; n_axes];
+                loc[i] = v;
+                master_locs.push(loc);
+            }
+        }
+    }
+    let mut font_master = Vec::new();
+    for loc in &master_locs {
+        let design = to_design(loc);
+        let name = instances
+            .iter()
+            .find(|(_, inst_loc)| inst_loc == loc)
+            .map(|(name, _)| name.clone())
+            .unwrap_or_else(|| {
+                let parts: Vec<String> = axes
+                    .iter()
+                    .zip(loc)
+                    .zip(&design)
+                    .filter(|((_, &v), _)| v != 0.0)
+                    .map(|((axis, _), d)| format!("{} {}", axis.name, d))
+                    .collect();
+                if parts.is_empty() {
+                    "Regular".to_string()
+                } else {
+                    parts.join(" ")
+                }
+            });
+        let mut other_stuff = HashMap::new();
+        other_stuff.insert("name".to_string(), name.into());
+        let hhea = required(&tables, "hhea")?;
+        other_stuff.insert(
+            "ascender".to_string(),
+            Plist::Integer(Reader::new(hhea, 4).i16()?.into()),
+        );
+        other_stuff.insert(
+            "descender".to_string(),
+            Plist::Integer(Reader::new(hhea, 6).i16()?.into()),
+        );
+        let mut master = FontMaster {
+            id: format!("master-{}", location_name(loc)),
+            weight_value: 400,
+            width_value: None,
+            other_stuff,
+        };
+        master.set_location(&axes, &design);
+        font_master.push(master);
+    }
+    let default_id = font_master[0].id.clone();
+
+    let sources = read_source_glyphs(&tables, n_axes)?;
+    let names = glyph_names(tables.get("post").copied(), sources.len())?;
+    let unicodes = match tables.get("cmap") {
+        Some(cmap) => read_cmap(cmap, sources.len())?,
+        None => vec![Vec::new(); sources.len()],
+    };
+    let mut glyphs = Vec::new();
+    for (i, source) in sources.iter().enumerate() {
+        let mut layers: Vec<Layer> = master_locs
+            .iter()
+            .zip(&font_master)
+            .map(|(loc, master)| source.instantiate(loc, &names, &master.id))
+            .collect();
+        let mut brace_locs: Vec<Vec<f64>> = Vec::new();
+        for variation in &source.variations {
+            let peak: Vec<f64> = variation
+                .support
+                .iter()
+                .map(|s| s.map(|s| s.1).unwrap_or(0.0))
+                .collect();
+            if !master_locs.contains(&peak) && !brace_locs.contains(&peak) {
+                brace_locs.push(peak);
+            }
+        }
+        for loc in brace_locs {
+            let name = brace_name(&to_design(&loc));
+            let mut layer = source.instantiate(&loc, &names, &format!("{}.{}", default_id, name));
+            layer.other_stuff.insert("name".to_string(), name.into());
+            layer
+                .other_stuff
+                .insert("associatedMasterId".to_string(), default_id.clone().into());
+            layers.push(layer);
+        }
+        let mut other_stuff = HashMap::new();
+        if !unicodes[i].is_empty() {
+            let hex: Vec<String> = unicodes[i].iter().map(|u| format!("{:04X}", u)).collect();
+            other_stuff.insert("unicode".to_string(), hex.join(",").into());
+        }
+        glyphs.push(Glyph {
+            layers,
+            glyphname: names[i].clone(),
+            other_stuff,
+        });
+    }
+
+    let mut other_stuff = HashMap::new();
+    let family = match lookup_name(16)? {
+        Some(family) => Some(family),
+        None => lookup_name(1)?,
+    };
+    if let Some(family) = family {
+        other_stuff.insert("familyName".to_string(), family.into());
+    }
+    let head = required(&tables, "head")?;
+    other_stuff.insert(
+        "unitsPerEm".to_string(),
+        Plist::Integer(Reader::new(head, 18).u16()?.into()),
+    );
+    let revision = Reader::new(head, 4).fixed()?;
+    other_stuff.insert(
+        "versionMajor".to_string(),
+        Plist::Integer(revision.trunc() as i64),
+    );
+    other_stuff.insert(
+        "versionMinor".to_string(),
+        Plist::Integer((revision.fract() * 1000.0).round() as i64),
+    );
+    let custom_parameters: Vec<Plist> = vec![axes_parameter(&axes)];
+    other_stuff.insert("customParameters".to_string(), custom_parameters.into());
+    if !instances.is_empty() {
+        let instances: Vec<Plist> = instances
+            .iter()
+            .map(|(name, loc)| instance_plist(name, &axes, &to_design(loc)))
+            .collect();
+        other_stuff.insert("instances".to_string(), instances.into());
+    }
+    Ok(Font {
+        glyphs,
+        font_master,
+        other_stuff,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn square_layer(layer_id: &str, right: f64, width: f64) -> Layer {
+        let mut path = Path::new(true);
+        path.add((100.0, 0.0), NodeType::Line);
+        path.add((100.0, 500.0), NodeType::Line);
+        path.add((right, 500.0), NodeType::Line);
+        path.add((right, 0.0), NodeType::Line);
+        Layer {
+            layer_id: layer_id.to_string(),
+            width,
+            paths: Some(vec![path]),
+            components: None,
+            anchors: None,
+            guide_lines: None,
+            other_stuff: HashMap::new(),
+        }
+    }
+
+    fn glyph(name: &str, layers: Vec<Layer>) -> Glyph {
+        Glyph {
+            layers,
+            glyphname: name.to_string(),
+            other_stuff: HashMap::new(),
+        }
+    }
+
+    fn master(id: &str, weight: i64) -> FontMaster {
+        FontMaster {
+            id: id.to_string(),
+            weight_value: weight,
+            width_value: None,
+            other_stuff: HashMap::new(),
+        }
+    }
+
+    /// Two masters on the weight axis, with .notdef after the glyph that
+    /// varies.
+    fn two_master_font() -> Font {
+        let a = glyph(
+            "a",
+            vec![
+                square_layer("light", 300.0, 500.0),
+                square_layer("bold", 320.0, 600.0),
+            ],
+        );
+        let notdef = glyph(
+            ".notdef",
+            vec![
+                square_layer("light", 300.0, 500.0),
+                square_layer("bold", 300.0, 500.0),
+            ],
+        );
+        Font {
+            glyphs: vec![a, notdef],
+            font_master: vec![master("light", 400), master("bold", 700)],
+            other_stuff: HashMap::new(),
+        }
+    }
+
+    #[test]
+    fn export_round_trip() {
+        let font = two_master_font();
+        let mut report = CompatReport::default();
+        let data = export_variable_ttf(&font, &TtfOptions::default(), &mut report).unwrap();
+        assert!(report.unfixed.is_empty(), "{:?}", report.unfixed);
+        let tables = read_tables(&data).unwrap();
+
+        let (axes, _) = read_fvar(tables["fvar"]).unwrap();
+        assert_eq!(axes.len(), 1);
+        assert_eq!(axes[0].tag, "wght");
+        assert_eq!(axes[0].range, (400.0, 400.0, 700.0));
+
+        let names = glyph_names(tables.get("post").copied(), 2).unwrap();
+        assert_eq!(names, vec![".notdef", "a"]);
+
+        let sources = read_source_glyphs(&tables, 1).unwrap();
+        assert!(sources[0].variations.is_empty());
+        let variations = &sources[1].variations;
+        assert_eq!(variations.len(), 1);
+        assert_eq!(variations[0].support, vec![Some((0.0, 1.0, 1.0))]);
+        // The two points on the right edge move by 20, and the advance
+        // phantom point by 100.
+        let deltas: Vec<Vec2> = variations[0].deltas.iter().map(|d| d.unwrap()).collect();
+        let moved: Vec<usize> = (0..4)
+            .filter(|&i| deltas[i] != Vec2::new(0.0, 0.0))
+            .collect();
+        assert_eq!(moved.len(), 2);
+        for &i in &moved {
+            assert_eq!(deltas[i], Vec2::new(20.0, 0.0));
+        }
+        assert_eq!(deltas[5], Vec2::new(100.0, 0.0));
+
+        let bold = sources[1].instantiate(&[1.0], &names, "bold");
+        assert_eq!(bold.width, 600.0);
+        let mut xs: Vec<f64> = bold.paths.unwrap()[0]
+            .nodes
+            .iter()
+            .map(|n| n.pt.x)
+            .collect();
+        xs.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        assert_eq!(xs, vec![100.0, 100.0, 320.0, 320.0]);
+    }
+
+    /// A composite whose xMin is not zero: its offset must not pick up the lsb.
+    #[test]
+    fn import_composite() {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/composite.ttf");
+        let font = import_variable_ttf(FsPath::new(path)).unwrap();
+        assert_eq!(font.font_master.len(), 2);
+        let comp = font.get_glyph("comp").unwrap();
+        let offsets: Vec<(f64, f64)> = comp
+            .layers
+            .iter()
+            .map(|layer| {
+                let components = layer.components.as_ref().unwrap();
+                assert_eq!(components[0].name, "bar");
+                let c = components[0].transform.unwrap().as_coeffs();
+                (c[4], layer.width)
+            })
+            .collect();
+        assert_eq!(offsets, vec![(200.0, 600.0), (230.0, 660.0)]);
+    }
+
+    #[test]
+    fn export_without_masters() {
+        let font = Font {
+            glyphs: Vec::new(),
+            font_master: Vec::new(),
+            other_stuff: HashMap::new(),
+        };
+        let mut report = CompatReport::default();
+        assert!(export_variable_ttf(&font, &TtfOptions::default(), &mut report).is_err());
+    }
+}
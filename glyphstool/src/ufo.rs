@@ -0,0 +1,1164 @@
+//! Export to and import from UFO 3, with a designspace document describing
+//! the masters.
+//!
+//! Each master becomes a UFO; brace layers become additional layers in the
+//! UFO of their associated master, referenced as sparse sources from the
+//! designspace.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::Path as FsPath;
+
+use kurbo::{Affine, Point};
+
+use crate::font::{
+    axes_parameter, instance_plist, Anchor, Axis, Component, Font, FontMaster, Glyph, GuideLine,
+    Layer, Node, NodeType, Path,
+};
+use crate::plist::Plist;
+use crate::xml::{self, escape, plist_from_xml, plist_to_xml, Element};
+
+/// Characters that can't appear in UFO file names.
+const ILLEGAL_CHARS: &str = "\"*+/:<>?[\\]|";
+
+/// File names that are reserved on Windows.
+const RESERVED_NAMES: &[&str] = &[
+    "con", "prn", "aux", "clock$", "nul", "com1", "com2", "com3", "com4", "com5", "com6", "com7",
+    "com8", "com9", "lpt1", "lpt2", "lpt3", "lpt4", "lpt5", "lpt6", "lpt7", "lpt8", "lpt9",
+];
+
+/// Convert a user name (glyph or layer name) into a file name, following
+/// the algorithm in the UFO 3 spec.
+///
+/// `existing` contains lowercased names already in use, and is updated.
+pub fn user_name_to_file_name(
+    name: &str,
+    prefix: &str,
+    suffix: &str,
+    existing: &mut HashSet<String>,
+) -> String {
+    let mut base = String::new();
+    for (i, c) in name.chars().enumerate() {
+        let illegal = (c as u32) < 0x20 || c as u32 == 0x7f || ILLEGAL_CHARS.contains(c);
+        if illegal || (i == 0 && c == '.' && prefix.is_empty()) {
+            base.push('_');
+        } else if c.is_ascii_uppercase() {
+            base.push(c);
+            base.push('_');
+        } else {
+            base.push(c);
+        }
+    }
+    let base = base
+        .split('.')
+        .map(|part| {
+            if RESERVED_NAMES.contains(&part.to_lowercase().as_str()) {
+                format!("_{}", part)
+            } else {
+                part.to_string()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(".");
+    let max_len = 255 - prefix.len() - suffix.len();
+    let base: String = base.chars().take(max_len).collect();
+    let mut candidate = format!("{}{}{}", prefix, base, suffix);
+    let mut counter = 1;
+    while existing.contains(&candidate.to_lowercase()) {
+        let digits = format!("{:015}", counter);
+        let base: String = base.chars().take(max_len - digits.len()).collect();
+        candidate = format!("{}{}{}{}", prefix, base, digits, suffix);
+        counter += 1;
+    }
+    existing.insert(candidate.to_lowercase());
+    candidate
+}
+
+fn file_safe(s: &str) -> String {
+    s.chars().filter(|c| !c.is_whitespace()).collect()
+}
+
+fn fmt_num(x: f64) -> String {
+    format!("{}", x)
+}
+
+/// The unicode values of a glyph, as uppercase hex strings.
+fn unicodes(glyph: &Glyph) -> Vec<String> {
+    match glyph.other_stuff.get("unicode") {
+        Some(Plist::String(s)) => s.split(',').map(|u| u.trim().to_uppercase()).collect(),
+        // Hex values that happen to be all digits parse as integers.
+        Some(Plist::Integer(i)) => vec![format!("{:04}", i)],
+        _ => Vec::new(),
+    }
+}
+
+/// Serialize a layer of a glyph as a .glif file.
+pub fn layer_to_glif(glyphname: &str, unicodes: &[String], layer: &Layer) -> String {
+    let mut s = String::new();
+    s.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    s.push_str(&format!(
+        "<glyph name=\"{}\" format=\"2\">\n",
+        escape(glyphname)
+    ));
+    s.push_str(&format!(
+        "  <advance width=\"{}\"/>\n",
+        fmt_num(layer.width)
+    ));
+    for u in unicodes {
+        s.push_str(&format!("  <unicode hex=\"{}\"/>\n", u));
+    }
+    if let Some(ref anchors) = layer.anchors {
+        for anchor in anchors {
+            s.push_str(&format!(
+                "  <anchor x=\"{}\" y=\"{}\" name=\"{}\"/>\n",
+                fmt_num(anchor.position.x),
+                fmt_num(anchor.position.y),
+                escape(&anchor.name)
+            ));
+        }
+    }
+    if let Some(ref guide_lines) = layer.guide_lines {
+        for guide in guide_lines {
+            s.push_str(&format!(
+                "  <guideline x=\"{}\" y=\"{}\" angle=\"{}\"/>\n",
+                fmt_num(guide.position.x),
+                fmt_num(guide.position.y),
+                fmt_num(guide.angle.unwrap_or(0.0))
+            ));
+        }
+    }
+    s.push_str("  <outline>\n");
+    if let Some(ref paths) = layer.paths {
+        for path in paths {
+            s.push_str("    <contour>\n");
+            // Glyphs stores the start node of a closed contour last, UFO
+            // first.
+            let mut nodes = path.nodes.clone();
+            if path.closed {
+                nodes.rotate_right(1);
+            }
+            for (i, node) in nodes.iter().enumerate() {
+                let pt_type = match node.node_type {
+                    _ if i == 0 && !path.closed => Some("move"),
+                    NodeType::OffCurve => None,
+                    NodeType::Line | NodeType::LineSmooth => Some("line"),
+                    NodeType::Curve | NodeType::CurveSmooth => Some("curve"),
+                    NodeType::QCurve | NodeType::QCurveSmooth => Some("qcurve"),
+                };
+                s.push_str(&format!(
+                    "      <point x=\"{}\" y=\"{}\"",
+                    fmt_num(node.pt.x),
+                    fmt_num(node.pt.y)
+                ));
+                if let Some(pt_type) = pt_type {
+                    s.push_str(&format!(" type=\"{}\"", pt_type));
+                }
+                if node.node_type.is_smooth() {
+                    s.push_str(" smooth=\"yes\"");
+                }
+                s.push_str("/>\n");
+            }
+            s.push_str("    </contour>\n");
+        }
+    }
+    if let Some(ref components) = layer.components {
+        for component in components {
+            s.push_str(&format!(
+                "    <component base=\"{}\"",
+                escape(&component.name)
+            ));
+            if let Some(transform) = component.transform {
+                let c = transform.as_coeffs();
+                let attrs = [
+                    ("xScale", c[0], 1.0),
+                    ("xyScale", c[1], 0.0),
+                    ("yxScale", c[2], 0.0),
+                    ("yScale", c[3], 1.0),
+                    ("xOffset", c[4], 0.0),
+                    ("yOffset", c[5], 0.0),
+                ];
+                for &(name, value, default) in &attrs {
+                    if value != default {
+                        s.push_str(&format!(" {}=\"{}\"", name, fmt_num(value)));
+                    }
+                }
+            }
+            s.push_str("/>\n");
+        }
+    }
+    s.push_str("  </outline>\n");
+    s.push_str("</glyph>\n");
+    s
+}
+
+fn string_array(strings: impl IntoIterator<Item = String>) -> Plist {
+    strings
+        .into_iter()
+        .map(Plist::String)
+        .collect::<Vec<_>>()
+        .into()
+}
+
+fn family_name(font: &Font) -> String {
+    font.other_stuff
+        .get("familyName")
+        .and_then(Plist::as_str)
+        .unwrap_or("Untitled")
+        .to_string()
+}
+
+fn fontinfo(font: &Font, master: &FontMaster) -> Plist {
+    let mut info = HashMap::new();
+    info.insert("familyName".to_string(), family_name(font).into());
+    info.insert("styleName".to_string(), master.name().into());
+    let font_keys = [
+        ("unitsPerEm", "unitsPerEm"),
+        ("versionMajor", "versionMajor"),
+        ("versionMinor", "versionMinor"),
+        ("copyright", "copyright"),
+        ("designer", "openTypeNameDesigner"),
+        ("designerURL", "openTypeNameDesignerURL"),
+        ("manufacturer", "openTypeNameManufacturer"),
+        ("manufacturerURL", "openTypeNameManufacturerURL"),
+    ];
+    for &(glyphs_key, ufo_key) in &font_keys {
+        if let Some(value) = font.other_stuff.get(glyphs_key) {
+            info.insert(ufo_key.to_string(), value.clone());
+        }
+    }
+    let master_keys = [
+        ("ascender", "ascender"),
+        ("descender", "descender"),
+        ("xHeight", "xHeight"),
+        ("capHeight", "capHeight"),
+        ("italicAngle", "italicAngle"),
+    ];
+    for &(glyphs_key, ufo_key) in &master_keys {
+        if let Some(value) = master.other_stuff.get(glyphs_key) {
+            info.insert(ufo_key.to_string(), value.clone());
+        }
+    }
+    info.into()
+}
+
+/// Kerning for one master, with Glyphs class names converted to UFO groups.
+fn kerning(font: &Font, master_id: &str) -> Plist {
+    fn convert(key: &str) -> String {
+        if let Some(name) = key.strip_prefix("@MMK_L_") {
+            format!("public.kern1.{}", name)
+        } else if let Some(name) = key.strip_prefix("@MMK_R_") {
+            format!("public.kern2.{}", name)
+        } else {
+            key.to_string()
+        }
+    }
+    let mut result = HashMap::new();
+    let master_kerning = font
+        .other_stuff
+        .get("kerning")
+        .and_then(|k| k.get(master_id))
+        .and_then(Plist::as_dict);
+    if let Some(master_kerning) = master_kerning {
+        for (left, pairs) in master_kerning {
+            if let Some(pairs) = pairs.as_dict() {
+                let pairs: HashMap<String, Plist> = pairs
+                    .iter()
+                    .map(|(right, value)| (convert(right), value.clone()))
+                    .collect();
+                result.insert(convert(left), pairs.into());
+            }
+        }
+    }
+    result.into()
+}
+
+/// Kerning groups, from the glyphs' kerning group assignments.
+fn groups(font: &Font) -> Plist {
+    let mut groups: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for glyph in &font.glyphs {
+        let sides = [
+            ("rightKerningGroup", "public.kern1."),
+            ("leftKerningGroup", "public.kern2."),
+        ];
+        for &(key, prefix) in &sides {
+            if let Some(group) = glyph.other_stuff.get(key).and_then(Plist::as_str) {
+                groups
+                    .entry(format!("{}{}", prefix, group))
+                    .or_default()
+                    .push(glyph.glyphname.clone());
+            }
+        }
+    }
+    let groups: HashMap<String, Plist> = groups
+        .into_iter()
+        .map(|(name, glyphs)| (name, string_array(glyphs)))
+        .collect();
+    groups.into()
+}
+
+/// Feature code, assembled from classes, prefixes and features.
+fn features(font: &Font) -> String {
+    let mut fea = String::new();
+    let items = |key: &str| -> Vec<Plist> {
+        font.other_stuff
+            .get(key)
+            .and_then(Plist::as_array)
+            .map(|a| a.to_vec())
+            .unwrap_or_default()
+    };
+    let get = |p: &Plist, key: &str| -> String {
+        p.get(key).and_then(Plist::as_str).unwrap_or("").to_string()
+    };
+    for class in items("classes") {
+        fea.push_str(&format!(
+            "@{} = [{}];\n",
+            get(&class, "name"),
+            get(&class, "code")
+        ));
+    }
+    for prefix in items("featurePrefixes") {
+        fea.push_str(&get(&prefix, "code"));
+        fea.push('\n');
+    }
+    for feature in items("features") {
+        let tag = get(&feature, "name");
+        fea.push_str(&format!(
+            "feature {} {{\n{}\n}} {};\n",
+            tag,
+            get(&feature, "code"),
+            tag
+        ));
+    }
+    fea
+}
+
+/// Write a glyph set (one layer of a UFO) into `dir`.
+fn write_glyph_set<'a>(
+    dir: &FsPath,
+    glyphs: impl Iterator<Item = (&'a Glyph, &'a Layer)>,
+) -> io::Result<()> {
+    fs::create_dir_all(dir)?;
+    let mut existing = HashSet::new();
+    let mut contents = HashMap::new();
+    for (glyph, layer) in glyphs {
+        let file_name = user_name_to_file_name(&glyph.glyphname, "", ".glif", &mut existing);
+        let glif = layer_to_glif(&glyph.glyphname, &unicodes(glyph), layer);
+        fs::write(dir.join(&file_name), glif)?;
+        contents.insert(glyph.glyphname.clone(), Plist::String(file_name));
+    }
+    fs::write(dir.join("contents.plist"), plist_to_xml(&contents.into()))
+}
+
+/// The brace layers associated with a master, keyed by layer name.
+fn brace_layers(font: &Font, master_id: &str) -> BTreeMap<String, Vec<f64>> {
+    let mut result = BTreeMap::new();
+    for glyph in &font.glyphs {
+        for layer in &glyph.layers {
+            let associated = layer
+                .other_stuff
+                .get("associatedMasterId")
+                .and_then(Plist::as_str);
+            if associated == Some(master_id) {
+                if let (Some(name), Some(coords)) = (layer.name(), layer.brace_coords()) {
+                    result.insert(name.to_string(), coords);
+                }
+            }
+        }
+    }
+    result
+}
+
+fn write_ufo(font: &Font, master: &FontMaster, ufo_dir: &FsPath) -> io::Result<()> {
+    fs::create_dir_all(ufo_dir)?;
+    let mut metainfo = HashMap::new();
+    metainfo.insert(
+        "creator".to_string(),
+        "org.linebender.glyphstool".to_string().into(),
+    );
+    metainfo.insert("formatVersion".to_string(), Plist::Integer(3));
+    fs::write(
+        ufo_dir.join("metainfo.plist"),
+        plist_to_xml(&metainfo.into()),
+    )?;
+    fs::write(
+        ufo_dir.join("fontinfo.plist"),
+        plist_to_xml(&fontinfo(font, master)),
+    )?;
+    fs::write(
+        ufo_dir.join("kerning.plist"),
+        plist_to_xml(&kerning(font, &master.id)),
+    )?;
+    fs::write(ufo_dir.join("groups.plist"), plist_to_xml(&groups(font)))?;
+    fs::write(ufo_dir.join("features.fea"), features(font))?;
+    let mut lib = HashMap::new();
+    let glyph_order = font.glyphs.iter().map(|g| g.glyphname.clone());
+    lib.insert("public.glyphOrder".to_string(), string_array(glyph_order));
+    fs::write(ufo_dir.join("lib.plist"), plist_to_xml(&lib.into()))?;
+
+    let master_glyphs = font
+        .glyphs
+        .iter()
+        .filter_map(|g| g.get_layer(&master.id).map(|l| (g, l)));
+    write_glyph_set(&ufo_dir.join("glyphs"), master_glyphs)?;
+    let mut layer_contents = vec![string_array(vec![
+        "public.default".to_string(),
+        "glyphs".to_string(),
+    ])];
+    let mut existing = HashSet::new();
+    existing.insert("glyphs".to_string());
+    for layer_name in brace_layers(font, &master.id).keys() {
+        let dir_name = user_name_to_file_name(layer_name, "glyphs.", "", &mut existing);
+        let glyphs = font.glyphs.iter().filter_map(|g| {
+            g.layers
+                .iter()
+                .find(|l| l.name() == Some(layer_name.as_str()) && l.brace_coords().is_some())
+                .map(|l| (g, l))
+        });
+        write_glyph_set(&ufo_dir.join(&dir_name), glyphs)?;
+        layer_contents.push(string_array(vec![layer_name.clone(), dir_name]));
+    }
+    fs::write(
+        ufo_dir.join("layercontents.plist"),
+        plist_to_xml(&layer_contents.into()),
+    )
+}
+
+/// Export a font with a single master, such as an instance, as one UFO
+/// at `path`. A font with several masters is an error; those are exported
+/// with `export_ufo`.
+pub fn export_master_ufo(font: &Font, path: &FsPath) -> Result<(), String> {
+    match font.font_master.as_slice() {
+        [master] => write_ufo(font, master, path).map_err(|e| e.to_string()),
+        masters => Err(format!(
+            "can't write a font with {} masters as one UFO",
+            masters.len()
+        )),
+    }
+}
+
+fn location_xml(axis_names: &[String], location: &[f64], indent: &str) -> String {
+    let mut s = format!("{}<location>\n", indent);
+    for (name, value) in axis_names.iter().zip(location) {
+        s.push_str(&format!(
+            "{}  <dimension name=\"{}\" xvalue=\"{}\"/>\n",
+            indent,
+            escape(name),
+            fmt_num(*value)
+        ));
+    }
+    s.push_str(&format!("{}</location>\n", indent));
+    s
+}
+
+/// Export the font as one UFO per master plus a designspace document, all
+/// written into `dir`. Returns the path of the designspace file.
+pub fn export_ufo(font: &Font, dir: &FsPath) -> io::Result<std::path::PathBuf> {
+    fs::create_dir_all(dir)?;
+    let family = family_name(font);
+    let axes = font.axes();
+    let axis_names: Vec<String> = axes.iter().map(|a| a.name.clone()).collect();
+    let locations: Vec<Vec<f64>> = font.font_master.iter().map(|m| m.location(&axes)).collect();
+
+    let mut ds = String::new();
+    ds.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    ds.push_str("<designspace format=\"4.0\">\n");
+    ds.push_str("  <axes>\n");
+    for (i, axis) in axes.iter().enumerate() {
+        let values = locations.iter().map(|l| l[i]);
+        let min = values.clone().fold(f64::INFINITY, f64::min);
+        let max = values.fold(f64::NEG_INFINITY, f64::max);
+        let default = locations.first().map(|l| l[i]).unwrap_or(0.0);
+        ds.push_str(&format!(
+            "    <axis tag=\"{}\" name=\"{}\" minimum=\"{}\" maximum=\"{}\" default=\"{}\"/>\n",
+            escape(&axis.tag),
+            escape(&axis.name),
+            fmt_num(min),
+            fmt_num(max),
+            fmt_num(default)
+        ));
+    }
+    ds.push_str("  </axes>\n");
+
+    ds.push_str("  <sources>\n");
+    for (master, location) in font.font_master.iter().zip(&locations) {
+        let style = master.name();
+        let ufo_name = format!("{}-{}.ufo", file_safe(&family), file_safe(&style));
+        write_ufo(font, master, &dir.join(&ufo_name))?;
+        ds.push_str(&format!(
+            "    <source filename=\"{}\" name=\"{}\" familyname=\"{}\" stylename=\"{}\">\n",
+            escape(&ufo_name),
+            escape(&format!("{} {}", family, style)),
+            escape(&family),
+            escape(&style)
+        ));
+        ds.push_str(&location_xml(&axis_names, location, "      "));
+        ds.push_str("    </source>\n");
+        for (layer_name, coords) in brace_layers(font, &master.id) {
+            ds.push_str(&format!(
+                "    <source filename=\"{}\" name=\"{} {}\" layer=\"{}\">\n",
+                escape(&ufo_name),
+                escape(&family),
+                escape(&layer_name),
+                escape(&layer_name)
+            ));
+            // Brace coordinates may omit trailing axes; take those from
+            // the associated master.
+            let location: Vec<f64> = (0..axes.len())
+                .map(|i| coords.get(i).copied().unwrap_or(location[i]))
+                .collect();
+            ds.push_str(&location_xml(&axis_names, &location, "      "));
+            ds.push_str("    </source>\n");
+        }
+    }
+    ds.push_str("  </sources>\n");
+
+    let instances = font.instances();
+    if !instances.is_empty() {
+        ds.push_str("  <instances>\n");
+        for (style, location) in &instances {
+            ds.push_str(&format!(
+                "    <instance familyname=\"{}\" stylename=\"{}\" filename=\"instances/{}-{}.ufo\">\n",
+                escape(&family),
+                escape(style),
+                escape(&file_safe(&family)),
+                escape(&file_safe(style))
+            ));
+            ds.push_str(&location_xml(&axis_names, location, "      "));
+            ds.push_str("    </instance>\n");
+        }
+        ds.push_str("  </instances>\n");
+    }
+    ds.push_str("</designspace>\n");
+
+    let ds_path = dir.join(format!("{}.designspace", file_safe(&family)));
+    fs::write(&ds_path, ds)?;
+    Ok(ds_path)
+}
+
+/// Font-level fontinfo keys, as (Glyphs key, UFO key).
+const FONT_INFO_KEYS: &[(&str, &str)] = &[
+    ("familyName", "familyName"),
+    ("unitsPerEm", "unitsPerEm"),
+    ("versionMajor", "versionMajor"),
+    ("versionMinor", "versionMinor"),
+    ("copyright", "copyright"),
+    ("designer", "openTypeNameDesigner"),
+    ("designerURL", "openTypeNameDesignerURL"),
+    ("manufacturer", "openTypeNameManufacturer"),
+    ("manufacturerURL", "openTypeNameManufacturerURL"),
+];
+
+/// Master-level fontinfo keys; these are the same in both formats.
+const MASTER_INFO_KEYS: &[&str] = &[
+    "ascender",
+    "descender",
+    "xHeight",
+    "capHeight",
+    "italicAngle",
+];
+
+/// A source from a designspace document.
+struct Source {
+    filename: String,
+    name: Option<String>,
+    style: Option<String>,
+    /// The UFO layer, for sparse (brace) sources.
+    layer: Option<String>,
+    location: Vec<f64>,
+}
+
+/// A glyph as read from a .glif file.
+struct GlifGlyph {
+    name: String,
+    unicodes: Vec<String>,
+    layer: Layer,
+}
+
+fn read_xml_plist(path: &FsPath) -> Result<Option<Plist>, String> {
+    match fs::read_to_string(path) {
+        Ok(contents) => plist_from_xml(&contents)
+            .map(Some)
+            .map_err(|e| format!("{}: {}", path.display(), e)),
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(format!("{}: {:?}", path.display(), e)),
+    }
+}
+
+fn node_type(point: &Element) -> Result<NodeType, String> {
+    let smooth = point.attr("smooth") == Some("yes");
+    Ok(match (point.attr("type"), smooth) {
+        (None, _) | (Some("offcurve"), _) => NodeType::OffCurve,
+        (Some("move"), false) | (Some("line"), false) => NodeType::Line,
+        (Some("move"), true) | (Some("line"), true) => NodeType::LineSmooth,
+        (Some("curve"), false) => NodeType::Curve,
+        (Some("curve"), true) => NodeType::CurveSmooth,
+        (Some("qcurve"), false) => NodeType::QCurve,
+        (Some("qcurve"), true) => NodeType::QCurveSmooth,
+        (Some(t), _) => return Err(format!("unknown point type {}", t)),
+    })
+}
+
+fn element_point(el: &Element) -> Point {
+    Point::new(
+        el.attr_f64("x").unwrap_or(0.0),
+        el.attr_f64("y").unwrap_or(0.0),
+    )
+}
+
+/// Parse a .glif file (format 1 or 2) into a layer.
+fn parse_glif(glif: &str, layer_id: &str) -> Result<GlifGlyph, String> {
+    let root = xml::parse(glif)?;
+    if root.name != "glyph" {
+        return Err(format!("expected glyph, got {}", root.name));
+    }
+    let name = root
+        .attr("name")
+        .ok_or_else(|| "glyph without name".to_string())?
+        .to_string();
+    let width = root
+        .child("advance")
+        .and_then(|a| a.attr_f64("width"))
+        .unwrap_or(0.0);
+    let unicodes = root
+        .children_named("unicode")
+        .filter_map(|u| u.attr("hex"))
+        .map(str::to_uppercase)
+        .collect();
+    let mut anchors: Vec<Anchor> = root
+        .children_named("anchor")
+        .map(|a| Anchor {
+            name: a.attr("name").unwrap_or("").to_string(),
+            position: element_point(a),
+        })
+        .collect();
+    let guide_lines: Vec<GuideLine> = root
+        .children_named("guideline")
+        .map(|g| {
+            // A guideline without x is vertical, without y horizontal.
+            let angle = match (g.attr("x"), g.attr("y")) {
+                (None, _) => 90.0,
+                (_, None) => 0.0,
+                _ => g.attr_f64("angle").unwrap_or(0.0),
+            };
+            GuideLine {
+                angle: Some(angle),
+                position: element_point(g),
+            }
+        })
+        .collect();
+    let mut paths = Vec::new();
+    let mut components = Vec::new();
+    if let Some(outline) = root.child("outline") {
+        for el in &outline.children {
+            match el.name.as_str() {
+                "contour" => {
+                    let points: Vec<&Element> = el.children_named("point").collect();
+                    // Format 1 stores anchors as named single-point contours.
+                    if points.len() == 1 && points[0].attr("type") == Some("move") {
+                        if let Some(name) = points[0].attr("name") {
+                            anchors.push(Anchor {
+                                name: name.to_string(),
+                                position: element_point(points[0]),
+                            });
+                            continue;
+                        }
+                    }
+                    let closed = points.first().and_then(|p| p.attr("type")) != Some("move");
+                    let nodes = points
+                        .iter()
+                        .map(|p| {
+                            Ok(Node {
+                                pt: element_point(p),
+                                node_type: node_type(p)?,
+                            })
+                        })
+                        .collect::<Result<_, String>>()?;
+                    let mut path = Path { closed, nodes };
+                    // The start node goes last, as in Glyphs.
+                    if closed {
+                        path.rotate_left(1);
+                    }
+                    paths.push(path);
+                }
+                "component" => {
+                    let coeff = |key: &str, default: f64| el.attr_f64(key).unwrap_or(default);
+                    let transform = Affine::new([
+                        coeff("xScale", 1.0),
+                        coeff("xyScale", 0.0),
+                        coeff("yxScale", 0.0),
+                        coeff("yScale", 1.0),
+                        coeff("xOffset", 0.0),
+                        coeff("yOffset", 0.0),
+                    ]);
+                    components.push(Component {
+                        name: el.attr("base").unwrap_or("").to_string(),
+                        transform: if transform.as_coeffs() == [1.0, 0.0, 0.0, 1.0, 0.0, 0.0] {
+                            None
+                        } else {
+                            Some(transform)
+                        },
+                        other_stuff: HashMap::new(),
+                    });
+                }
+                _ => (),
+            }
+        }
+    }
+    fn non_empty<T>(v: Vec<T>) -> Option<Vec<T>> {
+        if v.is_empty() {
+            None
+        } else {
+            Some(v)
+        }
+    }
+    let layer = Layer {
+        layer_id: layer_id.to_string(),
+        width,
+        paths: non_empty(paths),
+        components: non_empty(components),
+        anchors: non_empty(anchors),
+        guide_lines: non_empty(guide_lines),
+        other_stuff: HashMap::new(),
+    };
+    Ok(GlifGlyph {
+        name,
+        unicodes,
+        layer,
+    })
+}
+
+/// Read the glyphs of one layer of a UFO (the default layer if `layer_name`
+/// is `None`), sorted by name. The order of contents.plist isn't kept; the
+/// font takes its order from public.glyphOrder instead.
+fn read_glyph_set(
+    ufo_dir: &FsPath,
+    layer_name: Option<&str>,
+    layer_id: &str,
+) -> Result<Vec<GlifGlyph>, String> {
+    let layer_contents = read_xml_plist(&ufo_dir.join("layercontents.plist"))?;
+    let layers: Vec<(String, String)> = layer_contents
+        .as_ref()
+        .and_then(Plist::as_array)
+        .map(|layers| {
+            layers
+                .iter()
+                .filter_map(|l| {
+                    let l = l.as_array()?;
+                    Some((l.first()?.as_str()?.into(), l.get(1)?.as_str()?.into()))
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    let dir_name = match layer_name {
+        None => "glyphs".to_string(),
+        Some(name) => layers
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, dir)| dir.clone())
+            .ok_or_else(|| format!("{}: no layer named {}", ufo_dir.display(), name))?,
+    };
+    let dir = ufo_dir.join(dir_name);
+    let contents = read_xml_plist(&dir.join("contents.plist"))?
+        .ok_or_else(|| format!("{}: missing contents.plist", dir.display()))?;
+    let contents = contents
+        .as_dict()
+        .ok_or_else(|| format!("{}: malformed contents.plist", dir.display()))?;
+    let mut entries: Vec<(&String, &Plist)> = contents.iter().collect();
+    entries.sort_by(|a, b| a.0.cmp(b.0));
+    let mut glyphs = Vec::new();
+    for (glyphname, file_name) in entries {
+        let path = dir.join(file_name.as_str().unwrap_or(""));
+        let glif = fs::read_to_string(&path).map_err(|e| format!("{}: {:?}", path.display(), e))?;
+        let mut glyph =
+            parse_glif(&glif, layer_id).map_err(|e| format!("{}: {}", path.display(), e))?;
+        glyph.name = glyphname.clone();
+        glyphs.push(glyph);
+    }
+    Ok(glyphs)
+}
+
+pub(crate) fn brace_name(location: &[f64]) -> String {
+    let coords: Vec<String> = location.iter().map(|&x| fmt_num(x)).collect();
+    format!("{{{}}}", coords.join(", "))
+}
+
+/// Convert a UFO kerning group name to the Glyphs convention.
+fn glyphs_kern_name(name: &str) -> String {
+    if let Some(group) = name.strip_prefix("public.kern1.") {
+        format!("@MMK_L_{}", group)
+    } else if let Some(group) = name.strip_prefix("public.kern2.") {
+        format!("@MMK_R_{}", group)
+    } else {
+        name.to_string()
+    }
+}
+
+fn build_font(
+    axes: &[Axis],
+    sources: &[Source],
+    base_dir: &FsPath,
+    instances: &[(String, Vec<f64>)],
+) -> Result<Font, String> {
+    let master_sources: Vec<&Source> = sources.iter().filter(|s| s.layer.is_none()).collect();
+    let first = match master_sources.first() {
+        Some(first) => first,
+        None => return Err("no master sources".to_string()),
+    };
+    let first_dir = base_dir.join(&first.filename);
+
+    let mut other_stuff = HashMap::new();
+    let fontinfo = read_xml_plist(&first_dir.join("fontinfo.plist"))?;
+    for &(glyphs_key, ufo_key) in FONT_INFO_KEYS {
+        if let Some(value) = fontinfo.as_ref().and_then(|info| info.get(ufo_key)) {
+            other_stuff.insert(glyphs_key.to_string(), value.clone());
+        }
+    }
+    let custom_parameters: Vec<Plist> = vec![axes_parameter(axes)];
+    other_stuff.insert("customParameters".to_string(), custom_parameters.into());
+
+    let mut font_master = Vec::new();
+    let mut glyphs: Vec<Glyph> = Vec::new();
+    let mut glyph_ix: HashMap<String, usize> = HashMap::new();
+    let glyph_order = read_xml_plist(&first_dir.join("lib.plist"))?
+        .and_then(|lib| lib.get("public.glyphOrder").cloned());
+    if let Some(order) = glyph_order.as_ref().and_then(Plist::as_array) {
+        for name in order.iter().filter_map(Plist::as_str) {
+            glyph_ix.insert(name.to_string(), glyphs.len());
+            glyphs.push(Glyph {
+                layers: Vec::new(),
+                glyphname: name.to_string(),
+                other_stuff: HashMap::new(),
+            });
+        }
+    }
+    let mut add_glyphs = |glyph_set: Vec<GlifGlyph>, glyphs: &mut Vec<Glyph>| {
+        for glif in glyph_set {
+            let ix = *glyph_ix.entry(glif.name.clone()).or_insert_with(|| {
+                glyphs.push(Glyph {
+                    layers: Vec::new(),
+                    glyphname: glif.name.clone(),
+                    other_stuff: HashMap::new(),
+                });
+                glyphs.len() - 1
+            });
+            let glyph = &mut glyphs[ix];
+            if !glif.unicodes.is_empty() && !glyph.other_stuff.contains_key("unicode") {
+                glyph
+                    .other_stuff
+                    .insert("unicode".to_string(), glif.unicodes.join(",").into());
+            }
+            glyph.layers.push(glif.layer);
+        }
+    };
+
+    let mut kerning = HashMap::new();
+    for (i, source) in master_sources.iter().enumerate() {
+        let ufo_dir = base_dir.join(&source.filename);
+        let id = source
+            .name
+            .clone()
+            .unwrap_or_else(|| format!("master{}", i));
+        let fontinfo = read_xml_plist(&ufo_dir.join("fontinfo.plist"))?;
+        let mut master_stuff = HashMap::new();
+        let style = source.style.clone().or_else(|| {
+            fontinfo
+                .as_ref()
+                .and_then(|info| info.get("styleName"))
+                .and_then(Plist::as_str)
+                .map(String::from)
+        });
+        if let Some(style) = style {
+            master_stuff.insert("name".to_string(), style.into());
+        }
+        for &key in MASTER_INFO_KEYS {
+            if let Some(value) = fontinfo.as_ref().and_then(|info| info.get(key)) {
+                master_stuff.insert(key.to_string(), value.clone());
+            }
+        }
+        let mut master = FontMaster {
+            id: id.clone(),
+            weight_value: 400,
+            width_value: None,
+            other_stuff: master_stuff,
+        };
+        master.set_location(axes, &source.location);
+        font_master.push(master);
+        add_glyphs(read_glyph_set(&ufo_dir, None, &id)?, &mut glyphs);
+
+        if let Some(master_kerning) = read_xml_plist(&ufo_dir.join("kerning.plist"))? {
+            let master_kerning: HashMap<String, Plist> = master_kerning
+                .as_dict()
+                .into_iter()
+                .flatten()
+                .map(|(left, pairs)| {
+                    let pairs: HashMap<String, Plist> = pairs
+                        .as_dict()
+                        .into_iter()
+                        .flatten()
+                        .map(|(right, value)| (glyphs_kern_name(right), value.clone()))
+                        .collect();
+                    (glyphs_kern_name(left), pairs.into())
+                })
+                .collect();
+            kerning.insert(id, master_kerning.into());
+        }
+    }
+    other_stuff.insert("kerning".to_string(), kerning.into());
+
+    for source in sources {
+        let layer_name = match source.layer {
+            Some(ref layer_name) => layer_name,
+            None => continue,
+        };
+        let master_ix = master_sources
+            .iter()
+            .position(|s| s.filename == source.filename)
+            .unwrap_or(0);
+        let master_id = font_master[master_ix].id.clone();
+        let layer_id = format!("{}.{}", master_id, layer_name);
+        let mut glyph_set = read_glyph_set(
+            &base_dir.join(&source.filename),
+            Some(layer_name),
+            &layer_id,
+        )?;
+        for glif in &mut glyph_set {
+            let stuff = &mut glif.layer.other_stuff;
+            stuff.insert("name".to_string(), brace_name(&source.location).into());
+            stuff.insert("associatedMasterId".to_string(), master_id.clone().into());
+        }
+        add_glyphs(glyph_set, &mut glyphs);
+    }
+    glyphs.retain(|g| !g.layers.is_empty());
+
+    if let Some(groups) = read_xml_plist(&first_dir.join("groups.plist"))? {
+        for (group, members) in groups.as_dict().into_iter().flatten() {
+            let (key, name) = if let Some(name) = group.strip_prefix("public.kern1.") {
+                ("rightKerningGroup", name)
+            } else if let Some(name) = group.strip_prefix("public.kern2.") {
+                ("leftKerningGroup", name)
+            } else {
+                continue;
+            };
+            for member in members.as_array().unwrap_or(&[]) {
+                let glyph = member
+                    .as_str()
+                    .and_then(|m| glyphs.iter_mut().find(|g| g.glyphname == m));
+                if let Some(glyph) = glyph {
+                    glyph
+                        .other_stuff
+                        .insert(key.to_string(), name.to_string().into());
+                }
+            }
+        }
+    }
+
+    if let Ok(fea) = fs::read_to_string(first_dir.join("features.fea")) {
+        if !fea.trim().is_empty() {
+            let mut prefix = HashMap::new();
+            prefix.insert("name".to_string(), "Prefix".to_string().into());
+            prefix.insert("code".to_string(), fea.into());
+            let prefixes: Vec<Plist> = vec![prefix.into()];
+            other_stuff.insert("featurePrefixes".to_string(), prefixes.into());
+        }
+    }
+
+    if !instances.is_empty() {
+        let instances: Vec<Plist> = instances
+            .iter()
+            .map(|(name, location)| instance_plist(name, axes, location))
+            .collect();
+        other_stuff.insert("instances".to_string(), instances.into());
+    }
+
+    Ok(Font {
+        glyphs,
+        font_master,
+        other_stuff,
+    })
+}
+
+/// Map a value of an axis from user space to design space through its
+/// `<map>` elements, piecewise linearly. Beyond the first or last map the
+/// value is shifted as at that map; without maps the spaces are the same.
+fn user_to_design(axis: &Element, value: f64) -> f64 {
+    let mut maps: Vec<(f64, f64)> = axis
+        .children_named("map")
+        .filter_map(|m| Some((m.attr_f64("input")?, m.attr_f64("output")?)))
+        .collect();
+    maps.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+    let (first, last) = match (maps.first(), maps.last()) {
+        (Some(&first), Some(&last)) => (first, last),
+        _ => return value,
+    };
+    if value <= first.0 {
+        return value + first.1 - first.0;
+    }
+    if value >= last.0 {
+        return value + last.1 - last.0;
+    }
+    let i = maps.iter().position(|&(input, _)| input > value).unwrap();
+    let ((x0, y0), (x1, y1)) = (maps[i - 1], maps[i]);
+    y0 + (y1 - y0) * (value - x0) / (x1 - x0)
+}
+
+fn element_location(el: &Element, axes: &[(Axis, f64)]) -> Vec<f64> {
+    let dimensions: Vec<&Element> = el
+        .child("location")
+        .map(|l| l.children_named("dimension").collect())
+        .unwrap_or_default();
+    axes.iter()
+        .map(|(axis, default)| {
+            dimensions
+                .iter()
+                .find(|d| d.attr("name") == Some(axis.name.as_str()))
+                .and_then(|d| d.attr_f64("xvalue"))
+                .unwrap_or(*default)
+        })
+        .collect()
+}
+
+/// Read a designspace document and the UFOs it references.
+///
+/// Sources on the default layer of a UFO become masters; sources on other
+/// layers become brace layers associated with the master from the same UFO.
+pub fn import_designspace(path: &FsPath) -> Result<Font, String> {
+    let contents = fs::read_to_string(path).map_err(|e| format!("{:?}", e))?;
+    let root = xml::parse(&contents)?;
+    if root.name != "designspace" {
+        return Err(format!("expected designspace, got {}", root.name));
+    }
+    let axes: Vec<(Axis, f64)> = root
+        .child("axes")
+        .map(|axes| {
+            axes.children_named("axis")
+                .map(|a| {
+                    let axis = Axis {
+                        name: a.attr("name").unwrap_or("").to_string(),
+                        tag: a.attr("tag").unwrap_or("").to_string(),
+                    };
+                    // The default is in user space, locations in design
+                    // space.
+                    let default = a.attr_f64("default").unwrap_or(0.0);
+                    (axis, user_to_design(a, default))
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    let sources: Vec<Source> = root
+        .child("sources")
+        .map(|sources| {
+            sources
+                .children_named("source")
+                .map(|s| Source {
+                    filename: s.attr("filename").unwrap_or("").to_string(),
+                    name: s.attr("name").map(String::from),
+                    style: s.attr("stylename").map(String::from),
+                    layer: s.attr("layer").map(String::from),
+                    location: element_location(s, &axes),
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    let instances: Vec<(String, Vec<f64>)> = root
+        .child("instances")
+        .map(|instances| {
+            instances
+                .children_named("instance")
+                .map(|i| {
+                    let name = i.attr("stylename").unwrap_or("Regular").to_string();
+                    (name, element_location(i, &axes))
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    let axes: Vec<Axis> = axes.into_iter().map(|(axis, _)| axis).collect();
+    let base_dir = path.parent().unwrap_or_else(|| FsPath::new("."));
+    build_font(&axes, &sources, base_dir, &instances)
+}
+
+/// Read a single UFO as a font with one master.
+///
+/// The master is placed on the weight axis according to the OS/2 weight
+/// class, if present.
+pub fn import_ufo(path: &FsPath) -> Result<Font, String> {
+    let fontinfo = read_xml_plist(&path.join("fontinfo.plist"))?;
+    let weight = fontinfo
+        .as_ref()
+        .and_then(|info| info.get("openTypeOS2WeightClass"))
+        .and_then(Plist::as_f64)
+        .unwrap_or(400.0);
+    let axes = [Axis {
+        name: "Weight".into(),
+        tag: "wght".into(),
+    }];
+    let source = Source {
+        filename: path
+            .file_name()
+            .map(|f| f.to_string_lossy().into_owned())
+            .unwrap_or_default(),
+        name: None,
+        style: None,
+        layer: None,
+        location: vec![weight],
+    };
+    let base_dir = path.parent().unwrap_or_else(|| FsPath::new("."));
+    build_font(&axes, &[source], base_dir, &[])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The start node of a closed contour, last in Glyphs, is the first
+    /// point in the .glif, and comes back last.
+    #[test]
+    fn glif_round_trip_start_point() {
+        let mut path = Path::new(true);
+        path.add((0.0, 0.0), NodeType::Line);
+        path.add((100.0, 0.0), NodeType::Line);
+        path.add((100.0, 100.0), NodeType::Line);
+        let layer = Layer {
+            layer_id: "m".to_string(),
+            width: 500.0,
+            paths: Some(vec![path]),
+            components: None,
+            anchors: None,
+            guide_lines: None,
+            other_stuff: HashMap::new(),
+        };
+        let glif = layer_to_glif("a", &[], &layer);
+        let first = glif.find("<point").unwrap();
+        assert!(
+            glif[first..].starts_with("<point x=\"100\" y=\"100\""),
+            "{}",
+            glif
+        );
+        let glyph = parse_glif(&glif, "m").unwrap();
+        let pts = |layer: &Layer| -> Vec<Point> {
+            layer.paths.as_ref().unwrap()[0]
+                .nodes
+                .iter()
+                .map(|node| node.pt)
+                .collect()
+        };
+        assert_eq!(pts(&glyph.layer), pts(&layer));
+    }
+
+    #[test]
+    fn map_default_to_design() {
+        let axis = xml::parse(
+            r#"<axis tag="wght" name="Weight" minimum="100" maximum="900" default="400">
+                <map input="100" output="20"/>
+                <map input="900" output="180"/>
+                <map input="400" output="80"/>
+            </axis>"#,
+        )
+        .unwrap();
+        assert_eq!(user_to_design(&axis, 400.0), 80.0);
+        assert_eq!(user_to_design(&axis, 650.0), 130.0);
+        assert_eq!(user_to_design(&axis, 1000.0), 280.0);
+        let unmapped = xml::parse(r#"<axis tag="wdth" name="Width" default="100"/>"#).unwrap();
+        assert_eq!(user_to_design(&unmapped, 100.0), 100.0);
+    }
+}
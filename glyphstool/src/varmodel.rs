@@ -0,0 +1,289 @@
+//! The OpenType variation model.
+//!
+//! This is a port of `VariationModel` from fontTools.varLib.models. Given the
+//! normalized locations of a set of masters, it assigns each master a
+//! support region, such that values anywhere in the design space are a sum
+//! of deltas weighted by the scalar of each region. This is what gvar
+//! (and the other variation tables) encode.
+
+use std::cmp::Ordering;
+use std::ops::{Add, Mul, Sub};
+
+/// A region of normalized design space: (lower, peak, upper) per axis, or
+/// `None` for axes the region doesn't depend on.
+pub type Support = Vec<Option<(f64, f64, f64)>>;
+
+/// Normalize a design space coordinate to the range -1..1, given the
+/// (minimum, default, maximum) of the axis.
+pub fn normalize_value(v: f64, (min, default, max): (f64, f64, f64)) -> f64 {
+    let v = v.max(min).min(max);
+    if v < default {
+        (v - default) / (default - min)
+    } else if v > default {
+        (v - default) / (max - default)
+    } else {
+        0.0
+    }
+}
+
+/// The scalar of a support region at a location, as defined by OpenType.
+pub fn support_scalar(loc: &[f64], support: &[Option<(f64, f64, f64)>]) -> f64 {
+    let mut scalar = 1.0;
+    for (axis, region) in support.iter().enumerate() {
+        let (lower, peak, upper) = match *region {
+            Some(region) => region,
+            None => continue,
+        };
+        if peak == 0.0 || lower > peak || peak > upper || (lower < 0.0 && upper > 0.0) {
+            continue;
+        }
+        let v = loc.get(axis).copied().unwrap_or(0.0);
+        if v == peak {
+            continue;
+        }
+        if v <= lower || upper <= v {
+            return 0.0;
+        }
+        if v < peak {
+            scalar *= (v - lower) / (peak - lower);
+        } else {
+            scalar *= (v - upper) / (peak - upper);
+        }
+    }
+    scalar
+}
+
+pub struct VariationModel {
+    /// Master locations, in model order.
+    pub locations: Vec<Vec<f64>>,
+    /// The support region of each master, in model order.
+    pub supports: Vec<Support>,
+    /// For each master in model order, its index in the original order.
+    pub reverse_mapping: Vec<usize>,
+    delta_weights: Vec<Vec<(usize, f64)>>,
+}
+
+/// The sort key fontTools uses to order masters: fewer axes first, then
+/// masters on an axis, then by axis, sign and magnitude.
+struct SortKey {
+    rank: usize,
+    on_point_axes: usize,
+    axes: Vec<usize>,
+    signs: Vec<i32>,
+    magnitudes: Vec<f64>,
+}
+
+impl SortKey {
+    fn cmp(&self, other: &SortKey) -> Ordering {
+        self.rank
+            .cmp(&other.rank)
+            .then(other.on_point_axes.cmp(&self.on_point_axes))
+            .then(self.axes.cmp(&other.axes))
+            .then(self.signs.cmp(&other.signs))
+            .then(
+                self.magnitudes
+                    .partial_cmp(&other.magnitudes)
+                    .unwrap_or(Ordering::Equal),
+            )
+    }
+}
+
+fn active_axes(loc: &[f64]) -> Vec<usize> {
+    (0..loc.len()).filter(|&i| loc[i] != 0.0).collect()
+}
+
+impl VariationModel {
+    /// Build a model from normalized master locations. One of the
+    /// locations must be the default (all zeros).
+    pub fn new(locations: &[Vec<f64>]) -> Result<VariationModel, String> {
+        let n_axes = locations.iter().map(Vec::len).max().unwrap_or(0);
+        let locations: Vec<Vec<f64>> = locations
+            .iter()
+            .map(|loc| {
+                let mut loc = loc.clone();
+                loc.resize(n_axes, 0.0);
+                loc
+            })
+            .collect();
+        if !locations.iter().any(|loc| loc.iter().all(|&v| v == 0.0)) {
+            return Err("no master at the default location".to_string());
+        }
+        for (i, loc) in locations.iter().enumerate() {
+            if locations[..i].contains(loc) {
+                return Err(format!("duplicate master location {:?}", loc));
+            }
+        }
+
+        // Values present on each axis among masters on a single axis.
+        let mut axis_points = vec![vec![0.0]; n_axes];
+        for loc in &locations {
+            let axes = active_axes(loc);
+            if axes.len() == 1 {
+                axis_points[axes[0]].push(loc[axes[0]]);
+            }
+        }
+        let sort_key = |loc: &Vec<f64>| {
+            let axes = active_axes(loc);
+            SortKey {
+                rank: axes.len(),
+                on_point_axes: axes
+                    .iter()
+                    .filter(|&&a| axis_points[a].contains(&loc[a]))
+                    .count(),
+                signs: axes.iter().map(|&a| loc[a].signum() as i32).collect(),
+                magnitudes: axes.iter().map(|&a| loc[a].abs()).collect(),
+                axes,
+            }
+        };
+        let mut reverse_mapping: Vec<usize> = (0..locations.len()).collect();
+        reverse_mapping.sort_by(|&a, &b| sort_key(&locations[a]).cmp(&sort_key(&locations[b])));
+        let locations: Vec<Vec<f64>> = reverse_mapping
+            .iter()
+            .map(|&i| locations[i].clone())
+            .collect();
+
+        let supports = compute_supports(&locations, n_axes);
+        let delta_weights = locations
+            .iter()
+            .enumerate()
+            .map(|(i, loc)| {
+                (0..i)
+                    .filter_map(|j| {
+                        let scalar = support_scalar(loc, &supports[j]);
+                        if scalar != 0.0 {
+                            Some((j, scalar))
+                        } else {
+                            None
+                        }
+                    })
+                    .collect()
+            })
+            .collect();
+        Ok(VariationModel {
+            locations,
+            supports,
+            reverse_mapping,
+            delta_weights,
+        })
+    }
+
+    /// Compute deltas (in model order) from master values (in the original
+    /// order).
+    pub fn get_deltas<T>(&self, master_values: &[T]) -> Vec<T>
+    where
+        T: Copy + Sub<Output = T> + Mul<f64, Output = T>,
+    {
+        let mut out: Vec<T> = Vec::with_capacity(master_values.len());
+        for (i, weights) in self.delta_weights.iter().enumerate() {
+            let mut delta = master_values[self.reverse_mapping[i]];
+            for &(j, weight) in weights {
+                delta = delta - out[j] * weight;
+            }
+            out.push(delta);
+        }
+        out
+    }
+
+    /// The scalar of each master's support at a location.
+    pub fn get_scalars(&self, loc: &[f64]) -> Vec<f64> {
+        self.supports
+            .iter()
+            .map(|support| support_scalar(loc, support))
+            .collect()
+    }
+
+    /// Interpolate a value at a location, given deltas from `get_deltas`.
+    pub fn interpolate_from_deltas<T>(&self, loc: &[f64], deltas: &[T]) -> T
+    where
+        T: Copy + Add<Output = T> + Mul<f64, Output = T>,
+    {
+        let scalars = self.get_scalars(loc);
+        // The default master's support is the whole space, so its scalar
+        // is always 1.
+        let mut result = deltas[0];
+        for (&delta, &scalar) in deltas.iter().zip(&scalars).skip(1) {
+            if scalar != 0.0 {
+                result = result + delta * scalar;
+            }
+        }
+        result
+    }
+}
+
+fn compute_supports(locations: &[Vec<f64>], n_axes: usize) -> Vec<Support> {
+    let mut min_v = vec![0.0f64; n_axes];
+    let mut max_v = vec![0.0f64; n_axes];
+    for loc in locations {
+        for (a, &v) in loc.iter().enumerate() {
+            min_v[a] = min_v[a].min(v);
+            max_v[a] = max_v[a].max(v);
+        }
+    }
+    let mut regions: Vec<Support> = locations
+        .iter()
+        .map(|loc| {
+            loc.iter()
+                .enumerate()
+                .map(|(a, &v)| {
+                    if v > 0.0 {
+                        Some((0.0, v, max_v[a]))
+                    } else if v < 0.0 {
+                        Some((min_v[a], v, 0.0))
+                    } else {
+                        None
+                    }
+                })
+                .collect()
+        })
+        .collect();
+    for i in 0..regions.len() {
+        let (prev_regions, rest) = regions.split_at_mut(i);
+        let region = &mut rest[0];
+        let loc_axes: Vec<bool> = region.iter().map(Option::is_some).collect();
+        for prev_region in prev_regions.iter() {
+            // Masters on different axes don't participate.
+            if prev_region.iter().map(Option::is_some).collect::<Vec<_>>() != loc_axes {
+                continue;
+            }
+            // Nor do masters outside the current box.
+            let relevant = region.iter().zip(prev_region).all(|(r, p)| match (r, p) {
+                (Some((lower, peak, upper)), Some((_, prev_peak, _))) => {
+                    prev_peak == peak || (lower < prev_peak && prev_peak < upper)
+                }
+                _ => true,
+            });
+            if !relevant {
+                continue;
+            }
+            // Split the box in the direction with the largest range ratio,
+            // across all axes that tie, for symmetry.
+            let mut best_axes = Vec::new();
+            let mut best_ratio = -1.0;
+            for (a, prev) in prev_region.iter().enumerate() {
+                let val = match prev {
+                    Some((_, peak, _)) => *peak,
+                    None => continue,
+                };
+                let (lower, loc_v, upper) = region[a].unwrap();
+                let (new_triple, ratio) = if val < loc_v {
+                    ((val, loc_v, upper), (val - loc_v) / (lower - loc_v))
+                } else if loc_v < val {
+                    ((lower, loc_v, val), (val - loc_v) / (upper - loc_v))
+                } else {
+                    continue;
+                };
+                if ratio > best_ratio {
+                    best_axes.clear();
+                    best_ratio = ratio;
+                }
+                if ratio == best_ratio {
+                    best_axes.push((a, new_triple));
+                }
+            }
+            for (a, triple) in best_axes {
+                region[a] = Some(triple);
+            }
+        }
+    }
+    regions
+}
@@ -0,0 +1,333 @@
+//! Just enough XML to read and write UFO sources.
+
+use std::collections::HashMap;
+
+use crate::plist::Plist;
+
+/// An XML element, with its attributes, child elements and text content.
+#[derive(Debug, Default)]
+pub struct Element {
+    pub name: String,
+    pub attrs: Vec<(String, String)>,
+    pub children: Vec<Element>,
+    pub text: String,
+}
+
+impl Element {
+    pub fn attr(&self, name: &str) -> Option<&str> {
+        self.attrs
+            .iter()
+            .find(|(k, _)| k == name)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Parse an attribute as a number.
+    pub fn attr_f64(&self, name: &str) -> Option<f64> {
+        self.attr(name).and_then(|v| v.trim().parse().ok())
+    }
+
+    pub fn child(&self, name: &str) -> Option<&Element> {
+        self.children.iter().find(|c| c.name == name)
+    }
+
+    pub fn children_named<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a Element> {
+        self.children.iter().filter(move |c| c.name == name)
+    }
+}
+
+struct Parser<'a> {
+    s: &'a str,
+    pos: usize,
+}
+
+/// Parse an XML document, returning the root element.
+pub fn parse(s: &str) -> Result<Element, String> {
+    let mut parser = Parser { s, pos: 0 };
+    parser.skip_misc()?;
+    let root = parser.element()?;
+    parser.skip_misc()?;
+    if parser.pos != s.len() {
+        return Err(format!("trailing content at {}", parser.pos));
+    }
+    Ok(root)
+}
+
+impl<'a> Parser<'a> {
+    fn rest(&self) -> &'a str {
+        &self.s[self.pos..]
+    }
+
+    fn skip_ws(&mut self) {
+        let rest = self.rest();
+        self.pos += rest.len() - rest.trim_start().len();
+    }
+
+    fn skip_past(&mut self, end: &str) -> Result<(), String> {
+        match self.rest().find(end) {
+            Some(i) => {
+                self.pos += i + end.len();
+                Ok(())
+            }
+            None => Err(format!("unterminated markup, expected {:?}", end)),
+        }
+    }
+
+    /// Skip whitespace, the XML declaration, doctype, comments and
+    /// processing instructions.
+    fn skip_misc(&mut self) -> Result<(), String> {
+        loop {
+            self.skip_ws();
+            let rest = self.rest();
+            if rest.starts_with("<?") {
+                self.skip_past("?>")?;
+            } else if rest.starts_with("<!--") {
+                self.skip_past("-->")?;
+            } else if rest.starts_with("<!") {
+                self.skip_past(">")?;
+            } else {
+                return Ok(());
+            }
+        }
+    }
+
+    fn name(&mut self) -> Result<String, String> {
+        let rest = self.rest();
+        let len = rest
+            .find(|c: char| c.is_whitespace() || c == '/' || c == '>' || c == '=')
+            .unwrap_or(rest.len());
+        if len == 0 {
+            return Err(format!("expected name at {}", self.pos));
+        }
+        self.pos += len;
+        Ok(rest[..len].to_string())
+    }
+
+    fn expect(&mut self, s: &str) -> Result<(), String> {
+        if self.rest().starts_with(s) {
+            self.pos += s.len();
+            Ok(())
+        } else {
+            Err(format!("expected {:?} at {}", s, self.pos))
+        }
+    }
+
+    fn element(&mut self) -> Result<Element, String> {
+        self.expect("<")?;
+        let mut el = Element {
+            name: self.name()?,
+            ..Default::default()
+        };
+        loop {
+            self.skip_ws();
+            if self.rest().starts_with("/>") {
+                self.pos += 2;
+                return Ok(el);
+            }
+            if self.rest().starts_with('>') {
+                self.pos += 1;
+                break;
+            }
+            let key = self.name()?;
+            self.skip_ws();
+            self.expect("=")?;
+            self.skip_ws();
+            let quote = match self.rest().chars().next() {
+                Some(q) if q == '"' || q == '\'' => q,
+                _ => return Err(format!("expected quoted value at {}", self.pos)),
+            };
+            self.pos += 1;
+            let len = self
+                .rest()
+                .find(quote)
+                .ok_or_else(|| "unterminated attribute value".to_string())?;
+            let value = unescape(&self.rest()[..len])?;
+            self.pos += len + 1;
+            el.attrs.push((key, value));
+        }
+        loop {
+            let rest = self.rest();
+            if rest.starts_with("</") {
+                self.pos += 2;
+                let name = self.name()?;
+                if name != el.name {
+                    return Err(format!("mismatched end tag {} for {}", name, el.name));
+                }
+                self.skip_ws();
+                self.expect(">")?;
+                return Ok(el);
+            } else if rest.starts_with("<![CDATA[") {
+                let len = rest
+                    .find("]]>")
+                    .ok_or_else(|| "unterminated CDATA".to_string())?;
+                el.text.push_str(&rest[9..len]);
+                self.pos += len + 3;
+            } else if rest.starts_with("<!--") || rest.starts_with("<?") {
+                self.skip_misc()?;
+            } else if rest.starts_with('<') {
+                el.children.push(self.element()?);
+            } else if rest.is_empty() {
+                return Err(format!("unexpected end of input in {}", el.name));
+            } else {
+                let len = rest.find('<').unwrap_or(rest.len());
+                el.text.push_str(&unescape(&rest[..len])?);
+                self.pos += len;
+            }
+        }
+    }
+}
+
+/// Resolve entity and character references.
+fn unescape(s: &str) -> Result<String, String> {
+    let mut result = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(i) = rest.find('&') {
+        result.push_str(&rest[..i]);
+        rest = &rest[i..];
+        let end = rest
+            .find(';')
+            .ok_or_else(|| format!("unterminated entity in {:?}", s))?;
+        let entity = &rest[1..end];
+        let c = match entity {
+            "amp" => '&',
+            "lt" => '<',
+            "gt" => '>',
+            "quot" => '"',
+            "apos" => '\'',
+            _ if entity.starts_with("#x") => u32::from_str_radix(&entity[2..], 16)
+                .ok()
+                .and_then(std::char::from_u32)
+                .ok_or_else(|| format!("bad character reference {}", entity))?,
+            _ if entity.starts_with('#') => entity[1..]
+                .parse()
+                .ok()
+                .and_then(std::char::from_u32)
+                .ok_or_else(|| format!("bad character reference {}", entity))?,
+            _ => return Err(format!("unknown entity {}", entity)),
+        };
+        result.push(c);
+        rest = &rest[end + 1..];
+    }
+    result.push_str(rest);
+    Ok(result)
+}
+
+/// Escape a string for use in XML text or attribute values.
+pub fn escape(s: &str) -> String {
+    let mut result = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => result.push_str("&amp;"),
+            '<' => result.push_str("&lt;"),
+            '>' => result.push_str("&gt;"),
+            '"' => result.push_str("&quot;"),
+            '\'' => result.push_str("&apos;"),
+            _ => result.push(c),
+        }
+    }
+    result
+}
+
+/// Serialize a plist in the XML format used by UFO.
+pub fn plist_to_xml(plist: &Plist) -> String {
+    let mut s = String::new();
+    s.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    s.push_str("<!DOCTYPE plist PUBLIC \"-//Apple//DTD PLIST 1.0//EN\" ");
+    s.push_str("\"http://www.apple.com/DTDs/PropertyList-1.0.dtd\">\n");
+    s.push_str("<plist version=\"1.0\">\n");
+    push_plist(&mut s, plist, 0);
+    s.push_str("</plist>\n");
+    s
+}
+
+/// Read a plist in XML format.
+///
+/// Booleans become integers, as there is no boolean plist type.
+pub fn plist_from_xml(s: &str) -> Result<Plist, String> {
+    let root = parse(s)?;
+    if root.name != "plist" {
+        return Err(format!("expected plist, got {}", root.name));
+    }
+    match root.children.first() {
+        Some(el) => plist_from_element(el),
+        None => Ok(Plist::Dictionary(HashMap::new())),
+    }
+}
+
+fn plist_from_element(el: &Element) -> Result<Plist, String> {
+    Ok(match el.name.as_str() {
+        "dict" => {
+            let mut dict = HashMap::new();
+            for pair in el.children.chunks(2) {
+                if pair.len() != 2 || pair[0].name != "key" {
+                    return Err("malformed dict".to_string());
+                }
+                dict.insert(pair[0].text.clone(), plist_from_element(&pair[1])?);
+            }
+            Plist::Dictionary(dict)
+        }
+        "array" => Plist::Array(
+            el.children
+                .iter()
+                .map(plist_from_element)
+                .collect::<Result<_, _>>()?,
+        ),
+        "integer" => Plist::Integer(
+            el.text
+                .trim()
+                .parse()
+                .map_err(|_| format!("bad integer {:?}", el.text))?,
+        ),
+        "real" => Plist::Float(
+            el.text
+                .trim()
+                .parse()
+                .map_err(|_| format!("bad real {:?}", el.text))?,
+        ),
+        "true" => Plist::Integer(1),
+        "false" => Plist::Integer(0),
+        _ => Plist::String(el.text.clone()),
+    })
+}
+
+fn indent(s: &mut String, depth: usize) {
+    for _ in 0..depth {
+        s.push_str("  ");
+    }
+}
+
+fn push_plist(s: &mut String, plist: &Plist, depth: usize) {
+    indent(s, depth);
+    match plist {
+        Plist::Dictionary(dict) => {
+            if dict.is_empty() {
+                s.push_str("<dict/>\n");
+                return;
+            }
+            s.push_str("<dict>\n");
+            let mut keys: Vec<_> = dict.keys().collect();
+            keys.sort();
+            for key in keys {
+                indent(s, depth + 1);
+                s.push_str(&format!("<key>{}</key>\n", escape(key)));
+                push_plist(s, &dict[key], depth + 1);
+            }
+            indent(s, depth);
+            s.push_str("</dict>\n");
+        }
+        Plist::Array(array) => {
+            if array.is_empty() {
+                s.push_str("<array/>\n");
+                return;
+            }
+            s.push_str("<array>\n");
+            for el in array {
+                push_plist(s, el, depth + 1);
+            }
+            indent(s, depth);
+            s.push_str("</array>\n");
+        }
+        Plist::String(st) => s.push_str(&format!("<string>{}</string>\n", escape(st))),
+        Plist::Integer(i) => s.push_str(&format!("<integer>{}</integer>\n", i)),
+        Plist::Float(f) => s.push_str(&format!("<real>{}</real>\n", f)),
+    }
+}
@@ -0,0 +1,157 @@
+//! Headless animation: sweeping a path through the design space.
+
+use std::fmt::Write;
+
+use druid::kurbo::Rect;
+
+use crate::interp_pane::reconstruct_path;
+use crate::render::{
+    add_to_bbox, handle_lines, path_data, r, render_glyph, render_static, svg_document,
+    RenderOptions,
+};
+use crate::AppState;
+
+#[derive(Clone, Copy, Debug)]
+pub enum Easing {
+    Linear,
+    EaseInOut,
+}
+
+impl std::str::FromStr for Easing {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Easing, String> {
+        match s {
+            "linear" => Ok(Easing::Linear),
+            "ease-in-out" => Ok(Easing::EaseInOut),
+            _ => Err(format!("unknown easing {}", s)),
+        }
+    }
+}
+
+impl Easing {
+    fn apply(self, t: f64) -> f64 {
+        match self {
+            Easing::Linear => t,
+            Easing::EaseInOut => t * t * (3.0 - 2.0 * t),
+        }
+    }
+}
+
+/// A straight path through the design space, in slider units.
+pub struct Sweep {
+    pub from: Vec<f64>,
+    pub to: Vec<f64>,
+    pub frames: usize,
+    pub easing: Easing,
+}
+
+impl Sweep {
+    /// The location of each frame, including both ends.
+    pub fn locations(&self) -> Vec<Vec<f64>> {
+        let n = self.frames.max(2);
+        (0..n)
+            .map(|i| {
+                let t = self.easing.apply(i as f64 / (n - 1) as f64);
+                self.from
+                    .iter()
+                    .zip(&self.to)
+                    .map(|(from, to)| from + t * (to - from))
+                    .collect()
+            })
+            .collect()
+    }
+}
+
+/// Render each frame as a separate SVG document. All frames share the
+/// same view box, so they can be assembled into a video or GIF.
+pub fn render_frames(data: &mut AppState, opts: &RenderOptions, sweep: &Sweep) -> Vec<String> {
+    let mut bbox = None;
+    let mut bodies = Vec::new();
+    for location in sweep.locations() {
+        data.shared.location = location;
+        let mut body = render_static(data, opts, &mut bbox);
+        body.push_str(&render_glyph(data, opts, &mut bbox));
+        bodies.push(body);
+    }
+    bodies.iter().map(|body| svg_document(body, bbox)).collect()
+}
+
+fn animate_attr(attr: &str, values: &[String], duration: f64) -> String {
+    format!(
+        r#"<animate attributeName="{}" dur="{}s" repeatCount="indefinite" values="{}"/>"#,
+        attr,
+        duration,
+        values.join(";")
+    )
+}
+
+/// Render the sweep as a single animated SVG, using SMIL animation of the
+/// path data and node positions.
+pub fn render_smil(
+    data: &mut AppState,
+    opts: &RenderOptions,
+    sweep: &Sweep,
+    duration: f64,
+) -> String {
+    let mut bbox: Option<Rect> = None;
+    let frames: Vec<_> = sweep
+        .locations()
+        .into_iter()
+        .map(|location| {
+            let pts = data.eval_pts(&location);
+            add_to_bbox(&mut bbox, &pts);
+            pts
+        })
+        .collect();
+    data.shared.location = sweep.from.clone();
+    let mut body = render_static(data, opts, &mut bbox);
+
+    let glyph_values: Vec<String> = frames
+        .iter()
+        .map(|pts| path_data(&reconstruct_path(pts, &data.structure)))
+        .collect();
+    writeln!(
+        body,
+        r#"  <path d="{}" fill="black">{}</path>"#,
+        glyph_values[0],
+        animate_attr("d", &glyph_values, duration)
+    )
+    .unwrap();
+    if opts.handles {
+        let handle_values: Vec<String> = frames
+            .iter()
+            .map(|pts| path_data(&handle_lines(pts, &data.structure)))
+            .collect();
+        writeln!(
+            body,
+            r##"  <path d="{}" fill="none" stroke="#808080" stroke-width="0.5">{}</path>"##,
+            handle_values[0],
+            animate_attr("d", &handle_values, duration)
+        )
+        .unwrap();
+    }
+    if opts.nodes {
+        let types = data.structure.iter().flatten();
+        for (i, node_type) in types.enumerate() {
+            let (radius, fill) = match node_type {
+                glyphstool::NodeType::OffCurve => (1.5, "#8080ff"),
+                _ => (2.0, "#4040ff"),
+            };
+            let xs: Vec<String> = frames.iter().map(|pts| r(pts[i].x).to_string()).collect();
+            let ys: Vec<String> = frames.iter().map(|pts| r(pts[i].y).to_string()).collect();
+            writeln!(
+                body,
+                r#"  <circle cx="{}" cy="{}" r="{}" fill="{}">{}{}</circle>"#,
+                xs[0],
+                ys[0],
+                radius,
+                fill,
+                animate_attr("cx", &xs, duration),
+                animate_attr("cy", &ys, duration)
+            )
+            .unwrap();
+        }
+    }
+    svg_document(&body, bbox)
+}
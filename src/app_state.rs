@@ -1,20 +1,21 @@
-use std::ops::Deref;
+use std::ops::{Deref, Range};
 use std::sync::Arc;
 
-use nalgebra::DVector;
-
-use glyphstool::NodeType;
-use rbf_interp::{Basis, Scatter};
+use glyphstool::hull;
+use glyphstool::interp::Model;
+use glyphstool::{restore_smooth, NodeType, PointKind};
 
 use druid::kurbo::Point;
 
 use druid::Data;
 
+use crate::fit::sample_locations;
+use crate::interp_model;
+
+pub use glyphstool::interp::{Metric, Sample as InterpSample};
+
 #[derive(Clone, Default)]
 pub struct AppState {
-    // TODO: increasingly aware that we're hardcoding two parameters,
-    // this needs to be a variable number, but we're trying to keep
-    // complexity down for now.
     pub shared: Shared,
     pub sel: Option<usize>,
 
@@ -22,20 +23,216 @@ pub struct AppState {
 
     pub masters: Arc<Vec<Master>>,
     pub interp_type: InterpType,
+    pub kernel: KernelParams,
+    pub coords: Coords,
+    pub metric: Metric,
+    pub extrapolation: Extrapolation,
+    /// The interpolation type and kernel parameters of points that don't
+    /// use the ones above, by index. May be shorter than `pts`.
+    pub overrides: Arc<Vec<Option<(InterpType, KernelParams)>>>,
 
     pub structure: Arc<Vec<Vec<NodeType>>>,
+
+    /// Everything about the glyph besides its nodes: the advance width,
+    /// anchors, component transforms and guides, in the order of
+    /// `layer_geometry`.
+    pub attributes: Arc<Vec<InterpPt>>,
+    /// What each attribute is.
+    pub attribute_kinds: Arc<Vec<PointKind>>,
+
+    /// The model of the masters of the font, shared with instance export.
+    pub model: Model,
 }
 
-#[derive(Clone, Copy, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Default)]
 pub enum InterpType {
+    #[default]
     Gaussian,
     ThinPlate,
     Linear,
+    /// The OpenType variation model, as a variable font interpolates.
+    Variation,
+    Multiquadric,
+    InverseMultiquadric,
+    /// The polyharmonic spline r^3.
+    Cubic,
+    /// Wendland's C2 function, which is zero beyond the radius.
+    Wendland,
+}
+
+impl InterpType {
+    /// All interpolation types, in the order they are offered.
+    pub const ALL: &'static [InterpType] = &[
+        InterpType::Gaussian,
+        InterpType::ThinPlate,
+        InterpType::Linear,
+        InterpType::Variation,
+        InterpType::Multiquadric,
+        InterpType::InverseMultiquadric,
+        InterpType::Cubic,
+        InterpType::Wendland,
+    ];
+
+    /// The name used on the command line.
+    pub fn name(self) -> &'static str {
+        match self {
+            InterpType::Gaussian => "gaussian",
+            InterpType::ThinPlate => "thin-plate",
+            InterpType::Linear => "linear",
+            InterpType::Variation => "variation",
+            InterpType::Multiquadric => "multiquadric",
+            InterpType::InverseMultiquadric => "inverse-multiquadric",
+            InterpType::Cubic => "cubic",
+            InterpType::Wendland => "wendland",
+        }
+    }
+
+    /// Whether the kernel radius affects the result.
+    pub fn uses_radius(self) -> bool {
+        matches!(
+            self,
+            InterpType::Gaussian
+                | InterpType::Multiquadric
+                | InterpType::InverseMultiquadric
+                | InterpType::Wendland
+        )
+    }
+
+    /// The order of the polynomial term used when none is given.
+    pub fn default_order(self) -> usize {
+        match self {
+            InterpType::Linear => 1,
+            _ => 2,
+        }
+    }
+}
+
+/// How points are represented while interpolating.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum Coords {
+    /// Each point by its coordinates, so it moves in a straight line
+    /// between two samples.
+    #[default]
+    Cartesian,
+    /// Each on-curve point by angle and distance from the centroid of its
+    /// contour, and each handle from its on-curve point, so rotations keep
+    /// their size.
+    Polar,
+    /// Each contour by a rotation, scale and translation from its shape in
+    /// the first sample, plus the remaining deformation, so contours that
+    /// move rigidly stay rigid.
+    Rigid,
+}
+
+impl Coords {
+    /// The name used on the command line and in the font.
+    pub fn name(self) -> &'static str {
+        match self {
+            Coords::Cartesian => "cartesian",
+            Coords::Polar => "polar",
+            Coords::Rigid => "rigid",
+        }
+    }
+}
+
+impl std::str::FromStr for Coords {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Coords, String> {
+        match s {
+            "cartesian" => Ok(Coords::Cartesian),
+            "polar" => Ok(Coords::Polar),
+            "rigid" => Ok(Coords::Rigid),
+            _ => Err(format!("unknown coordinates {}", s)),
+        }
+    }
+}
+
+/// What happens at locations outside the convex hull of the samples of a
+/// point, where a gaussian decays toward its polynomial term and a
+/// thin-plate spline can grow without bound.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum Extrapolation {
+    /// Evaluate the interpolation there anyway.
+    #[default]
+    Raw,
+    /// Evaluate at the nearest location in the hull.
+    Clamp,
+    /// Continue linearly from the nearest location in the hull, with the
+    /// slope the interpolation has there toward the location.
+    Linear,
+}
+
+impl Extrapolation {
+    /// The name used on the command line and in the font.
+    pub fn name(self) -> &'static str {
+        match self {
+            Extrapolation::Raw => "raw",
+            Extrapolation::Clamp => "clamp",
+            Extrapolation::Linear => "linear",
+        }
+    }
+}
+
+impl std::str::FromStr for Extrapolation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Extrapolation, String> {
+        match s {
+            "raw" => Ok(Extrapolation::Raw),
+            "clamp" => Ok(Extrapolation::Clamp),
+            "linear" => Ok(Extrapolation::Linear),
+            _ => Err(format!("unknown extrapolation {}", s)),
+        }
+    }
 }
 
-impl Default for InterpType {
+/// Parameters of the radial basis function.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct KernelParams {
+    /// Kernel radius, in units of the range of the masters. Used by the gaussian, multiquadric,
+    /// inverse multiquadric and Wendland bases.
+    pub radius: f64,
+    /// Order of the polynomial term: 0 for none, 1 for a constant, 2 for
+    /// linear. `None` for the default of the interpolation type.
+    pub order: Option<usize>,
+    /// Regularization added to the diagonal of the kernel matrix. At 0 the
+    /// samples are interpolated exactly; larger values smooth the result.
+    pub smoothing: f64,
+}
+
+impl Default for KernelParams {
     fn default() -> Self {
-        InterpType::Gaussian
+        KernelParams {
+            radius: 1.0,
+            order: None,
+            smoothing: 0.0,
+        }
+    }
+}
+
+impl KernelParams {
+    /// The order of the polynomial term for the given type.
+    pub fn order(&self, interp_type: InterpType) -> usize {
+        self.order.unwrap_or_else(|| interp_type.default_order())
+    }
+}
+
+impl std::str::FromStr for InterpType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<InterpType, String> {
+        match s {
+            "gaussian" => Ok(InterpType::Gaussian),
+            "thin-plate" => Ok(InterpType::ThinPlate),
+            "linear" => Ok(InterpType::Linear),
+            "variation" => Ok(InterpType::Variation),
+            "multiquadric" => Ok(InterpType::Multiquadric),
+            "inverse-multiquadric" => Ok(InterpType::InverseMultiquadric),
+            "cubic" => Ok(InterpType::Cubic),
+            "wendland" => Ok(InterpType::Wendland),
+            _ => Err(format!("unknown interpolation type {}", s)),
+        }
     }
 }
 
@@ -46,7 +243,13 @@ impl Data for AppState {
             && self.pts.same(&other.pts)
             && self.masters.same(&other.masters)
             && self.interp_type.same(&other.interp_type)
+            && self.kernel.same(&other.kernel)
+            && self.coords.same(&other.coords)
+            && self.metric == other.metric
+            && self.extrapolation.same(&other.extrapolation)
+            && self.overrides.same(&other.overrides)
             && self.structure.same(&other.structure)
+            && self.attributes.same(&other.attributes)
     }
 }
 
@@ -56,9 +259,27 @@ impl Data for InterpType {
     }
 }
 
+impl Data for Extrapolation {
+    fn same(&self, other: &Extrapolation) -> bool {
+        self == other
+    }
+}
+
+impl Data for Coords {
+    fn same(&self, other: &Coords) -> bool {
+        self == other
+    }
+}
+
+impl Data for KernelParams {
+    fn same(&self, other: &KernelParams) -> bool {
+        self == other
+    }
+}
+
 impl Data for Shared {
     fn same(&self, other: &Shared) -> bool {
-        self.width.same(&other.width) && self.weight.same(&other.weight)
+        self.axes.same(&other.axes) && self.location == other.location
     }
 }
 
@@ -68,28 +289,66 @@ pub struct InterpPt {
 }
 
 /// This is data that's made available to individual master entries
-#[derive(Clone, Default)]
+#[derive(Clone)]
 pub struct Shared {
-    pub width: f64,
-    pub weight: f64,
+    pub axes: Arc<Vec<Axis>>,
+    /// The current location, in slider units, one value per axis.
+    pub location: Vec<f64>,
 }
 
+/// A design space axis. Locations are in slider units, from 0 to 1 across
+/// the range of the masters.
 #[derive(Clone)]
-pub struct Master {
-    pub width: f64,
-    pub weight: f64,
+pub struct Axis {
+    pub name: String,
+    /// The design space values at the ends of the slider.
+    pub min: f64,
+    pub max: f64,
 }
 
 #[derive(Clone)]
-pub struct InterpSample {
-    pub pt: Point,
-    pub width: f64,
-    pub weight: f64,
+pub struct Master {
+    pub location: Vec<f64>,
+}
+
+impl Default for Shared {
+    /// Weight and width, for adding points manually.
+    fn default() -> Self {
+        let axes = vec![Axis::new("weight", 0.0, 1.0), Axis::new("width", 0.0, 1.0)];
+        Shared {
+            location: vec![0.0; axes.len()],
+            axes: axes.into(),
+        }
+    }
+}
+
+impl Axis {
+    pub fn new(name: impl Into<String>, min: f64, max: f64) -> Axis {
+        Axis {
+            name: name.into(),
+            min,
+            max,
+        }
+    }
+
+    /// Map a design space value to slider units.
+    pub fn to_slider(&self, value: f64) -> f64 {
+        if self.max == self.min {
+            0.0
+        } else {
+            (value - self.min) / (self.max - self.min)
+        }
+    }
+
+    /// Map a value in slider units to design space.
+    pub fn to_design(&self, value: f64) -> f64 {
+        self.min + value * (self.max - self.min)
+    }
 }
 
 impl Data for Master {
     fn same(&self, other: &Self) -> bool {
-        self.width.same(&other.width) && self.weight.same(&other.weight)
+        self.location == other.location
     }
 }
 
@@ -102,30 +361,22 @@ pub mod lenses {
         use crate::lens2::Lens2;
         use druid::Lens;
         use std::sync::Arc;
-        pub struct Width;
-        pub struct Weight;
+        /// The value of the axis with the given index.
+        pub struct AxisValue(pub usize);
         pub struct Shared;
         pub struct Masters;
         pub struct InterpType;
+        pub struct Order;
+        pub struct Coords;
+        pub struct Extrapolation;
 
-        // Note: this lens isn't quite right.
-        impl Lens<AppState, f64> for Width {
+        impl Lens<AppState, f64> for AxisValue {
             fn get<'a>(&self, data: &'a AppState) -> &'a f64 {
-                &data.shared.width
+                &data.shared.location[self.0]
             }
 
             fn with_mut<V, F: FnOnce(&mut f64) -> V>(&self, data: &mut AppState, f: F) -> V {
-                f(&mut data.shared.width)
-            }
-        }
-
-        impl Lens<AppState, f64> for Weight {
-            fn get<'a>(&self, data: &'a AppState) -> &'a f64 {
-                &data.shared.weight
-            }
-
-            fn with_mut<V, F: FnOnce(&mut f64) -> V>(&self, data: &mut AppState, f: F) -> V {
-                f(&mut data.shared.weight)
+                f(&mut data.shared.location[self.0])
             }
         }
 
@@ -157,6 +408,48 @@ pub mod lenses {
             }
         }
 
+        impl Lens<AppState, Option<usize>> for Order {
+            fn get<'a>(&self, data: &'a AppState) -> &'a Option<usize> {
+                &data.kernel.order
+            }
+
+            fn with_mut<V, F: FnOnce(&mut Option<usize>) -> V>(
+                &self,
+                data: &mut AppState,
+                f: F,
+            ) -> V {
+                f(&mut data.kernel.order)
+            }
+        }
+
+        impl Lens<AppState, super::super::Coords> for Coords {
+            fn get<'a>(&self, data: &'a AppState) -> &'a super::super::Coords {
+                &data.coords
+            }
+
+            fn with_mut<V, F: FnOnce(&mut super::super::Coords) -> V>(
+                &self,
+                data: &mut AppState,
+                f: F,
+            ) -> V {
+                f(&mut data.coords)
+            }
+        }
+
+        impl Lens<AppState, super::super::Extrapolation> for Extrapolation {
+            fn get<'a>(&self, data: &'a AppState) -> &'a super::super::Extrapolation {
+                &data.extrapolation
+            }
+
+            fn with_mut<V, F: FnOnce(&mut super::super::Extrapolation) -> V>(
+                &self,
+                data: &mut AppState,
+                f: F,
+            ) -> V {
+                f(&mut data.extrapolation)
+            }
+        }
+
         impl Lens2<AppState, super::super::Shared> for Shared {
             fn get<V, F: FnOnce(&super::super::Shared) -> V>(&self, data: &AppState, f: F) -> V {
                 f(&data.shared)
@@ -197,8 +490,7 @@ impl AppState {
         }
         let mut masters = self.masters.deref().to_owned();
         masters.push(Master {
-            width: self.shared.width,
-            weight: self.shared.weight,
+            location: self.shared.location.clone(),
         });
         self.masters = masters.into();
         println!("adding new master");
@@ -207,11 +499,36 @@ impl AppState {
     pub fn is_at_master(&self) -> bool {
         self.masters
             .iter()
-            .any(|master| self.shared.width == master.width && self.shared.weight == master.weight)
+            .any(|master| self.shared.location == master.location)
+    }
+
+    /// Set the axes, and move to the start of each.
+    pub fn set_axes(&mut self, axes: Vec<Axis>) {
+        self.shared.location = vec![0.0; axes.len()];
+        self.shared.axes = axes.into();
+    }
+
+    /// A location with one value per axis, from values given in axis
+    /// order. Missing values are 0.
+    pub fn pad_location(&self, values: &[f64]) -> Vec<f64> {
+        (0..self.shared.axes.len())
+            .map(|i| values.get(i).copied().unwrap_or(0.0))
+            .collect()
+    }
+
+    /// The design space coordinates of a location in slider units, in the
+    /// style of a brace layer name, such as "{550, 100}".
+    pub fn location_name(&self, location: &[f64]) -> String {
+        let coords: Vec<String> = location
+            .iter()
+            .zip(self.shared.axes.iter())
+            .map(|(&x, axis)| format!("{}", (axis.to_design(x) * 100.0).round() / 100.0))
+            .collect();
+        format!("{{{}}}", coords.join(", "))
     }
 
-    pub fn add_weight(&mut self, weight: f64) {
-        let master = Master { width: 0.0, weight };
+    pub fn add_master(&mut self, location: Vec<f64>) {
+        let master = Master { location };
         Arc::make_mut(&mut self.masters).push(master);
     }
 
@@ -222,49 +539,199 @@ impl AppState {
     pub fn set_structure(&mut self, structure: Vec<Vec<NodeType>>) {
         *Arc::make_mut(&mut self.structure) = structure;
     }
-}
 
-impl InterpPt {
-    pub fn new(pt: Point, width: f64, weight: f64) -> InterpPt {
-        let sample = InterpSample { pt, width, weight };
-        InterpPt {
-            samples: vec![sample],
+    pub fn set_attributes(&mut self, attributes: Vec<InterpPt>, kinds: Vec<PointKind>) {
+        *Arc::make_mut(&mut self.attributes) = attributes;
+        *Arc::make_mut(&mut self.attribute_kinds) = kinds;
+    }
+
+    /// The indices of the points of the contour containing a point.
+    pub fn contour_range(&self, i: usize) -> Option<Range<usize>> {
+        let mut j = 0;
+        for subpath in self.structure.iter() {
+            let range = j..j + subpath.len();
+            if range.contains(&i) {
+                return Some(range);
+            }
+            j = range.end;
         }
+        None
     }
 
-    pub fn eval(&self, width: f64, weight: f64, interp_type: InterpType) -> Point {
-        let len = self.samples.len();
-        let mut centers = Vec::with_capacity(len);
-        let mut vals = Vec::with_capacity(len);
-        for sample in &self.samples {
-            centers.push(DVector::from_vec(vec![sample.width, sample.weight]));
-            vals.push(DVector::from_vec(vec![sample.pt.x, sample.pt.y]));
+    /// The interpolation type and kernel parameters of a point.
+    pub fn point_kernel(&self, i: usize) -> (InterpType, KernelParams) {
+        self.overrides
+            .get(i)
+            .copied()
+            .flatten()
+            .unwrap_or((self.interp_type, self.kernel))
+    }
+
+    /// Set or clear the override of some points.
+    pub fn set_override(&mut self, range: Range<usize>, value: Option<(InterpType, KernelParams)>) {
+        let overrides = Arc::make_mut(&mut self.overrides);
+        if overrides.len() < range.end {
+            overrides.resize(range.end, None);
+        }
+        for i in range {
+            overrides[i] = value;
         }
-        let (basis, order) = match interp_type {
-            InterpType::ThinPlate => (Basis::PolyHarmonic(2), 2),
-            // TODO: control over radius
-            InterpType::Gaussian => (Basis::Gaussian(1.0), 2),
-            InterpType::Linear => (Basis::PolyHarmonic(1), 1),
+    }
+
+    /// Whether a location is outside the convex hull of the sample
+    /// locations, so that the points there are extrapolated.
+    pub fn is_extrapolated(&self, location: &[f64]) -> bool {
+        let locations = sample_locations(self);
+        !locations.is_empty() && hull::is_outside(&locations, location)
+    }
+
+    /// A location in slider units in design space.
+    fn to_design(&self, location: &[f64]) -> Vec<f64> {
+        location
+            .iter()
+            .zip(self.shared.axes.iter())
+            .map(|(&x, axis)| axis.to_design(x))
+            .collect()
+    }
+
+    /// Interpolate points at a location in slider units with the model of
+    /// the font, the metric and extrapolation policy of the toy, and the
+    /// interpolation type and kernel parameters of the point with each
+    /// index given by `kernel`.
+    fn interpolate(
+        &self,
+        pts: &[InterpPt],
+        kernel: impl Fn(usize) -> (InterpType, KernelParams),
+        location: &[f64],
+    ) -> Vec<Point> {
+        let method = |i: usize| {
+            let (interp_type, kernel) = kernel(i);
+            interp_model::method(interp_type, kernel)
         };
-        let scatter = Scatter::create(centers, vals, basis, order);
-        let params = DVector::from_vec(vec![width, weight]);
-        let interp = scatter.eval(params);
-        Point::new(interp[0], interp[1])
+        self.model()
+            .interpolate_points(&self.design_samples(pts), method, &self.to_design(location))
+            .expect("error interpolating")
+    }
+
+    /// The model of the font with the metric and extrapolation policy of
+    /// the toy.
+    fn model(&self) -> Model {
+        self.model
+            .clone()
+            .with_metric(self.metric.clone())
+            .with_extrapolation(interp_model::extrapolation(self.extrapolation))
+    }
+
+    /// The samples of points, with their locations in design space.
+    fn design_samples(&self, pts: &[InterpPt]) -> Vec<Vec<InterpSample>> {
+        pts.iter()
+            .map(|pt| {
+                pt.samples
+                    .iter()
+                    .map(|s| InterpSample {
+                        pt: s.pt,
+                        location: self.to_design(&s.location),
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// Evaluate all points at the given params.
+    pub fn eval_pts(&self, location: &[f64]) -> Vec<Point> {
+        self.eval_samples(&self.pts, self.interp_type, self.kernel, location)
+    }
+
+    /// Evaluate the attributes at the given params. These are always
+    /// interpolated directly, with the interpolation type and kernel
+    /// parameters of the glyph.
+    pub fn eval_attributes(&self, location: &[f64]) -> Vec<Point> {
+        self.interpolate(
+            &self.attributes,
+            |_| (self.interp_type, self.kernel),
+            location,
+        )
+    }
+
+    /// Evaluate the given points, which have the structure of the glyph, in
+    /// the current coordinates. Points with an override use its type and
+    /// kernel parameters instead of the given ones, and outside the hull of
+    /// their samples follow the extrapolation policy. The neighbours of
+    /// smooth nodes are then made collinear again, except in contours with
+    /// a sample at exactly this location, which are left as drawn.
+    pub fn eval_samples(
+        &self,
+        pts: &[InterpPt],
+        interp_type: InterpType,
+        kernel: KernelParams,
+        location: &[f64],
+    ) -> Vec<Point> {
+        let method = |i: usize| {
+            let (interp_type, kernel) = self
+                .overrides
+                .get(i)
+                .copied()
+                .flatten()
+                .unwrap_or((interp_type, kernel));
+            interp_model::method(interp_type, kernel)
+        };
+        let mut result = self
+            .model()
+            .interpolate_coords(
+                &self.design_samples(pts),
+                &self.structure,
+                interp_model::coords(self.coords),
+                method,
+                &self.to_design(location),
+            )
+            .expect("error interpolating");
+        let mut j = 0;
+        for subpath in self.structure.iter() {
+            let n = subpath.len();
+            if j + n > result.len() {
+                break;
+            }
+            let at_sample = pts[j..j + n]
+                .iter()
+                .all(|pt| pt.samples.iter().any(|s| s.location == location));
+            if !at_sample {
+                restore_smooth(&mut result[j..j + n], subpath, true);
+            }
+            j += n;
+        }
+        result
+    }
+}
+
+impl InterpPt {
+    pub fn new(pt: Point, location: Vec<f64>) -> InterpPt {
+        let sample = InterpSample { pt, location };
+        InterpPt {
+            samples: vec![sample],
+        }
     }
 
     /// Update a point, either by adding a new sample or updating an existing
     /// sample that's "close" to the specific params.
-    pub fn update(&mut self, pt: Point, width: f64, weight: f64) {
+    pub fn update(&mut self, pt: Point, location: &[f64]) {
         // Try to find an existing sample to update.
         for sample in &mut self.samples {
-            if (sample.width - width).powi(2) + (sample.weight - weight).powi(2) < 0.001 {
-                sample.width = width;
-                sample.weight = weight;
+            let dist2: f64 = sample
+                .location
+                .iter()
+                .zip(location)
+                .map(|(a, b)| (a - b).powi(2))
+                .sum();
+            if dist2 < 0.001 {
+                sample.location = location.to_owned();
                 sample.pt = pt;
                 return;
             }
         }
-        let sample = InterpSample { pt, width, weight };
+        let sample = InterpSample {
+            pt,
+            location: location.to_owned(),
+        };
         self.samples.push(sample);
     }
 }
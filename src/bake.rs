@@ -0,0 +1,313 @@
+//! Baking smooth interpolation into brace layers.
+//!
+//! Variable fonts can only interpolate linearly between masters. Given an
+//! RBF interpolation of a glyph, this adds intermediate (brace) layers
+//! until linear interpolation stays within a tolerance of the RBF result
+//! everywhere in the range of the masters, along all axes.
+
+use std::sync::Arc;
+
+use druid::kurbo::{Affine, Point};
+
+use glyphstool::{set_layer_geometry, Font, Layer, Plist, PointKind};
+
+use crate::app_state::{Coords, Extrapolation, InterpSample, InterpType};
+use crate::fit::sample_locations;
+use crate::AppState;
+
+pub struct BakeOptions {
+    /// Maximum distance from the RBF result, in font units.
+    pub tolerance: f64,
+    /// Number of steps at which each axis is checked.
+    pub steps: usize,
+}
+
+/// The locations (in slider units) at which interpolation is checked: a
+/// grid over the range of the samples along each axis, with `steps` steps
+/// plus the positions of the samples.
+fn check_grid(samples: &[Vec<f64>], n_axes: usize, steps: usize) -> Vec<Vec<f64>> {
+    let mut grid = vec![Vec::new()];
+    for i in 0..n_axes {
+        let positions = samples.iter().map(|location| location[i]);
+        let min = positions.clone().fold(f64::INFINITY, f64::min);
+        let max = positions.clone().fold(f64::NEG_INFINITY, f64::max);
+        let mut values: Vec<f64> = (0..=steps)
+            .map(|k| min + (max - min) * k as f64 / steps as f64)
+            .chain(positions)
+            .collect();
+        values.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        values.dedup();
+        grid = grid
+            .iter()
+            .flat_map(|location: &Vec<f64>| {
+                values.iter().map(move |&x| {
+                    let mut location = location.clone();
+                    location.push(x);
+                    location
+                })
+            })
+            .collect();
+    }
+    grid
+}
+
+/// Choose the locations (in slider units) at which to add brace layers,
+/// with the RBF result at each one, in font units.
+///
+/// Existing samples are fixed. Brace layers are added one at a time at the
+/// location on the grid where linear interpolation (the variation model of
+/// the samples and the layers added so far) is furthest from the RBF
+/// result, until it is within tolerance everywhere.
+pub fn brace_positions(
+    data: &AppState,
+    to_font: Affine,
+    opts: &BakeOptions,
+) -> Vec<(Vec<f64>, Vec<Point>)> {
+    let samples = sample_locations(data);
+    if samples.len() < 2 {
+        return Vec::new();
+    }
+    let grid = check_grid(&samples, data.shared.axes.len(), opts.steps.max(2));
+    let smooth: Vec<Vec<Point>> = grid
+        .iter()
+        .map(|location| data.eval_pts(location))
+        .collect();
+    // Linear interpolation as in the font: the variation model of all
+    // points, in cartesian coordinates, extrapolated as it is.
+    let mut linear = data.clone();
+    linear.interp_type = InterpType::Variation;
+    linear.coords = Coords::Cartesian;
+    linear.extrapolation = Extrapolation::Raw;
+    linear.overrides = Arc::new(Vec::new());
+    let mut result: Vec<(Vec<f64>, Vec<Point>)> = Vec::new();
+    loop {
+        let errors = grid.iter().zip(&smooth).map(|(location, smooth)| {
+            linear
+                .eval_pts(location)
+                .iter()
+                .zip(smooth)
+                .map(|(&p, &q)| (to_font * p - to_font * q).hypot())
+                .fold(0.0, f64::max)
+        });
+        let (k, error) =
+            errors.enumerate().fold(
+                (0, 0.0),
+                |worst, (k, error)| if error > worst.1 { (k, error) } else { worst },
+            );
+        // A location that has a sample is already as close as it gets.
+        let location = &grid[k];
+        if error <= opts.tolerance || result.iter().any(|(added, _)| added == location) {
+            break;
+        }
+        for (pt, &p) in Arc::make_mut(&mut linear.pts).iter_mut().zip(&smooth[k]) {
+            pt.samples.push(InterpSample {
+                pt: p,
+                location: location.clone(),
+            });
+        }
+        let pts = smooth[k].iter().map(|&p| to_font * p).collect();
+        result.push((location.clone(), pts));
+    }
+    result
+}
+
+fn round2(x: f64) -> f64 {
+    (x * 100.0).round() / 100.0
+}
+
+/// Round an attribute in font units: the advance to a whole unit, the axes
+/// of components (which are scale factors) to 5 places, and positions and
+/// angles to hundredths.
+fn round_attribute(kind: PointKind, p: Point) -> Point {
+    let round5 = |x: f64| (x * 1e5).round() / 1e5;
+    match kind {
+        PointKind::Advance => Point::new(p.x.round(), 0.0),
+        PointKind::ComponentXAxis | PointKind::ComponentYAxis => {
+            Point::new(round5(p.x), round5(p.y))
+        }
+        _ => Point::new(round2(p.x), round2(p.y)),
+    }
+}
+
+/// Add brace layers to a glyph so that linear interpolation follows the
+/// interpolation in `data` within tolerance. Returns the number of layers
+/// added, or an error if the font has no masters.
+///
+/// `to_font` maps the toy's coordinates to font units.
+pub fn bake_glyph(
+    font: &mut Font,
+    glyphname: &str,
+    data: &AppState,
+    to_font: Affine,
+    opts: &BakeOptions,
+) -> Result<usize, String> {
+    let axes = &data.shared.axes;
+    let master_id = match font.font_master.first() {
+        Some(master) => master.id.clone(),
+        None => return Err("font has no masters".to_string()),
+    };
+    let braces = brace_positions(data, to_font, opts);
+    let glyph = match font.get_glyph_mut(glyphname) {
+        Some(glyph) => glyph,
+        None => return Ok(0),
+    };
+    let template = match glyph
+        .layers
+        .iter()
+        .find(|l| l.layer_id == master_id)
+        .or_else(|| glyph.layers.first())
+    {
+        Some(layer) => layer.clone(),
+        None => return Ok(0),
+    };
+    let n_nodes: usize = template.paths.iter().flatten().map(|p| p.nodes.len()).sum();
+    for (location, pts) in &braces {
+        let design: Vec<f64> = location
+            .iter()
+            .zip(axes.iter())
+            .map(|(&x, axis)| round2(axis.to_design(x)))
+            .collect();
+        let coords: Vec<String> = design.iter().map(|x| x.to_string()).collect();
+        let name = format!("{{{}}}", coords.join(", "));
+        let mut layer: Layer = template.clone();
+        layer.layer_id = format!("{}.{}", master_id, name);
+        // The nodes, then the attributes (advance, anchors, components and
+        // guides) interpolated at the same location.
+        let attributes = data.eval_attributes(location);
+        let geometry: Vec<Point> = pts
+            .iter()
+            .take(n_nodes)
+            .map(|&p| Point::new(round2(p.x), round2(p.y)))
+            .chain(
+                data.attribute_kinds
+                    .iter()
+                    .zip(attributes)
+                    .map(|(&kind, p)| round_attribute(kind, kind.transform(to_font, p))),
+            )
+            .collect();
+        set_layer_geometry(&mut layer, &geometry);
+        layer
+            .other_stuff
+            .insert("name".to_string(), Plist::String(name));
+        layer.other_stuff.insert(
+            "associatedMasterId".to_string(),
+            Plist::String(master_id.clone()),
+        );
+        glyph.layers.push(layer);
+    }
+    Ok(braces.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use glyphstool::NodeType;
+
+    use super::*;
+    use crate::app_state::InterpPt;
+    use crate::{glyph_transform, set_app_state_for_glyph};
+
+    /// The brace layers make the variation model follow the smooth
+    /// interpolation within tolerance, allowing for rounding.
+    #[test]
+    fn brace_layers_within_tolerance() {
+        let path = Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/testfont.glyphs"));
+        let mut font = Font::load(path).unwrap();
+        let mut smooth = AppState::default();
+        set_app_state_for_glyph(&mut smooth, &font, "n");
+        smooth.interp_type = InterpType::Gaussian;
+        let to_font = glyph_transform().inverse();
+        let opts = BakeOptions {
+            tolerance: 0.25,
+            steps: 64,
+        };
+        let n = bake_glyph(&mut font, "n", &smooth, to_font, &opts).unwrap();
+        assert!(n > 0);
+
+        let mut linear = AppState::default();
+        set_app_state_for_glyph(&mut linear, &font, "n");
+        linear.interp_type = InterpType::Variation;
+        for i in 0..=opts.steps {
+            let location = smooth.pad_location(&[i as f64 / opts.steps as f64]);
+            let expected = smooth.eval_pts(&location);
+            let actual = linear.eval_pts(&location);
+            for (&p, &q) in expected.iter().zip(&actual) {
+                let dist = (to_font * p - to_font * q).hypot();
+                assert!(dist <= opts.tolerance + 0.02, "{} at step {}", dist, i);
+            }
+        }
+    }
+
+    /// With two axes, brace layers are added away from the axes too, and
+    /// bring linear interpolation within tolerance everywhere.
+    #[test]
+    fn brace_layers_two_axes() {
+        let mut data = AppState::default();
+        let corners = [[0.0, 0.0], [1.0, 0.0], [0.0, 1.0], [1.0, 1.0]];
+        let pts = [(0.0, 0.0), (100.0, 0.0), (0.0, 100.0)]
+            .iter()
+            .map(|&(x, y)| {
+                // Only the far corner moves, so the axes don't blend
+                // linearly.
+                let samples = corners
+                    .iter()
+                    .map(|c| InterpSample {
+                        pt: Point::new(x + c[0] * c[1] * 300.0, y),
+                        location: c.to_vec(),
+                    })
+                    .collect();
+                InterpPt { samples }
+            })
+            .collect();
+        data.set_pts(pts);
+        data.set_structure(vec![vec![NodeType::Line; 3]]);
+        data.interp_type = InterpType::Gaussian;
+        let opts = BakeOptions {
+            tolerance: 2.0,
+            steps: 16,
+        };
+        let braces = brace_positions(&data, Affine::default(), &opts);
+        assert!(braces
+            .iter()
+            .any(|(location, _)| location.iter().all(|&x| x > 0.0 && x < 1.0)));
+
+        let mut linear = data.clone();
+        linear.interp_type = InterpType::Variation;
+        for (location, values) in &braces {
+            for (pt, &p) in Arc::make_mut(&mut linear.pts).iter_mut().zip(values) {
+                pt.samples.push(InterpSample {
+                    pt: p,
+                    location: location.clone(),
+                });
+            }
+        }
+        for i in 0..=opts.steps {
+            for j in 0..=opts.steps {
+                let steps = opts.steps as f64;
+                let location = vec![i as f64 / steps, j as f64 / steps];
+                let expected = data.eval_pts(&location);
+                let actual = linear.eval_pts(&location);
+                for (&p, &q) in expected.iter().zip(&actual) {
+                    let dist = (p - q).hypot();
+                    assert!(dist <= opts.tolerance + 1e-9, "{} at {:?}", dist, location);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn bake_without_masters() {
+        let path = Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/testfont.glyphs"));
+        let mut font = Font::load(path).unwrap();
+        let mut data = AppState::default();
+        set_app_state_for_glyph(&mut data, &font, "n");
+        font.font_master.clear();
+        let opts = BakeOptions {
+            tolerance: 1.0,
+            steps: 64,
+        };
+        let to_font = glyph_transform().inverse();
+        assert!(bake_glyph(&mut font, "n", &data, to_font, &opts).is_err());
+    }
+}
@@ -0,0 +1,200 @@
+//! Choosing kernel parameters by leave-one-out cross-validation.
+//!
+//! Each sample location (usually a master) in turn is left out and predicted
+//! from the others. The parameters with the smallest total squared error
+//! over all points are chosen, and can be recorded in the glyph so that
+//! later runs use the same ones, along with overrides for some points or
+//! contours. The metric of the axes is recorded in the font. Parameters are
+//! recorded with `glyphstool::settings`, so instance export uses them too.
+
+use druid::kurbo::{Affine, Point};
+
+use glyphstool::settings;
+use glyphstool::{Glyph, NodeType};
+
+use crate::app_state::{AppState, Coords, Extrapolation, InterpPt, InterpType, KernelParams};
+use crate::interp_model::{self, from_extrapolation, from_kernel, to_kernel};
+
+/// The relative improvement in error needed to prefer other parameters,
+/// so that ties (for example when the polynomial term alone determines the
+/// prediction) keep the current ones.
+const MIN_IMPROVEMENT: f64 = 1e-6;
+
+/// Candidate smoothing values.
+const SMOOTHING: &[f64] = &[0.0, 1e-4, 1e-3, 1e-2, 1e-1];
+
+/// Candidate radii, in units of the range of the masters, in geometric steps.
+fn radii() -> impl Iterator<Item = f64> {
+    (0..=24).map(|i| 0.05 * 1.25f64.powi(i))
+}
+
+pub struct Fit {
+    pub kernel: KernelParams,
+    /// Each sample location, in slider units, with the largest distance (in
+    /// font units) between a point and its prediction when that location is
+    /// left out.
+    pub errors: Vec<(Vec<f64>, f64)>,
+}
+
+/// The distinct locations of the samples of a glyph, in order.
+pub fn sample_locations(data: &AppState) -> Vec<Vec<f64>> {
+    let mut locations: Vec<Vec<f64>> = Vec::new();
+    for pt in data.pts.iter() {
+        for sample in &pt.samples {
+            if !locations.contains(&sample.location) {
+                locations.push(sample.location.clone());
+            }
+        }
+    }
+    locations
+}
+
+/// Predict each point at a sample location from its samples elsewhere.
+///
+/// Returns the sample and the prediction for each point, or `None` for
+/// points without a sample there or without any other samples.
+pub fn predict_held_out(
+    data: &AppState,
+    interp_type: InterpType,
+    kernel: KernelParams,
+    location: &[f64],
+) -> Vec<Option<(Point, Point)>> {
+    let mut held_out = Vec::with_capacity(data.pts.len());
+    let reduced: Vec<InterpPt> = data
+        .pts
+        .iter()
+        .map(|pt| {
+            let (out, samples): (Vec<_>, Vec<_>) = pt
+                .samples
+                .iter()
+                .cloned()
+                .partition(|s| s.location == location);
+            held_out.push(out.first().map(|s| s.pt).filter(|_| !samples.is_empty()));
+            InterpPt { samples }
+        })
+        .collect();
+    let predicted = data.eval_samples(&reduced, interp_type, kernel, location);
+    held_out
+        .into_iter()
+        .zip(predicted)
+        .map(|(actual, p)| actual.map(|a| (a, p)))
+        .collect()
+}
+
+/// The total squared error of leaving out each sample location, and the
+/// largest error at each.
+fn cross_validate(
+    data: &AppState,
+    kernel: KernelParams,
+    to_font: Affine,
+) -> (f64, Vec<(Vec<f64>, f64)>) {
+    let mut total = 0.0;
+    let errors = sample_locations(data)
+        .into_iter()
+        .map(|location| {
+            let mut max_err = 0.0f64;
+            let preds = predict_held_out(data, data.interp_type, kernel, &location);
+            for (actual, predicted) in preds.into_iter().flatten() {
+                let err = (to_font * actual - to_font * predicted).hypot();
+                total += err * err;
+                max_err = max_err.max(err);
+            }
+            (location, max_err)
+        })
+        .collect();
+    (total, errors)
+}
+
+/// Choose the kernel radius and smoothing for the current interpolation
+/// type that best predict each sample from the others. The polynomial
+/// order is kept.
+///
+/// `to_font` maps the toy's coordinates to font units.
+pub fn fit_kernel(data: &AppState, to_font: Affine) -> Fit {
+    let interp_type = data.interp_type;
+    let mut best = data.kernel;
+    let (mut best_score, _) = cross_validate(data, best, to_font);
+    if interp_type != InterpType::Variation {
+        let radii: Vec<f64> = if interp_type.uses_radius() {
+            radii().collect()
+        } else {
+            vec![data.kernel.radius]
+        };
+        for &radius in &radii {
+            for &smoothing in SMOOTHING {
+                let kernel = KernelParams {
+                    radius,
+                    smoothing,
+                    ..data.kernel
+                };
+                let (score, _) = cross_validate(data, kernel, to_font);
+                if score < best_score * (1.0 - MIN_IMPROVEMENT) {
+                    best = kernel;
+                    best_score = score;
+                }
+            }
+        }
+    }
+    let (_, errors) = cross_validate(data, best, to_font);
+    Fit {
+        kernel: best,
+        errors,
+    }
+}
+
+/// Record the interpolation type, coordinates, extrapolation and kernel
+/// parameters in the userData of a glyph, so they are used whenever it is
+/// loaded.
+pub fn record_kernel(
+    glyph: &mut Glyph,
+    interp_type: InterpType,
+    coords: Coords,
+    extrapolation: Extrapolation,
+    kernel: KernelParams,
+) {
+    settings::record_coords(glyph, interp_model::coords(coords));
+    let extrapolation = interp_model::extrapolation(extrapolation);
+    settings::record_extrapolation(glyph, extrapolation);
+    settings::record_kernel(glyph, to_kernel(interp_type, kernel));
+}
+
+/// The interpolation type and kernel parameters recorded for a glyph.
+pub fn recorded_kernel(glyph: &Glyph) -> Option<(InterpType, KernelParams)> {
+    settings::recorded_kernel(glyph).map(from_kernel)
+}
+
+/// The coordinates recorded for a glyph.
+pub fn recorded_coords(glyph: &Glyph) -> Option<Coords> {
+    let coords = settings::recorded_coords(glyph).ok()??;
+    Some(interp_model::from_coords(coords))
+}
+
+/// The extrapolation recorded for a glyph.
+pub fn recorded_extrapolation(glyph: &Glyph) -> Option<Extrapolation> {
+    settings::recorded_extrapolation(glyph).map(from_extrapolation)
+}
+
+/// Record the overrides of a glyph, by point index, in its userData.
+pub fn record_overrides(
+    glyph: &mut Glyph,
+    overrides: &[Option<(InterpType, KernelParams)>],
+    structure: &[Vec<NodeType>],
+) {
+    let overrides: Vec<_> = overrides
+        .iter()
+        .map(|value| value.map(|(interp_type, kernel)| to_kernel(interp_type, kernel)))
+        .collect();
+    settings::record_overrides(glyph, &overrides, structure);
+}
+
+/// The overrides recorded for a glyph with the given structure, by point
+/// index.
+pub fn recorded_overrides(
+    glyph: &Glyph,
+    structure: &[Vec<NodeType>],
+) -> Vec<Option<(InterpType, KernelParams)>> {
+    settings::recorded_overrides(glyph, structure)
+        .into_iter()
+        .map(|value| value.map(from_kernel))
+        .collect()
+}
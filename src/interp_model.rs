@@ -0,0 +1,92 @@
+//! The interpolation settings of the toy, in terms of the library's model.
+//!
+//! Glyphs are evaluated with the same `Model` that instance export uses,
+//! and settings are recorded in the font in the library's terms; this maps
+//! the toy's interpolation types, coordinates and extrapolation policies to
+//! it.
+
+use glyphstool::interp::{self, Basis, Method};
+use glyphstool::settings::Kernel;
+
+use crate::app_state::{Coords, Extrapolation, InterpType, KernelParams};
+
+/// The library's interpolation method for an interpolation type.
+pub fn method(interp_type: InterpType, kernel: KernelParams) -> Method {
+    to_kernel(interp_type, kernel).method()
+}
+
+/// An interpolation type and kernel parameters as recorded in the font.
+pub fn to_kernel(interp_type: InterpType, kernel: KernelParams) -> Kernel {
+    let basis = match interp_type {
+        InterpType::Gaussian => Some(Basis::Gaussian),
+        InterpType::ThinPlate => Some(Basis::ThinPlate),
+        InterpType::Linear => Some(Basis::Linear),
+        InterpType::Multiquadric => Some(Basis::Multiquadric),
+        InterpType::InverseMultiquadric => Some(Basis::InverseMultiquadric),
+        InterpType::Cubic => Some(Basis::Cubic),
+        InterpType::Wendland => Some(Basis::Wendland),
+        InterpType::Variation => None,
+    };
+    Kernel {
+        basis,
+        radius: kernel.radius,
+        order: kernel.order,
+        smoothing: kernel.smoothing,
+    }
+}
+
+/// The interpolation type and kernel parameters of a recorded kernel.
+pub fn from_kernel(kernel: Kernel) -> (InterpType, KernelParams) {
+    let interp_type = match kernel.basis {
+        Some(Basis::Gaussian) => InterpType::Gaussian,
+        Some(Basis::ThinPlate) => InterpType::ThinPlate,
+        Some(Basis::Linear) => InterpType::Linear,
+        Some(Basis::Multiquadric) => InterpType::Multiquadric,
+        Some(Basis::InverseMultiquadric) => InterpType::InverseMultiquadric,
+        Some(Basis::Cubic) => InterpType::Cubic,
+        Some(Basis::Wendland) => InterpType::Wendland,
+        None => InterpType::Variation,
+    };
+    let params = KernelParams {
+        radius: kernel.radius,
+        order: kernel.order,
+        smoothing: kernel.smoothing,
+    };
+    (interp_type, params)
+}
+
+/// The library's coordinates.
+pub fn coords(coords: Coords) -> interp::Coords {
+    match coords {
+        Coords::Cartesian => interp::Coords::Cartesian,
+        Coords::Polar => interp::Coords::Polar,
+        Coords::Rigid => interp::Coords::Rigid,
+    }
+}
+
+/// The toy's coordinates.
+pub fn from_coords(coords: interp::Coords) -> Coords {
+    match coords {
+        interp::Coords::Cartesian => Coords::Cartesian,
+        interp::Coords::Polar => Coords::Polar,
+        interp::Coords::Rigid => Coords::Rigid,
+    }
+}
+
+/// The library's extrapolation policy.
+pub fn extrapolation(extrapolation: Extrapolation) -> interp::Extrapolation {
+    match extrapolation {
+        Extrapolation::Raw => interp::Extrapolation::Raw,
+        Extrapolation::Clamp => interp::Extrapolation::Clamp,
+        Extrapolation::Linear => interp::Extrapolation::Linear,
+    }
+}
+
+/// The toy's extrapolation policy.
+pub fn from_extrapolation(extrapolation: interp::Extrapolation) -> Extrapolation {
+    match extrapolation {
+        interp::Extrapolation::Raw => Extrapolation::Raw,
+        interp::Extrapolation::Clamp => Extrapolation::Clamp,
+        interp::Extrapolation::Linear => Extrapolation::Linear,
+    }
+}
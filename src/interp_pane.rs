@@ -1,9 +1,9 @@
 use std::ops::Deref;
 use std::sync::Arc;
 
-use glyphstool::NodeType;
+use glyphstool::{append_contour, NodeType, PointKind};
 
-use druid::kurbo::{BezPath, Circle, Point, Size};
+use druid::kurbo::{BezPath, Circle, Line, Point, Rect, Size, Vec2};
 use druid::piet::{Color, RenderContext};
 use druid::{
     BaseState, BoxConstraints, Env, Event, EventCtx, LayoutCtx, PaintCtx, UpdateCtx, Widget,
@@ -13,6 +13,13 @@ use crate::AppState;
 
 use crate::InterpPt;
 
+/// How far the axes of a component are drawn, in multiples of its
+/// transform (so in font units at the scale of the component).
+const AXIS_LENGTH: f64 = 100.0;
+
+/// How far guides are drawn either side of their position.
+const GUIDE_LENGTH: f64 = 1000.0;
+
 #[derive(Default)]
 pub struct InterpPane {
     drag_ix: Option<usize>,
@@ -27,31 +34,50 @@ enum PtState {
     IsMaster,
 }
 
-fn reconstruct_path(pts: &[Point], structure: &[Vec<NodeType>]) -> BezPath {
+/// Draw the attributes of the glyph: the advance as a vertical line, anchors
+/// as rings, components as their offset and axes, and guides as lines.
+fn paint_attributes(paint_ctx: &mut PaintCtx, size: Size, kinds: &[PointKind], values: &[Point]) {
+    let advance_color = Color::rgb8(0xa0, 0xa0, 0xa0);
+    let anchor_color = Color::rgb8(0xc0, 0x40, 0x40);
+    let component_color = Color::rgb8(0x40, 0xa0, 0x40);
+    let guide_color = Color::rgb8(0x40, 0xa0, 0xc0);
+    let offset = |p: Point, v: Point| p + Vec2::new(v.x, v.y) * AXIS_LENGTH;
+    let mut component = Point::ORIGIN;
+    let mut guide = Point::ORIGIN;
+    for (&kind, &p) in kinds.iter().zip(values) {
+        match kind {
+            PointKind::Node => (),
+            PointKind::Advance => {
+                let line = Line::new((p.x, 0.0), (p.x, size.height));
+                paint_ctx.stroke(line, &advance_color, 1.0);
+            }
+            PointKind::Anchor => paint_ctx.stroke(Circle::new(p, 4.0), &anchor_color, 1.0),
+            PointKind::ComponentOffset => {
+                component = p;
+                let square = Rect::from_origin_size(p - Vec2::new(2.5, 2.5), (5.0, 5.0));
+                paint_ctx.fill(square, &component_color);
+            }
+            PointKind::ComponentXAxis | PointKind::ComponentYAxis => {
+                let line = Line::new(component, offset(component, p));
+                paint_ctx.stroke(line, &component_color, 1.0);
+            }
+            PointKind::GuidePosition => guide = p,
+            PointKind::GuideAngle => {
+                let angle = p.x.to_radians();
+                let d = Vec2::new(angle.cos(), angle.sin()) * GUIDE_LENGTH;
+                let line = Line::new(guide - d, guide + d);
+                paint_ctx.stroke(line, &guide_color, 0.5);
+            }
+        }
+    }
+}
+
+pub fn reconstruct_path(pts: &[Point], structure: &[Vec<NodeType>]) -> BezPath {
     let mut bez_path = BezPath::new();
     let mut j = 0;
     for subpath in structure {
-        let mut start_ix = 0;
-        while subpath[start_ix] == NodeType::OffCurve {
-            start_ix += 1;
-        }
         let n = subpath.len();
-        bez_path.move_to(pts[j + start_ix]);
-        let mut ctrl_pts = Vec::with_capacity(2);
-        for i in 0..n {
-            let ix = (start_ix + i + 1) % n;
-            let node_type = subpath[ix];
-            let p = pts[j + ix];
-            match node_type {
-                NodeType::Line | NodeType::LineSmooth => bez_path.line_to(p),
-                NodeType::OffCurve => ctrl_pts.push(p),
-                NodeType::Curve | NodeType::CurveSmooth => {
-                    bez_path.curve_to(ctrl_pts[0], ctrl_pts[1], p);
-                    ctrl_pts.clear();
-                }
-            }
-        }
-        bez_path.close_path();
+        append_contour(&mut bez_path, &pts[j..j + n], subpath, true);
         j += n;
     }
     bez_path
@@ -61,25 +87,36 @@ impl Widget<AppState> for InterpPane {
     fn paint(
         &mut self,
         paint_ctx: &mut PaintCtx,
-        _base_state: &BaseState,
+        base_state: &BaseState,
         data: &AppState,
         _env: &Env,
     ) {
-        let width = data.shared.width;
-        let weight = data.shared.weight;
+        // Outside the hull of the masters, tint the glyph and frame the pane.
+        let is_extrapolated = data.is_extrapolated(&data.shared.location);
+        if is_extrapolated {
+            let frame = Rect::from_origin_size(Point::ORIGIN, base_state.size());
+            paint_ctx.stroke(frame, &Color::rgb8(0xff, 0x40, 0x40), 4.0);
+        }
         let pt_state = if data.is_at_master() {
             PtState::CanAddMaster
         } else {
             PtState::Interpolated
         };
-        let pts: Vec<_> = data
-            .pts
-            .iter()
-            .map(|pt| pt.eval(width, weight, data.interp_type))
-            .collect();
-        let fill_color = Color::WHITE;
+        let pts = data.eval_pts(&data.shared.location);
+        let fill_color = if is_extrapolated {
+            Color::rgb8(0xff, 0xd0, 0xd0)
+        } else {
+            Color::WHITE
+        };
         let path = reconstruct_path(&pts, &data.structure);
         paint_ctx.fill(path, &fill_color);
+        let attributes = data.eval_attributes(&data.shared.location);
+        paint_attributes(
+            paint_ctx,
+            base_state.size(),
+            &data.attribute_kinds,
+            &attributes,
+        );
         for i in 0..pts.len() {
             let fg_color = match pt_state {
                 PtState::CanAddMaster => Color::rgb8(0x80, 0x80, 0xff),
@@ -105,24 +142,23 @@ impl Widget<AppState> for InterpPane {
     }
 
     fn event(&mut self, event: &Event, ctx: &mut EventCtx, data: &mut AppState, _env: &Env) {
-        let width = data.shared.width;
-        let weight = data.shared.weight;
+        let location = data.shared.location.clone();
         match event {
             Event::MouseDown(e) => {
                 println!("mouse down {:?}!", e);
                 let pos = e.pos;
-                let mut pts = data.pts.deref().clone();
-                for (i, pt) in pts.iter().enumerate() {
-                    let interp = pt.eval(width, weight, data.interp_type);
+                let interp_pts = data.eval_pts(&location);
+                for (i, interp) in interp_pts.iter().enumerate() {
                     if interp.distance(pos) < 5.0 {
                         self.drag_ix = Some(i);
                         data.sel = Some(i);
                         return;
                     }
                 }
+                let mut pts = data.pts.deref().clone();
                 self.drag_ix = Some(pts.len());
                 data.sel = self.drag_ix;
-                let pt = InterpPt::new(pos, width, weight);
+                let pt = InterpPt::new(pos, location);
                 pts.push(pt);
                 data.pts = Arc::new(pts);
                 ctx.invalidate();
@@ -133,7 +169,7 @@ impl Widget<AppState> for InterpPane {
             Event::MouseMoved(e) => {
                 if let Some(drag_ix) = self.drag_ix {
                     let mut pts = data.pts.deref().clone();
-                    pts[drag_ix].update(e.pos, data.shared.width, data.shared.weight);
+                    pts[drag_ix].update(e.pos, &location);
                     data.pts = Arc::new(pts);
                     ctx.invalidate();
                 }
@@ -1,7 +1,7 @@
-use std::collections::HashMap;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use kurbo::Affine;
+use nalgebra::DMatrix;
 
 use druid::widget::{Button, Column, DynLabel, Padding, RadioGroup, Row, Scroll, Slider};
 use druid::{AppLauncher, LensWrap, LocalizedString, Widget, WindowDesc};
@@ -14,59 +14,428 @@ enum Cmd {
     Blank,
     /// Load a glyph from a font.
     Glyph(GlyphCmd),
+    /// Render a glyph to SVG without opening a window.
+    Render(RenderCmd),
+    /// Render an animation sweeping between two locations.
+    Animate(AnimateCmd),
+    /// Add brace layers so linear interpolation follows RBF within tolerance.
+    Bake(BakeCmd),
+    /// Choose kernel parameters by cross-validation and record them.
+    Fit(FitCmd),
+    /// Compare interpolation types by predicting each master from the rest.
+    Report(ReportCmd),
 }
 
 #[derive(Debug, StructOpt)]
 struct GlyphCmd {
-    /// A font file in Glyphs format.
+    /// A font file: Glyphs, designspace, UFO or variable TTF.
     #[structopt(parse(from_os_str))]
     input: PathBuf,
 
     /// The name of the glyph to read.
     glyph: String,
+
+    #[structopt(flatten)]
+    kernel: KernelArgs,
+}
+
+#[derive(Debug, StructOpt)]
+struct RenderCmd {
+    /// A font file: Glyphs, designspace, UFO or variable TTF.
+    #[structopt(parse(from_os_str))]
+    input: PathBuf,
+
+    /// The name of the glyph to render.
+    glyph: String,
+
+    /// The SVG file to write.
+    #[structopt(short, long, parse(from_os_str))]
+    output: PathBuf,
+
+    /// Location in slider units (0 to 1 across the masters), one value per
+    /// axis, comma separated. Missing values are 0.
+    #[structopt(long, use_delimiter = true)]
+    location: Vec<f64>,
+
+    /// Interpolation type: gaussian, thin-plate, linear, variation,
+    /// multiquadric, inverse-multiquadric, cubic or wendland (default the
+    /// one recorded for the glyph, else gaussian).
+    #[structopt(long)]
+    interp: Option<InterpType>,
+
+    #[structopt(flatten)]
+    kernel: KernelArgs,
+
+    #[structopt(flatten)]
+    opts: RenderOptions,
+}
+
+#[derive(Debug, StructOpt)]
+struct AnimateCmd {
+    /// A font file: Glyphs, designspace, UFO or variable TTF.
+    #[structopt(parse(from_os_str))]
+    input: PathBuf,
+
+    /// The name of the glyph to render.
+    glyph: String,
+
+    /// The directory to write SVG files into.
+    #[structopt(short, long, parse(from_os_str))]
+    output: PathBuf,
+
+    /// Location at the start of the sweep, in slider units, comma
+    /// separated. Missing values are 0.
+    #[structopt(long, use_delimiter = true)]
+    from: Vec<f64>,
+
+    /// Location at the end of the sweep (default the end of the first
+    /// axis).
+    #[structopt(long, use_delimiter = true)]
+    to: Vec<f64>,
+
+    /// Number of frames, including both ends.
+    #[structopt(long, default_value = "30")]
+    frames: usize,
+
+    /// Easing: linear or ease-in-out.
+    #[structopt(long, default_value = "linear")]
+    easing: Easing,
+
+    /// Interpolation types to render, one output each (default the one
+    /// recorded for the glyph, else gaussian).
+    #[structopt(long)]
+    interp: Vec<InterpType>,
+
+    #[structopt(flatten)]
+    kernel: KernelArgs,
+
+    /// Write one animated SVG (SMIL) per type instead of numbered frames.
+    #[structopt(long)]
+    smil: bool,
+
+    /// Duration of the SMIL animation, in seconds.
+    #[structopt(long, default_value = "2")]
+    duration: f64,
+
+    #[structopt(flatten)]
+    opts: RenderOptions,
+}
+
+#[derive(Debug, StructOpt)]
+struct BakeCmd {
+    /// A font file: Glyphs, designspace, UFO or variable TTF. The result
+    /// is written in Glyphs format, alongside if it isn't already.
+    #[structopt(parse(from_os_str))]
+    input: PathBuf,
+
+    /// Glyphs to bake (default all).
+    glyphs: Vec<String>,
+
+    /// Maximum distance from the RBF result, in font units.
+    #[structopt(long, default_value = "1.0")]
+    tolerance: f64,
+
+    /// Interpolation type to follow (default the one recorded for each
+    /// glyph, else gaussian).
+    #[structopt(long)]
+    interp: Option<InterpType>,
+
+    #[structopt(flatten)]
+    kernel: KernelArgs,
+
+    /// Number of steps at which each axis is checked.
+    #[structopt(long, default_value = "64")]
+    steps: usize,
 }
 
+#[derive(Debug, StructOpt)]
+struct FitCmd {
+    /// A font file: Glyphs, designspace, UFO or variable TTF. The result
+    /// is written in Glyphs format, alongside if it isn't already.
+    #[structopt(parse(from_os_str))]
+    input: PathBuf,
+
+    /// Glyphs to fit (default all).
+    glyphs: Vec<String>,
+
+    /// Interpolation type to fit (default the one recorded for each glyph,
+    /// else gaussian).
+    #[structopt(long)]
+    interp: Option<InterpType>,
+
+    /// Order of the polynomial term, kept fixed while fitting.
+    #[structopt(long)]
+    order: Option<usize>,
+
+    /// Coordinates to interpolate in: cartesian, polar or rigid (default
+    /// the ones recorded for each glyph, else cartesian).
+    #[structopt(long)]
+    coords: Option<Coords>,
+}
+
+#[derive(Debug, StructOpt)]
+struct ReportCmd {
+    /// A font file: Glyphs, designspace, UFO or variable TTF.
+    #[structopt(parse(from_os_str))]
+    input: PathBuf,
+
+    /// Glyphs to report on (default all).
+    glyphs: Vec<String>,
+
+    /// Interpolation types to compare (default all). Each uses the kernel
+    /// parameters recorded for the glyph if they are for that type.
+    #[structopt(long)]
+    interp: Vec<InterpType>,
+
+    #[structopt(flatten)]
+    kernel: KernelArgs,
+
+    /// Output format: csv or json.
+    #[structopt(long, default_value = "csv")]
+    format: report::ReportFormat,
+
+    /// Write the largest and mean deviation of each point over the left-out
+    /// locations, instead of a row per location.
+    #[structopt(long)]
+    points: bool,
+
+    /// The file to write (default standard output).
+    #[structopt(short, long, parse(from_os_str))]
+    output: Option<PathBuf>,
+}
+
+/// Kernel parameters and coordinates given on the command line. These
+/// override the ones recorded for the glyph.
+#[derive(Debug, StructOpt)]
+struct KernelArgs {
+    /// Coordinates to interpolate in: cartesian, polar to keep rotating
+    /// features their size, or rigid to keep contours that move as a whole
+    /// rigid (default the ones recorded for the glyph, else cartesian).
+    #[structopt(long)]
+    coords: Option<Coords>,
+
+    /// What to do outside the convex hull of the masters: raw to evaluate
+    /// the interpolation anyway, clamp to stop at the hull, or linear to
+    /// continue from it in a straight line (default the one recorded for
+    /// the glyph, else raw).
+    #[structopt(long)]
+    extrapolation: Option<Extrapolation>,
+
+    /// Kernel radius, in units of the range of the masters. Used by the gaussian, multiquadric,
+    /// inverse multiquadric and Wendland bases.
+    #[structopt(long)]
+    radius: Option<f64>,
+
+    /// Order of the polynomial term: 0 for none, 1 for a constant, 2 for
+    /// linear (default 1 for linear interpolation, otherwise 2).
+    #[structopt(long)]
+    order: Option<usize>,
+
+    /// Regularization added to the diagonal of the kernel matrix.
+    #[structopt(long)]
+    smoothing: Option<f64>,
+
+    /// Factor by which distances along each axis are scaled, in units of
+    /// the range of the masters, comma separated (default the ones recorded
+    /// for the font, else the length of each axis relative to the longest).
+    #[structopt(long, use_delimiter = true)]
+    axis_scales: Vec<f64>,
+
+    /// Metric matrix for the scaled offsets between locations: one row per
+    /// axis, row by row, comma separated. It must be symmetric positive
+    /// definite (default the one recorded for the font, else the identity).
+    #[structopt(long, use_delimiter = true)]
+    metric: Vec<f64>,
+}
+
+mod animate;
 mod app_state;
+mod bake;
+mod fit;
+mod interp_model;
 mod interp_pane;
 mod lens2;
 mod list;
 mod master;
+mod render;
+mod report;
 
-use glyphstool::Font;
+use glyphstool::interp::{Method, Model};
+use glyphstool::settings;
+use glyphstool::{layer_geometry, unwrap_angle, Font, PointKind, ToPlist};
 
-use app_state::{lenses, AppState, InterpPt, InterpSample, InterpType};
+use animate::{Easing, Sweep};
+use app_state::{
+    lenses, AppState, Axis, Coords, Extrapolation, InterpPt, InterpSample, InterpType, KernelParams,
+};
 use interp_pane::InterpPane;
 use lens2::{Lens2Wrap, Pair};
 use list::List;
 use master::MasterItem;
+use render::RenderOptions;
 
-fn build_ui() -> impl Widget<AppState> {
+/// Build the UI. `source` is the font file and glyph being edited, if any,
+/// which the kernel settings are saved to.
+fn build_ui(n_axes: usize, source: Option<(PathBuf, String)>) -> impl Widget<AppState> {
     let pane = InterpPane::default();
     let mut col = Column::new();
+    for i in 0..n_axes {
+        col.add_child(
+            Padding::uniform(
+                5.0,
+                LensWrap::new(Slider::new(), lenses::app_state::AxisValue(i)),
+            ),
+            0.0,
+        );
+        let mut axis_row = Row::new();
+        let label = DynLabel::new(move |data: &AppState, _env| {
+            let axis = &data.shared.axes[i];
+            format!(
+                "{}: {:.2}, scale {:.2}",
+                axis.name,
+                data.shared.location[i],
+                data.metric.scale(i)
+            )
+        });
+        axis_row.add_child(Padding::uniform(5.0, label), 1.0);
+        let shrink = Button::new("Scale -", move |_ctx, data: &mut AppState, _env| {
+            let scale = data.metric.scale(i);
+            data.metric.set_scale(i, scale / SCALE_STEP);
+        });
+        axis_row.add_child(Padding::uniform(5.0, shrink), 0.0);
+        let grow = Button::new("Scale +", move |_ctx, data: &mut AppState, _env| {
+            let scale = data.metric.scale(i);
+            data.metric.set_scale(i, scale * SCALE_STEP);
+        });
+        axis_row.add_child(Padding::uniform(5.0, grow), 0.0);
+        col.add_child(axis_row, 0.0);
+    }
+    col.add_child(
+        LensWrap::new(
+            RadioGroup::new(vec![
+                (LocalizedString::new("Gaussian"), InterpType::Gaussian),
+                (LocalizedString::new("Thin plate"), InterpType::ThinPlate),
+                (LocalizedString::new("Linear"), InterpType::Linear),
+                (LocalizedString::new("Variation"), InterpType::Variation),
+                (
+                    LocalizedString::new("Multiquadric"),
+                    InterpType::Multiquadric,
+                ),
+                (
+                    LocalizedString::new("Inverse multiquadric"),
+                    InterpType::InverseMultiquadric,
+                ),
+                (LocalizedString::new("Cubic"), InterpType::Cubic),
+                (LocalizedString::new("Wendland"), InterpType::Wendland),
+            ]),
+            lenses::app_state::InterpType,
+        ),
+        0.0,
+    );
     col.add_child(
-        Padding::uniform(5.0, LensWrap::new(Slider::new(), lenses::app_state::Weight)),
+        LensWrap::new(
+            RadioGroup::new(vec![
+                (LocalizedString::new("Cartesian"), Coords::Cartesian),
+                (LocalizedString::new("Polar"), Coords::Polar),
+                (LocalizedString::new("Rigid"), Coords::Rigid),
+            ]),
+            lenses::app_state::Coords,
+        ),
         0.0,
     );
-    let label = DynLabel::new(|data: &AppState, _env| format!("weight: {:.2}", data.shared.weight));
-    col.add_child(Padding::uniform(5.0, label), 0.0);
     col.add_child(
-        Padding::uniform(5.0, LensWrap::new(Slider::new(), lenses::app_state::Width)),
+        LensWrap::new(
+            RadioGroup::new(vec![
+                (
+                    LocalizedString::new("Raw extrapolation"),
+                    Extrapolation::Raw,
+                ),
+                (LocalizedString::new("Clamp to hull"), Extrapolation::Clamp),
+                (
+                    LocalizedString::new("Linear extrapolation"),
+                    Extrapolation::Linear,
+                ),
+            ]),
+            lenses::app_state::Extrapolation,
+        ),
         0.0,
     );
-    let label_wdth =
-        DynLabel::new(|data: &AppState, _env| format!("width: {:.2}", data.shared.width));
-    col.add_child(Padding::uniform(5.0, label_wdth), 0.0);
+    let mut radius_row = Row::new();
+    let smaller = Button::new("Smaller", |_ctx, data: &mut AppState, _env| {
+        data.kernel.radius /= RADIUS_STEP
+    });
+    radius_row.add_child(Padding::uniform(5.0, smaller), 0.0);
+    let larger = Button::new("Larger", |_ctx, data: &mut AppState, _env| {
+        data.kernel.radius *= RADIUS_STEP
+    });
+    radius_row.add_child(Padding::uniform(5.0, larger), 0.0);
+    let radius_label =
+        DynLabel::new(|data: &AppState, _env| format!("radius: {:.3}", data.kernel.radius));
+    radius_row.add_child(Padding::uniform(5.0, radius_label), 1.0);
+    col.add_child(radius_row, 0.0);
     col.add_child(
         LensWrap::new(
             RadioGroup::new(vec![
-                (LocalizedString::new("Gaussian"), InterpType::Gaussian),
-                (LocalizedString::new("Thin plate"), InterpType::ThinPlate),
-                (LocalizedString::new("Linear"), InterpType::Linear),
+                (LocalizedString::new("Default order"), None),
+                (LocalizedString::new("No polynomial"), Some(0)),
+                (LocalizedString::new("Constant"), Some(1)),
+                (LocalizedString::new("Linear polynomial"), Some(2)),
             ]),
-            lenses::app_state::InterpType,
+            lenses::app_state::Order,
         ),
         0.0,
     );
+    let fit_button = Button::new("Fit Kernel", |_ctx, data: &mut AppState, _env| {
+        let fit = fit::fit_kernel(data, glyph_transform().inverse());
+        print_fit(data, &fit);
+        data.kernel = fit.kernel;
+    });
+    col.add_child(Padding::uniform(5.0, fit_button), 0.0);
+    let mut override_row = Row::new();
+    let override_point = Button::new("Override Point", |_ctx, data: &mut AppState, _env| {
+        if let Some(i) = data.sel {
+            data.set_override(i..i + 1, Some((data.interp_type, data.kernel)));
+        }
+    });
+    override_row.add_child(Padding::uniform(5.0, override_point), 0.0);
+    let override_contour = Button::new("Override Contour", |_ctx, data: &mut AppState, _env| {
+        if let Some(range) = data.sel.and_then(|i| data.contour_range(i)) {
+            data.set_override(range, Some((data.interp_type, data.kernel)));
+        }
+    });
+    override_row.add_child(Padding::uniform(5.0, override_contour), 0.0);
+    let clear_override = Button::new("Clear Contour", |_ctx, data: &mut AppState, _env| {
+        if let Some(range) = data.sel.and_then(|i| data.contour_range(i)) {
+            data.set_override(range, None);
+        }
+    });
+    override_row.add_child(Padding::uniform(5.0, clear_override), 0.0);
+    col.add_child(override_row, 0.0);
+    let override_label = DynLabel::new(|data: &AppState, _env| match data.sel {
+        Some(i) => {
+            let (interp_type, kernel) = data.point_kernel(i);
+            let kind = if data.overrides.get(i).copied().flatten().is_some() {
+                "override"
+            } else {
+                "glyph"
+            };
+            format!(
+                "point {}: {} ({}), radius {:.3}",
+                i,
+                interp_type.name(),
+                kind,
+                kernel.radius
+            )
+        }
+        None => "no point selected".to_string(),
+    });
+    col.add_child(Padding::uniform(5.0, override_label), 0.0);
+    if let Some((path, glyphname)) = source {
+        let save_button = Button::new("Save", move |_ctx, data: &mut AppState, _env| {
+            save_glyph_settings(&path, &glyphname, data)
+        });
+        col.add_child(Padding::uniform(5.0, save_button), 0.0);
+    }
     let new_master_button = Button::new("New Master", |_ctx, data: &mut AppState, _env| {
         data.add_new_master()
     });
@@ -85,66 +454,410 @@ fn build_ui() -> impl Widget<AppState> {
     row
 }
 
+/// The factor by which the radius buttons change the kernel radius.
+const RADIUS_STEP: f64 = 1.25;
+
+/// The factor by which the scale buttons change the scale of an axis.
+const SCALE_STEP: f64 = 1.25;
+
+/// The transform from font units to the coordinates of the toy.
+fn glyph_transform() -> Affine {
+    Affine::new([0.5, 0.0, 0.0, -0.5, 0.0, 500.0])
+}
+
+impl KernelArgs {
+    fn apply(&self, kernel: &mut KernelParams) {
+        if let Some(radius) = self.radius {
+            kernel.radius = radius;
+        }
+        if self.order.is_some() {
+            kernel.order = self.order;
+        }
+        if let Some(smoothing) = self.smoothing {
+            kernel.smoothing = smoothing;
+        }
+    }
+}
+
+/// Set the interpolation type, kernel parameters, coordinates, extrapolation
+/// and metric. The kernel parameters recorded for the glyph are used unless
+/// another type is given, and options given on the command line override
+/// them.
+fn set_kernel(
+    app_state: &mut AppState,
+    recorded: (InterpType, KernelParams),
+    interp_type: Option<InterpType>,
+    args: &KernelArgs,
+) {
+    let interp_type = interp_type.unwrap_or(recorded.0);
+    app_state.interp_type = interp_type;
+    app_state.kernel = if interp_type == recorded.0 {
+        recorded.1
+    } else {
+        KernelParams::default()
+    };
+    args.apply(&mut app_state.kernel);
+    if let Some(coords) = args.coords {
+        app_state.coords = coords;
+    }
+    if let Some(extrapolation) = args.extrapolation {
+        app_state.extrapolation = extrapolation;
+    }
+    if !args.axis_scales.is_empty() {
+        app_state.metric.scales = args.axis_scales.clone();
+    }
+    if !args.metric.is_empty() {
+        let n_axes = app_state.shared.axes.len();
+        let matrix = metric_matrix(&args.metric, n_axes).expect("invalid metric");
+        app_state.metric.matrix = Some(matrix);
+    }
+}
+
+/// The metric matrix from its values row by row. It must be symmetric and
+/// positive definite to measure distances.
+fn metric_matrix(values: &[f64], n_axes: usize) -> Result<Vec<Vec<f64>>, String> {
+    if values.len() != n_axes * n_axes {
+        return Err(format!(
+            "expected {} values for {} axes, got {}",
+            n_axes * n_axes,
+            n_axes,
+            values.len()
+        ));
+    }
+    let matrix: Vec<Vec<f64>> = values.chunks(n_axes).map(|row| row.to_vec()).collect();
+    for (i, row) in matrix.iter().enumerate() {
+        for (j, &x) in row.iter().enumerate().take(i) {
+            if x != matrix[j][i] {
+                return Err("the matrix is not symmetric".to_string());
+            }
+        }
+    }
+    if DMatrix::from_row_slice(n_axes, n_axes, values)
+        .cholesky()
+        .is_none()
+    {
+        return Err("the matrix is not positive definite".to_string());
+    }
+    Ok(matrix)
+}
+
+/// Record the interpolation settings and overrides of the toy in a glyph,
+/// and the metric in the font, and write the font in Glyphs format,
+/// alongside if it isn't already.
+fn save_glyph_settings(path: &Path, glyphname: &str, data: &AppState) {
+    let mut font = Font::load(path).unwrap();
+    if let Some(glyph) = font.get_glyph_mut(glyphname) {
+        fit::record_kernel(
+            glyph,
+            data.interp_type,
+            data.coords,
+            data.extrapolation,
+            data.kernel,
+        );
+        fit::record_overrides(glyph, &data.overrides, &data.structure);
+    }
+    settings::record_metric(&mut font, &data.metric);
+    let path = path.with_extension("glyphs");
+    std::fs::write(&path, font.to_plist().to_string()).expect("error writing font");
+    println!("saved {}", path.display());
+}
+
+/// Print fitted kernel parameters and the held-out error at each sample.
+fn print_fit(data: &AppState, fit: &fit::Fit) {
+    let kernel = &fit.kernel;
+    println!(
+        "{}: radius {:.4}, smoothing {}",
+        data.interp_type.name(),
+        kernel.radius,
+        kernel.smoothing
+    );
+    for (location, error) in &fit.errors {
+        println!(
+            "  held-out error at {}: {:.2}",
+            data.location_name(location),
+            error
+        );
+    }
+}
+
+/// The axes of a font, with the range of the masters along each, used to
+/// map design space locations to slider units.
+fn font_axes(font: &Font) -> Vec<Axis> {
+    let axes = font.axes();
+    let locations: Vec<Vec<f64>> = font.font_master.iter().map(|m| m.location(&axes)).collect();
+    axes.iter()
+        .enumerate()
+        .map(|(i, axis)| {
+            let mut values = locations.iter().map(|loc| loc[i]);
+            let first = values.next().unwrap_or(0.0);
+            let (min, max) = values.fold((first, first), |(min, max), x| (min.min(x), max.max(x)));
+            Axis::new(axis.name.clone(), min, max)
+        })
+        .collect()
+}
+
 fn set_app_state_for_glyph(app_state: &mut AppState, font: &Font, glyphname: &str) {
-    let a = Affine::new([0.5, 0.0, 0.0, -0.5, 0.0, 500.0]);
-    let mut weight_map = HashMap::new();
+    let a = glyph_transform();
+    let axes = font_axes(font);
+    let font_axes = font.axes();
+    let to_slider = |location: Vec<f64>| -> Vec<f64> {
+        location
+            .iter()
+            .zip(&axes)
+            .map(|(&x, axis)| axis.to_slider(x))
+            .collect()
+    };
     for master in &font.font_master {
-        let weight = (master.weight_value - 200) as f64 / 700.0;
-        weight_map.insert(master.id.clone(), weight);
-        app_state.add_weight(weight);
+        app_state.add_master(to_slider(master.location(&font_axes)));
     }
+    app_state.model = Model::new(font, Method::Linear);
+    app_state.metric = app_state.model.metric().clone();
     if let Some(glyph) = font.get_glyph(glyphname) {
         let mut structure = Vec::new();
         let mut first_layer = true;
         let mut pts = Vec::new();
+        let mut attributes = Vec::new();
+        let mut attribute_kinds = Vec::new();
         for layer in &glyph.layers {
-            if let Some(&weight) = weight_map.get(&layer.layer_id) {
-                let mut i = 0;
-                for p in layer.paths.as_ref().unwrap() {
-                    if first_layer {
-                        structure.push(Vec::new());
+            // Brace layers (such as intermediate regions of a variable font)
+            // are extra samples at their own location.
+            if let Some(location) = font.layer_location(layer) {
+                let location = to_slider(location);
+                if first_layer {
+                    for p in layer.paths.iter().flatten() {
+                        structure.push(p.nodes.iter().map(|n| n.node_type).collect());
                     }
-                    for n in &p.nodes {
+                }
+                // Nodes are the points of the toy, and everything else about
+                // the layer its attributes.
+                let (mut i, mut j) = (0, 0);
+                for (kind, pt) in layer_geometry(layer) {
+                    let mut pt = kind.transform(a, pt);
+                    let (pts, k) = if kind == PointKind::Node {
+                        (&mut pts, &mut i)
+                    } else {
                         if first_layer {
-                            structure.last_mut().unwrap().push(n.node_type);
+                            attribute_kinds.push(kind);
                         }
-                        if i == pts.len() {
-                            pts.push(InterpPt::default());
-                        }
-                        let sample = InterpSample {
-                            pt: a * n.pt,
-                            weight,
-                            width: 0.0,
-                        };
-                        pts[i].samples.push(sample);
-                        i += 1;
+                        (&mut attributes, &mut j)
+                    };
+                    if *k == pts.len() {
+                        pts.push(InterpPt::default());
+                    }
+                    // Guide angles turn the short way from the first sample.
+                    if let (PointKind::GuideAngle, Some(first)) = (kind, pts[*k].samples.first()) {
+                        pt.x = unwrap_angle(pt.x, first.pt.x);
                     }
+                    let sample = InterpSample {
+                        pt,
+                        location: location.clone(),
+                    };
+                    pts[*k].samples.push(sample);
+                    *k += 1;
                 }
             }
             first_layer = false;
         }
+        app_state.set_attributes(attributes, attribute_kinds);
         app_state.set_pts(pts);
         app_state.set_structure(structure);
+        if let Some((interp_type, kernel)) = fit::recorded_kernel(glyph) {
+            app_state.interp_type = interp_type;
+            app_state.kernel = kernel;
+        }
+        if let Some(coords) = fit::recorded_coords(glyph) {
+            app_state.coords = coords;
+        }
+        if let Some(extrapolation) = fit::recorded_extrapolation(glyph) {
+            app_state.extrapolation = extrapolation;
+        }
+        app_state.overrides = fit::recorded_overrides(glyph, &app_state.structure).into();
     }
+    app_state.set_axes(axes);
 }
 
 fn main() {
     let cmd = Cmd::from_args();
 
     let mut app_state = AppState::default();
+    let mut source = None;
     match cmd {
         Cmd::Glyph(g) => {
             println!("loading glyph {} from file {:?}", g.glyph, g.input);
             let font = Font::load(&g.input).unwrap();
             set_app_state_for_glyph(&mut app_state, &font, &g.glyph);
+            let recorded = (app_state.interp_type, app_state.kernel);
+            set_kernel(&mut app_state, recorded, None, &g.kernel);
             println!("masters: {:?}", font.font_master);
+            source = Some((g.input, g.glyph));
+        }
+        Cmd::Render(r) => {
+            let font = Font::load(&r.input).unwrap();
+            set_app_state_for_glyph(&mut app_state, &font, &r.glyph);
+            app_state.shared.location = app_state.pad_location(&r.location);
+            let recorded = (app_state.interp_type, app_state.kernel);
+            set_kernel(&mut app_state, recorded, r.interp, &r.kernel);
+            let svg = render::render_svg(&app_state, &r.opts);
+            std::fs::write(&r.output, svg).expect("error writing svg");
+            return;
+        }
+        Cmd::Animate(a) => {
+            let font = Font::load(&a.input).unwrap();
+            set_app_state_for_glyph(&mut app_state, &font, &a.glyph);
+            let recorded = (app_state.interp_type, app_state.kernel);
+            std::fs::create_dir_all(&a.output).expect("error creating output directory");
+            let to = if a.to.is_empty() { vec![1.0] } else { a.to };
+            let sweep = Sweep {
+                from: app_state.pad_location(&a.from),
+                to: app_state.pad_location(&to),
+                frames: a.frames,
+                easing: a.easing,
+            };
+            let interp_types = if a.interp.is_empty() {
+                vec![recorded.0]
+            } else {
+                a.interp
+            };
+            for interp_type in interp_types {
+                set_kernel(&mut app_state, recorded, Some(interp_type), &a.kernel);
+                let stem = format!("{}-{}", a.glyph, interp_type.name());
+                if a.smil {
+                    let svg = animate::render_smil(&mut app_state, &a.opts, &sweep, a.duration);
+                    let path = a.output.join(format!("{}.svg", stem));
+                    std::fs::write(&path, svg).expect("error writing svg");
+                } else {
+                    let frames = animate::render_frames(&mut app_state, &a.opts, &sweep);
+                    for (i, svg) in frames.iter().enumerate() {
+                        let path = a.output.join(format!("{}-{:04}.svg", stem, i));
+                        std::fs::write(&path, svg).expect("error writing svg");
+                    }
+                }
+            }
+            return;
+        }
+        Cmd::Bake(b) => {
+            let mut font = Font::load(&b.input).unwrap();
+            let glyphs = if b.glyphs.is_empty() {
+                font.glyphs.iter().map(|g| g.glyphname.clone()).collect()
+            } else {
+                b.glyphs
+            };
+            let opts = bake::BakeOptions {
+                tolerance: b.tolerance,
+                steps: b.steps,
+            };
+            for glyphname in &glyphs {
+                let mut app_state = AppState::default();
+                set_app_state_for_glyph(&mut app_state, &font, glyphname);
+                let recorded = (app_state.interp_type, app_state.kernel);
+                set_kernel(&mut app_state, recorded, b.interp, &b.kernel);
+                let to_font = glyph_transform().inverse();
+                let n = bake::bake_glyph(&mut font, glyphname, &app_state, to_font, &opts)
+                    .expect("error baking glyph");
+                if n > 0 {
+                    println!("{}: added {} brace layers", glyphname, n);
+                }
+            }
+            let path = b.input.with_extension("glyphs");
+            std::fs::write(&path, font.to_plist().to_string()).expect("error writing font");
+            return;
+        }
+        Cmd::Fit(f) => {
+            let mut font = Font::load(&f.input).unwrap();
+            let glyphs = if f.glyphs.is_empty() {
+                font.glyphs.iter().map(|g| g.glyphname.clone()).collect()
+            } else {
+                f.glyphs
+            };
+            for glyphname in &glyphs {
+                let mut app_state = AppState::default();
+                set_app_state_for_glyph(&mut app_state, &font, glyphname);
+                let recorded = (app_state.interp_type, app_state.kernel);
+                let args = KernelArgs {
+                    coords: f.coords,
+                    extrapolation: None,
+                    radius: None,
+                    order: f.order,
+                    smoothing: None,
+                    axis_scales: Vec::new(),
+                    metric: Vec::new(),
+                };
+                set_kernel(&mut app_state, recorded, f.interp, &args);
+                if app_state.pts.is_empty() {
+                    continue;
+                }
+                let fit = fit::fit_kernel(&app_state, glyph_transform().inverse());
+                print!("{}: ", glyphname);
+                print_fit(&app_state, &fit);
+                if let Some(glyph) = font.get_glyph_mut(glyphname) {
+                    fit::record_kernel(
+                        glyph,
+                        app_state.interp_type,
+                        app_state.coords,
+                        app_state.extrapolation,
+                        fit.kernel,
+                    );
+                }
+            }
+            let path = f.input.with_extension("glyphs");
+            std::fs::write(&path, font.to_plist().to_string()).expect("error writing font");
+            return;
+        }
+        Cmd::Report(r) => {
+            let font = Font::load(&r.input).unwrap();
+            let glyphs = if r.glyphs.is_empty() {
+                font.glyphs.iter().map(|g| g.glyphname.clone()).collect()
+            } else {
+                r.glyphs
+            };
+            let interp_types = if r.interp.is_empty() {
+                InterpType::ALL.to_vec()
+            } else {
+                r.interp
+            };
+            let mut rows = Vec::new();
+            for glyphname in &glyphs {
+                let mut app_state = AppState::default();
+                set_app_state_for_glyph(&mut app_state, &font, glyphname);
+                let recorded = (app_state.interp_type, app_state.kernel);
+                let kernels: Vec<(InterpType, KernelParams)> = interp_types
+                    .iter()
+                    .map(|&interp_type| {
+                        set_kernel(&mut app_state, recorded, Some(interp_type), &r.kernel);
+                        (interp_type, app_state.kernel)
+                    })
+                    .collect();
+                let to_font = glyph_transform().inverse();
+                rows.extend(report::glyph_report(
+                    &app_state, glyphname, &kernels, to_font,
+                ));
+            }
+            let text = if r.points {
+                let points = report::point_report(&rows);
+                match r.format {
+                    report::ReportFormat::Csv => report::points_to_csv(&points),
+                    report::ReportFormat::Json => report::points_to_json(&points),
+                }
+            } else {
+                let ranking = report::ranking(&rows);
+                match r.format {
+                    report::ReportFormat::Csv => report::to_csv(&rows, &ranking),
+                    report::ReportFormat::Json => report::to_json(&rows, &ranking),
+                }
+            };
+            match &r.output {
+                Some(path) => std::fs::write(path, text).expect("error writing report"),
+                None => print!("{}", text),
+            }
+            return;
         }
         Cmd::Blank => {
             println!("blank app");
         }
     }
     let title = LocalizedString::new("Interpolation toy");
-    let window = WindowDesc::new(build_ui).title(title);
+    let n_axes = app_state.shared.axes.len();
+    let window = WindowDesc::new(move || build_ui(n_axes, source.clone())).title(title);
     AppLauncher::with_window(window)
         .launch(app_state)
         .expect("launch failed");
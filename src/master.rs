@@ -21,7 +21,14 @@ impl MasterItem {
         let child = Padding::uniform(
             3.0,
             DynLabel::new(|data: &(Shared, Master), _env| {
-                format!("weight {:.2} width {:.2}", data.1.weight, data.1.width)
+                let coords: Vec<String> = data
+                    .0
+                    .axes
+                    .iter()
+                    .zip(&data.1.location)
+                    .map(|(axis, value)| format!("{} {:.2}", axis.name, value))
+                    .collect();
+                coords.join(" ")
             }),
         );
         MasterItem {
@@ -57,8 +64,7 @@ impl Widget<(Shared, Master)> for MasterItem {
     fn event(&mut self, event: &Event, ctx: &mut EventCtx, data: &mut (Shared, Master), env: &Env) {
         match event {
             Event::MouseDown(_) => {
-                data.0.weight = data.1.weight;
-                data.0.width = data.1.width;
+                data.0.location = data.1.location.clone();
                 return;
             }
             _ => (),
@@ -0,0 +1,230 @@
+//! Headless rendering of the interpolated glyph to SVG.
+
+use std::fmt::Write;
+
+use druid::kurbo::{BezPath, PathEl, Point, Rect};
+
+use glyphstool::NodeType;
+
+use structopt::StructOpt;
+
+use crate::interp_pane::reconstruct_path;
+use crate::AppState;
+
+/// Number of steps used to draw point trajectories.
+const TRAJECTORY_STEPS: usize = 32;
+
+const MARGIN: f64 = 20.0;
+
+/// What to draw in addition to the filled glyph.
+#[derive(Clone, Copy, Debug, Default, StructOpt)]
+pub struct RenderOptions {
+    /// Draw nodes.
+    #[structopt(long)]
+    pub nodes: bool,
+
+    /// Draw off-curve handles.
+    #[structopt(long)]
+    pub handles: bool,
+
+    /// Draw master outlines.
+    #[structopt(long)]
+    pub masters: bool,
+
+    /// Draw point trajectories along the first axis.
+    #[structopt(long)]
+    pub trajectories: bool,
+}
+
+/// Round a coordinate for output.
+pub fn r(x: f64) -> f64 {
+    (x * 1000.0).round() / 1000.0
+}
+
+pub fn path_data(bez_path: &BezPath) -> String {
+    let mut d = String::new();
+    for el in bez_path.elements() {
+        match el {
+            PathEl::MoveTo(p) => write!(d, "M{} {}", r(p.x), r(p.y)),
+            PathEl::LineTo(p) => write!(d, "L{} {}", r(p.x), r(p.y)),
+            PathEl::QuadTo(p1, p2) => write!(d, "Q{} {} {} {}", r(p1.x), r(p1.y), r(p2.x), r(p2.y)),
+            PathEl::CurveTo(p1, p2, p3) => write!(
+                d,
+                "C{} {} {} {} {} {}",
+                r(p1.x),
+                r(p1.y),
+                r(p2.x),
+                r(p2.y),
+                r(p3.x),
+                r(p3.y)
+            ),
+            PathEl::ClosePath => write!(d, "Z"),
+        }
+        .unwrap();
+    }
+    d
+}
+
+fn polyline(pts: &[Point]) -> BezPath {
+    let mut bez_path = BezPath::new();
+    for (i, &pt) in pts.iter().enumerate() {
+        if i == 0 {
+            bez_path.move_to(pt);
+        } else {
+            bez_path.line_to(pt);
+        }
+    }
+    bez_path
+}
+
+/// Lines from each off-curve point to the adjacent on-curve points.
+pub fn handle_lines(pts: &[Point], structure: &[Vec<NodeType>]) -> BezPath {
+    let mut bez_path = BezPath::new();
+    let mut j = 0;
+    for subpath in structure {
+        let n = subpath.len();
+        for i in 0..n {
+            let next = (i + 1) % n;
+            let is_off = |k: usize| subpath[k] == NodeType::OffCurve;
+            if is_off(i) != is_off(next) {
+                bez_path.move_to(pts[j + i]);
+                bez_path.line_to(pts[j + next]);
+            }
+        }
+        j += n;
+    }
+    bez_path
+}
+
+/// Extend a bounding box to include the given points.
+pub fn add_to_bbox(bbox: &mut Option<Rect>, pts: &[Point]) {
+    for &pt in pts {
+        let r = Rect::from_points(pt, pt);
+        *bbox = Some(bbox.map(|b| b.union(r)).unwrap_or(r));
+    }
+}
+
+/// The parts of the drawing that don't depend on the current params: master
+/// outlines and trajectories.
+pub fn render_static(data: &AppState, opts: &RenderOptions, bbox: &mut Option<Rect>) -> String {
+    let mut body = String::new();
+    if opts.masters {
+        for master in data.masters.iter() {
+            let master_pts = data.eval_pts(&master.location);
+            add_to_bbox(bbox, &master_pts);
+            let path = reconstruct_path(&master_pts, &data.structure);
+            writeln!(
+                body,
+                r##"  <path d="{}" fill="none" stroke="#c08040" stroke-width="0.5"/>"##,
+                path_data(&path)
+            )
+            .unwrap();
+        }
+    }
+    if opts.trajectories {
+        let steps: Vec<Vec<Point>> = (0..=TRAJECTORY_STEPS)
+            .map(|i| {
+                let mut location = data.shared.location.clone();
+                if let Some(first) = location.first_mut() {
+                    *first = i as f64 / TRAJECTORY_STEPS as f64;
+                }
+                data.eval_pts(&location)
+            })
+            .collect();
+        for i in 0..data.pts.len() {
+            let trajectory: Vec<Point> = steps.iter().map(|pts| pts[i]).collect();
+            add_to_bbox(bbox, &trajectory);
+            writeln!(
+                body,
+                r##"  <path d="{}" fill="none" stroke="#40a040" stroke-width="0.5"/>"##,
+                path_data(&polyline(&trajectory))
+            )
+            .unwrap();
+        }
+    }
+    body
+}
+
+/// The glyph, with nodes and handles if requested, at the current params.
+pub fn render_glyph(data: &AppState, opts: &RenderOptions, bbox: &mut Option<Rect>) -> String {
+    let pts = data.eval_pts(&data.shared.location);
+    add_to_bbox(bbox, &pts);
+    let mut body = String::new();
+    let path = reconstruct_path(&pts, &data.structure);
+    writeln!(body, r#"  <path d="{}" fill="black"/>"#, path_data(&path)).unwrap();
+    if opts.handles {
+        let lines = handle_lines(&pts, &data.structure);
+        writeln!(
+            body,
+            r##"  <path d="{}" fill="none" stroke="#808080" stroke-width="0.5"/>"##,
+            path_data(&lines)
+        )
+        .unwrap();
+    }
+    if opts.nodes {
+        let types = data.structure.iter().flatten();
+        for (pt, node_type) in pts.iter().zip(types) {
+            let (radius, fill) = match node_type {
+                NodeType::OffCurve => (1.5, "#8080ff"),
+                _ => (2.0, "#4040ff"),
+            };
+            writeln!(
+                body,
+                r#"  <circle cx="{}" cy="{}" r="{}" fill="{}"/>"#,
+                r(pt.x),
+                r(pt.y),
+                radius,
+                fill
+            )
+            .unwrap();
+        }
+    }
+    body
+}
+
+/// Wrap drawing elements in an SVG document, with a white background
+/// covering the bounding box plus a margin.
+pub fn svg_document(body: &str, bbox: Option<Rect>) -> String {
+    let bbox = bbox.unwrap_or_else(|| Rect::new(0.0, 0.0, 0.0, 0.0));
+    let bbox = Rect::new(
+        r(bbox.x0 - MARGIN),
+        r(bbox.y0 - MARGIN),
+        r(bbox.x1 + MARGIN),
+        r(bbox.y1 + MARGIN),
+    );
+    let mut svg = String::new();
+    writeln!(
+        svg,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" viewBox="{} {} {} {}" width="{}" height="{}">"#,
+        bbox.x0,
+        bbox.y0,
+        r(bbox.width()),
+        r(bbox.height()),
+        r(bbox.width()),
+        r(bbox.height())
+    )
+    .unwrap();
+    writeln!(
+        svg,
+        r#"  <rect x="{}" y="{}" width="{}" height="{}" fill="white"/>"#,
+        bbox.x0,
+        bbox.y0,
+        r(bbox.width()),
+        r(bbox.height())
+    )
+    .unwrap();
+    svg.push_str(body);
+    svg.push_str("</svg>\n");
+    svg
+}
+
+/// Render the glyph at the current params as an SVG document.
+///
+/// Points are evaluated exactly as in the interactive pane, so the result
+/// matches what the toy shows.
+pub fn render_svg(data: &AppState, opts: &RenderOptions) -> String {
+    let mut bbox = None;
+    let mut body = render_static(data, opts, &mut bbox);
+    body.push_str(&render_glyph(data, opts, &mut bbox));
+    svg_document(&body, bbox)
+}
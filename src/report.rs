@@ -0,0 +1,411 @@
+//! Comparing interpolation types by leave-one-out prediction.
+//!
+//! Each sample location (usually a master) of a glyph in turn is left out
+//! and predicted from the others under each interpolation type. The result
+//! says how far the points move and how much the area of the outline
+//! changes, and the types are ranked by their average over the font.
+//!
+//! A prediction that fails (such as a singular kernel matrix) gives NaN
+//! deviations. These rank last, and are written as null in JSON.
+
+use std::cmp::Ordering;
+use std::fmt::Write;
+use std::str::FromStr;
+
+use druid::kurbo::{Affine, BezPath, PathEl, Point};
+
+use crate::app_state::{AppState, InterpType, KernelParams};
+use crate::fit::{predict_held_out, sample_locations};
+use crate::interp_pane::reconstruct_path;
+
+#[derive(Clone, Copy, Debug)]
+pub enum ReportFormat {
+    Csv,
+    Json,
+}
+
+impl FromStr for ReportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(ReportFormat::Csv),
+            "json" => Ok(ReportFormat::Json),
+            _ => Err(format!("unknown report format: {}", s)),
+        }
+    }
+}
+
+/// How well one interpolation type predicts one left-out sample location.
+/// Distances are in font units, areas in square font units.
+pub struct Row {
+    pub glyph: String,
+    /// The design space location left out, such as "{550}".
+    pub master: String,
+    pub interp_type: InterpType,
+    /// The largest distance between a point and its prediction.
+    pub max_deviation: f64,
+    /// The mean distance between a point and its prediction.
+    pub mean_deviation: f64,
+    /// The difference in the area enclosed by the outline.
+    pub area_difference: f64,
+    /// The distance between each point and its prediction (empty in the
+    /// ranking).
+    pub deviations: Vec<f64>,
+}
+
+/// How far one point of a glyph moves from its prediction under one
+/// interpolation type, over all the sample locations left out.
+pub struct PointRow {
+    pub glyph: String,
+    pub interp_type: InterpType,
+    /// The index of the point in the glyph.
+    pub point: usize,
+    pub max_deviation: f64,
+    pub mean_deviation: f64,
+}
+
+/// The largest of some deviations, or NaN if any of them is.
+fn max_deviation(deviations: impl Iterator<Item = f64>) -> f64 {
+    deviations.fold(0.0, |max, d| {
+        if max.is_nan() || d.is_nan() {
+            f64::NAN
+        } else {
+            max.max(d)
+        }
+    })
+}
+
+fn mean(values: impl Iterator<Item = f64>) -> f64 {
+    let (sum, n) = values.fold((0.0, 0), |(sum, n), x| (sum + x, n + 1));
+    sum / n as f64
+}
+
+/// Order deviations ascending, with NaN last.
+fn compare_deviations(a: f64, b: f64) -> Ordering {
+    a.partial_cmp(&b)
+        .unwrap_or_else(|| a.is_nan().cmp(&b.is_nan()))
+}
+
+/// The signed area enclosed by a path, by Green's theorem.
+fn area(bez_path: &BezPath) -> f64 {
+    let mut area = 0.0;
+    let mut start = Point::ORIGIN;
+    let mut p0 = Point::ORIGIN;
+    for el in bez_path.elements() {
+        match *el {
+            PathEl::MoveTo(p) => {
+                start = p;
+                p0 = p;
+            }
+            PathEl::LineTo(p1) => {
+                area += line_area(p0, p1);
+                p0 = p1;
+            }
+            PathEl::QuadTo(p1, p2) => {
+                area += (p0.x * (2.0 * p1.y + p2.y) + 2.0 * p1.x * (p2.y - p0.y)
+                    - p2.x * (p0.y + 2.0 * p1.y))
+                    / 6.0;
+                p0 = p2;
+            }
+            PathEl::CurveTo(p1, p2, p3) => {
+                area += (p0.x * (6.0 * p1.y + 3.0 * p2.y + p3.y)
+                    + 3.0
+                        * (p1.x * (-2.0 * p0.y + p2.y + p3.y) - p2.x * (p0.y + p1.y - 2.0 * p3.y))
+                    - p3.x * (p0.y + 3.0 * p1.y + 6.0 * p2.y))
+                    / 20.0;
+                p0 = p3;
+            }
+            PathEl::ClosePath => {
+                area += line_area(p0, start);
+                p0 = start;
+            }
+        }
+    }
+    area
+}
+
+fn line_area(p0: Point, p1: Point) -> f64 {
+    (p0.x * p1.y - p1.x * p0.y) / 2.0
+}
+
+/// Leave out each sample location of a glyph in turn, and predict it under
+/// each of the given interpolation types and kernel parameters.
+///
+/// Locations where some points have no sample, or nothing to predict them
+/// from, are skipped. `to_font` maps the toy's coordinates to font units.
+pub fn glyph_report(
+    data: &AppState,
+    glyphname: &str,
+    kernels: &[(InterpType, KernelParams)],
+    to_font: Affine,
+) -> Vec<Row> {
+    let mut rows = Vec::new();
+    for location in sample_locations(data) {
+        for &(interp_type, kernel) in kernels {
+            let preds = predict_held_out(data, interp_type, kernel, &location);
+            let preds: Option<Vec<(Point, Point)>> = preds.into_iter().collect();
+            let (actual, predicted): (Vec<Point>, Vec<Point>) = match preds {
+                Some(preds) if !preds.is_empty() => preds
+                    .into_iter()
+                    .map(|(a, p)| (to_font * a, to_font * p))
+                    .unzip(),
+                _ => continue,
+            };
+            let deviations: Vec<f64> = actual
+                .iter()
+                .zip(&predicted)
+                .map(|(&a, &p)| (a - p).hypot())
+                .collect();
+            let area_difference = (area(&reconstruct_path(&actual, &data.structure))
+                - area(&reconstruct_path(&predicted, &data.structure)))
+            .abs();
+            rows.push(Row {
+                glyph: glyphname.to_string(),
+                master: data.location_name(&location),
+                interp_type,
+                max_deviation: max_deviation(deviations.iter().cloned()),
+                mean_deviation: mean(deviations.iter().cloned()),
+                area_difference,
+                deviations,
+            });
+        }
+    }
+    rows
+}
+
+/// Summarize the rows for each interpolation type over the whole font, best
+/// first by mean deviation. The glyph and master of each summary are "*";
+/// the deviation is the largest and the mean over all rows, and the area
+/// difference the mean.
+pub fn ranking(rows: &[Row]) -> Vec<Row> {
+    let mut ranking: Vec<Row> = InterpType::ALL
+        .iter()
+        .filter_map(|&interp_type| {
+            let rows: Vec<&Row> = rows
+                .iter()
+                .filter(|row| row.interp_type == interp_type)
+                .collect();
+            if rows.is_empty() {
+                return None;
+            }
+            Some(Row {
+                glyph: "*".to_string(),
+                master: "*".to_string(),
+                interp_type,
+                max_deviation: max_deviation(rows.iter().map(|row| row.max_deviation)),
+                mean_deviation: mean(rows.iter().map(|row| row.mean_deviation)),
+                area_difference: mean(rows.iter().map(|row| row.area_difference)),
+                deviations: Vec::new(),
+            })
+        })
+        .collect();
+    ranking.sort_by(|a, b| compare_deviations(a.mean_deviation, b.mean_deviation));
+    ranking
+}
+
+/// The largest and mean deviation of each point, for each glyph and
+/// interpolation type, over the sample locations left out.
+pub fn point_report(rows: &[Row]) -> Vec<PointRow> {
+    let mut keys: Vec<(&str, InterpType)> = Vec::new();
+    for row in rows {
+        let key = (row.glyph.as_str(), row.interp_type);
+        if !keys.contains(&key) {
+            keys.push(key);
+        }
+    }
+    let mut points = Vec::new();
+    for (glyph, interp_type) in keys {
+        let rows: Vec<&Row> = rows
+            .iter()
+            .filter(|row| row.glyph == glyph && row.interp_type == interp_type)
+            .collect();
+        let n_points = rows
+            .iter()
+            .map(|row| row.deviations.len())
+            .max()
+            .unwrap_or(0);
+        for point in 0..n_points {
+            let deviations = || {
+                rows.iter()
+                    .filter_map(|row| row.deviations.get(point).copied())
+            };
+            points.push(PointRow {
+                glyph: glyph.to_string(),
+                interp_type,
+                point,
+                max_deviation: max_deviation(deviations()),
+                mean_deviation: mean(deviations()),
+            });
+        }
+    }
+    points
+}
+
+/// One line per row, then one per type of the ranking, with a header.
+pub fn to_csv(rows: &[Row], ranking: &[Row]) -> String {
+    let mut csv = String::new();
+    writeln!(
+        csv,
+        "glyph,master,interp,max_deviation,mean_deviation,area_difference"
+    )
+    .unwrap();
+    for row in rows.iter().chain(ranking) {
+        writeln!(
+            csv,
+            "{},{},{},{:.3},{:.3},{:.1}",
+            csv_field(&row.glyph),
+            csv_field(&row.master),
+            row.interp_type.name(),
+            row.max_deviation,
+            row.mean_deviation,
+            row.area_difference
+        )
+        .unwrap();
+    }
+    csv
+}
+
+/// One line per point, with a header.
+pub fn points_to_csv(points: &[PointRow]) -> String {
+    let mut csv = String::new();
+    writeln!(csv, "glyph,interp,point,max_deviation,mean_deviation").unwrap();
+    for point in points {
+        writeln!(
+            csv,
+            "{},{},{},{:.3},{:.3}",
+            csv_field(&point.glyph),
+            point.interp_type.name(),
+            point.point,
+            point.max_deviation,
+            point.mean_deviation
+        )
+        .unwrap();
+    }
+    csv
+}
+
+fn csv_field(s: &str) -> String {
+    if s.contains(&[',', '"'][..]) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+/// An object with the rows under "results" and the ranking, with the rank
+/// of each type, under "ranking".
+pub fn to_json(rows: &[Row], ranking: &[Row]) -> String {
+    let results: Vec<String> = rows
+        .iter()
+        .map(|row| {
+            format!(
+                "    {{\"glyph\": {}, \"master\": {}, {}}}",
+                json_string(&row.glyph),
+                json_string(&row.master),
+                json_measures(row)
+            )
+        })
+        .collect();
+    let ranks: Vec<String> = ranking
+        .iter()
+        .enumerate()
+        .map(|(i, row)| format!("    {{\"rank\": {}, {}}}", i + 1, json_measures(row)))
+        .collect();
+    format!(
+        "{{\n  \"results\": [\n{}\n  ],\n  \"ranking\": [\n{}\n  ]\n}}\n",
+        results.join(",\n"),
+        ranks.join(",\n")
+    )
+}
+
+/// An object with the points under "points".
+pub fn points_to_json(points: &[PointRow]) -> String {
+    let points: Vec<String> = points
+        .iter()
+        .map(|point| {
+            format!(
+                "    {{\"glyph\": {}, \"interp\": \"{}\", \"point\": {}, \"max_deviation\": {}, \"mean_deviation\": {}}}",
+                json_string(&point.glyph),
+                point.interp_type.name(),
+                point.point,
+                json_number(point.max_deviation, 3),
+                json_number(point.mean_deviation, 3)
+            )
+        })
+        .collect();
+    format!("{{\n  \"points\": [\n{}\n  ]\n}}\n", points.join(",\n"))
+}
+
+fn json_measures(row: &Row) -> String {
+    format!(
+        "\"interp\": \"{}\", \"max_deviation\": {}, \"mean_deviation\": {}, \"area_difference\": {}",
+        row.interp_type.name(),
+        json_number(row.max_deviation, 3),
+        json_number(row.mean_deviation, 3),
+        json_number(row.area_difference, 1)
+    )
+}
+
+/// A number with the given decimal places, or null if it isn't finite, as
+/// JSON has no NaN or infinity.
+fn json_number(x: f64, precision: usize) -> String {
+    if x.is_finite() {
+        format!("{:.*}", precision, x)
+    } else {
+        "null".to_string()
+    }
+}
+
+fn json_string(s: &str) -> String {
+    let mut json = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            c if (c as u32) < 0x20 => write!(json, "\\u{:04x}", c as u32).unwrap(),
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    json
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row(interp_type: InterpType, deviations: Vec<f64>) -> Row {
+        Row {
+            glyph: "n".to_string(),
+            master: "{550}".to_string(),
+            interp_type,
+            max_deviation: max_deviation(deviations.iter().cloned()),
+            mean_deviation: mean(deviations.iter().cloned()),
+            area_difference: 0.0,
+            deviations,
+        }
+    }
+
+    /// A failed prediction ranks last and is written as null.
+    #[test]
+    fn nan_deviations() {
+        let rows = vec![
+            row(InterpType::Gaussian, vec![f64::NAN, 1.0]),
+            row(InterpType::Linear, vec![2.0, 4.0]),
+        ];
+        let ranking = ranking(&rows);
+        assert_eq!(ranking[0].interp_type, InterpType::Linear);
+        assert!(ranking[1].max_deviation.is_nan());
+        let json = to_json(&rows, &ranking);
+        assert!(json.contains("\"max_deviation\": null, \"mean_deviation\": null"));
+        assert!(!json.contains("NaN"));
+
+        let points = point_report(&rows);
+        assert_eq!(points.len(), 4);
+        assert!(points[0].max_deviation.is_nan());
+        assert_eq!(points[1].max_deviation, 1.0);
+        assert_eq!(points[3].mean_deviation, 4.0);
+        assert!(points_to_json(&points).contains("\"point\": 0, \"max_deviation\": null"));
+    }
+}